@@ -27,6 +27,16 @@ impl ops::Index<usize> for BoundingBox {
     }
 }
 
+impl Default for BoundingBox {
+    fn default() -> BoundingBox {
+        BoundingBox {
+            min: Point(MAX, MAX, MAX),
+            max: Point(MIN, MIN, MIN),
+            centroid: Point::default(),
+        }
+    }
+}
+
 impl BoundingBox {
     pub fn new(min: Point, max: Point) -> BoundingBox {
         let centroid = Point(
@@ -36,14 +46,6 @@ impl BoundingBox {
         );
         BoundingBox { min, max, centroid }
     }
-    pub fn default() -> BoundingBox {
-        BoundingBox {
-            min: Point(MAX, MAX, MAX),
-            max: Point(MIN, MIN, MIN),
-            centroid: Point::default(),
-        }
-    }
-
     pub fn intersect(&self, ray: &Ray) -> bool {
         let Ray {
             origin,
@@ -51,6 +53,7 @@ impl BoundingBox {
             direction_reciprocal,
             max_distance: _,
             refraction_index: _,
+            time: _,
         } = ray;
         let [dxi, dyi, dzi] = *direction_reciprocal;
         let (sx, rsx) = if dxi < 0.0 { (1, 0) } else { (0, 1) };
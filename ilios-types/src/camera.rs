@@ -1,5 +1,5 @@
 use crate::{
-    float::Float,
+    float::{Float, PI},
     geometry::{Point, Vector},
     ray::Ray,
     transform::Transform,
@@ -22,12 +22,120 @@ impl CoordinateSystem {
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+pub enum FieldOfView {
+    // vertical angle in radians
+    Vertical(Float),
+    // focal length and sensor height, both in millimeters
+    Lens {
+        focal_length: Float,
+        sensor_height: Float,
+    },
+}
+
+impl FieldOfView {
+    pub fn vertical_angle(&self) -> Float {
+        match self {
+            FieldOfView::Vertical(angle) => *angle,
+            FieldOfView::Lens {
+                focal_length,
+                sensor_height,
+            } => 2.0 * (sensor_height / (2.0 * focal_length)).atan(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    // width of the visible area in world units, the height follows the aspect ratio
+    Orthographic {
+        view_width: Float,
+    },
+    // full 360 by 180 degrees panorama
+    Equirectangular,
+    // equidistant fisheye, the angle in radians covers the image height
+    Fisheye {
+        field_of_view: Float,
+    },
+}
+
+// Interval during which the camera collects light, motion is defined from time 0.0 to 1.0
+#[derive(Clone, Copy, Debug)]
+pub struct Shutter {
+    pub open: Float,
+    pub close: Float,
+}
+
+impl Default for Shutter {
+    fn default() -> Shutter {
+        Shutter {
+            open: 0.0,
+            close: 1.0,
+        }
+    }
+}
+
+impl Shutter {
+    pub fn new(open: Float, close: Float) -> Shutter {
+        Shutter { open, close }
+    }
+
+    // Maps a random number in [0, 1) to an instant within the interval
+    pub fn sample(&self, random: Float) -> Float {
+        self.open + (self.close - self.open) * random
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LookAt {
+    pub position: Point,
+    pub target: Point,
+    pub up: Vector,
+    pub field_of_view: FieldOfView,
+}
+
+impl LookAt {
+    // Returns the left_top, left_bottom and right_top corners of an image plane
+    // placed one unit in front of the position, matching the given aspect ratio
+    fn image_plane(&self, aspect_ratio: Float) -> (Point, Point, Point) {
+        let forward: Vector = (&self.target - &self.position).unit().into();
+        let right: Vector = self.up.cross(&forward).unit().into();
+        let up = forward.cross(&right);
+
+        let half_height = (self.field_of_view.vertical_angle() / 2.0).tan();
+        let half_width = half_height * aspect_ratio;
+
+        let center = self.position + forward;
+        let left_top = center + (up * half_height - right * half_width);
+        let left_bottom = center + (up * -half_height - right * half_width);
+        let right_top = center + (up * half_height + right * half_width);
+        (left_top, left_bottom, right_top)
+    }
+
+    fn apply_transform(&mut self, transform: &Transform) {
+        let up_point = self.position + self.up;
+        self.position = transform.apply(&self.position);
+        self.target = transform.apply(&self.target);
+        self.up = &transform.apply(&up_point) - &self.position;
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Camera {
     pub eye: Point,
     pub left_top: Point,
     pub left_bottom: Point,
     pub right_top: Point,
+    pub look_at: Option<LookAt>,
+    pub projection: Projection,
+    pub shutter: Shutter,
+    // transform that takes the camera to where it is when the shutter closes
+    pub motion: Option<Transform>,
+    end: Option<Box<Camera>>,
+    // sideways displacement of a stereo eye for omnidirectional stereo panoramas
+    eye_offset: Float,
     delta_right: Vector,
     delta_down: Vector,
     width: Float,
@@ -42,6 +150,12 @@ impl Default for Camera {
             left_top: Point::new(-8.0, 4.5, 5.0),
             left_bottom: Point::new(-8.0, -4.5, 5.0),
             right_top: Point::new(8.0, 4.5, 5.0),
+            look_at: None,
+            projection: Projection::Perspective,
+            shutter: Shutter::default(),
+            motion: None,
+            end: None,
+            eye_offset: 0.0,
             delta_right: Vector::default(),
             delta_down: Vector::default(),
             width: 0.0,
@@ -58,6 +172,12 @@ impl Camera {
             left_top,
             left_bottom,
             right_top,
+            look_at: None,
+            projection: Projection::Perspective,
+            shutter: Shutter::default(),
+            motion: None,
+            end: None,
+            eye_offset: 0.0,
             delta_right: Vector::default(),
             delta_down: Vector::default(),
             width: 0.0,
@@ -66,7 +186,42 @@ impl Camera {
         }
     }
 
+    // Builds a camera from a position looking towards a target; the image plane
+    // is derived from the render resolution when the camera is initialized
+    pub fn look_at(
+        position: Point,
+        target: Point,
+        up: Vector,
+        field_of_view: FieldOfView,
+    ) -> Camera {
+        let look_at = LookAt {
+            position,
+            target,
+            up,
+            field_of_view,
+        };
+        let (left_top, left_bottom, right_top) = look_at.image_plane(16.0 / 9.0);
+        Camera {
+            eye: position,
+            left_top,
+            left_bottom,
+            right_top,
+            look_at: Some(look_at),
+            ..Camera::default()
+        }
+    }
+
     pub fn init(&mut self, width: Float, height: Float) {
+        if let Some(look_at) = &self.look_at
+            && width > 0.0
+            && height > 0.0
+        {
+            let (left_top, left_bottom, right_top) = look_at.image_plane(width / height);
+            self.eye = look_at.position;
+            self.left_top = left_top;
+            self.left_bottom = left_bottom;
+            self.right_top = right_top;
+        }
         let delta_right = (&self.right_top - &self.left_top) / width;
         let delta_down = (&self.left_bottom - &self.left_top) / height;
         self.delta_down = delta_down;
@@ -76,9 +231,83 @@ impl Camera {
         let edge1 = &self.right_top - &self.left_top;
         let edge2 = &self.left_bottom - &self.left_top;
         self.coordinate_system = CoordinateSystem::new(&edge1, &edge2);
+        self.end = self.motion.as_ref().map(|motion| {
+            let mut end = self.clone();
+            end.motion = None;
+            end.end = None;
+            end.apply_transform(motion);
+            Box::new(end)
+        });
+    }
+
+    // Camera for one eye of a stereo pair, displaced sideways by offset (negative for the left eye).
+    // Perspective cameras get an off-axis frustum with zero parallax at the convergence distance,
+    // equirectangular ones rotate the offset with the viewing direction (omnidirectional stereo)
+    pub fn stereo_eye(&self, offset: Float, convergence: Float) -> Camera {
+        let mut camera = self.clone();
+        camera.look_at = None;
+        let (right, _, forward) = self.basis();
+        let shift = right * offset;
+        let plane_shift = match self.projection {
+            Projection::Perspective => {
+                let distance = (&self.left_top - &self.eye).dot(&forward);
+                right * (offset * (1.0 - distance / convergence))
+            }
+            _ => shift,
+        };
+        match self.projection {
+            Projection::Equirectangular => camera.eye_offset = offset,
+            _ => {
+                camera.eye = self.eye + shift;
+                camera.left_top = self.left_top + plane_shift;
+                camera.left_bottom = self.left_bottom + plane_shift;
+                camera.right_top = self.right_top + plane_shift;
+            }
+        }
+        camera.init(self.width, self.height);
+        camera
     }
 
     pub fn get_ray(&self, x: Float, y: Float) -> Ray {
+        self.get_ray_at(x, y, self.shutter.open)
+    }
+
+    // Casts a ray at the given time, moving cameras interpolate between their start and end rays
+    pub fn get_ray_at(&self, x: Float, y: Float, time: Float) -> Ray {
+        let ray = self.project(x, y);
+        let ray = match &self.end {
+            Some(end) => {
+                let end_ray = end.project(x, y);
+                let origin = ray.origin + (&end_ray.origin - &ray.origin) * time;
+                let direction = ray.direction * (1.0 - time) + end_ray.direction * time;
+                Ray::new(
+                    origin,
+                    direction.unit(),
+                    ray.max_distance,
+                    ray.refraction_index,
+                )
+            }
+            None => ray,
+        };
+        ray.with_time(time)
+    }
+
+    fn project(&self, x: Float, y: Float) -> Ray {
+        match self.projection {
+            Projection::Perspective => self.perspective_ray(x, y),
+            Projection::Orthographic { view_width } => self.orthographic_ray(x, y, view_width),
+            Projection::Equirectangular => self.equirectangular_ray(x, y),
+            Projection::Fisheye { field_of_view } => self.fisheye_ray(x, y, field_of_view),
+        }
+    }
+
+    // Unit vectors pointing right, up and forward from the eye
+    fn basis(&self) -> (Vector, Vector, Vector) {
+        let CoordinateSystem { u, v, w } = &self.coordinate_system;
+        (*u, -*v, -*w)
+    }
+
+    fn perspective_ray(&self, x: Float, y: Float) -> Ray {
         let Camera {
             eye,
             left_top,
@@ -93,7 +322,52 @@ impl Camera {
         Ray::new(origin, direction.unit(), Float::INFINITY, 1.0)
     }
 
+    fn orthographic_ray(&self, x: Float, y: Float, view_width: Float) -> Ray {
+        let (right, up, forward) = self.basis();
+        let view_height = view_width * self.height / self.width;
+        let horizontal = (x / self.width - 0.5) * view_width;
+        let vertical = (0.5 - y / self.height) * view_height;
+        let origin = self.eye + (right * horizontal + up * vertical);
+
+        Ray::new(origin, forward.unit(), Float::INFINITY, 1.0)
+    }
+
+    fn equirectangular_ray(&self, x: Float, y: Float) -> Ray {
+        let (right, up, forward) = self.basis();
+        let phi = (x / self.width - 0.5) * 2.0 * PI;
+        let theta = (0.5 - y / self.height) * PI;
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let direction =
+            forward * (cos_theta * cos_phi) + right * (cos_theta * sin_phi) + up * sin_theta;
+        let origin = self.eye + (right * cos_phi - forward * sin_phi) * self.eye_offset;
+
+        Ray::new(origin, direction.unit(), Float::INFINITY, 1.0)
+    }
+
+    fn fisheye_ray(&self, x: Float, y: Float, field_of_view: Float) -> Ray {
+        let (right, up, forward) = self.basis();
+        let half_height = self.height / 2.0;
+        let nx = (x - self.width / 2.0) / half_height;
+        let ny = (half_height - y) / half_height;
+        let radius = (nx * nx + ny * ny).sqrt();
+        let theta = radius * field_of_view / 2.0;
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let (cos_phi, sin_phi) = if radius > 0.0 {
+            (nx / radius, ny / radius)
+        } else {
+            (0.0, 0.0)
+        };
+        let direction =
+            forward * cos_theta + right * (sin_theta * cos_phi) + up * (sin_theta * sin_phi);
+
+        Ray::new(self.eye, direction.unit(), Float::INFINITY, 1.0)
+    }
+
     pub fn apply_transform(&mut self, transform: &Transform) {
+        if let Some(look_at) = &mut self.look_at {
+            look_at.apply_transform(transform);
+        }
         self.eye = transform.apply(&self.eye);
         self.left_top = transform.apply(&self.left_top);
         self.left_bottom = transform.apply(&self.left_bottom);
@@ -101,3 +375,84 @@ impl Camera {
         self.init(self.width, self.height);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        camera::{Camera, FieldOfView, Projection},
+        float::PI,
+        geometry::{Normal, Point, Vector},
+    };
+
+    #[test]
+    fn look_at_matches_aspect_ratio() {
+        let mut camera = Camera::look_at(
+            Point(0.0, 0.0, -10.0),
+            Point(0.0, 0.0, 0.0),
+            Vector(0.0, 1.0, 0.0),
+            FieldOfView::Vertical(PI / 2.0),
+        );
+        camera.init(200.0, 100.0);
+
+        let width = (&camera.right_top - &camera.left_top).norm();
+        let height = (&camera.left_bottom - &camera.left_top).norm();
+        assert!((width - 4.0).abs() < 1e-5);
+        assert!((height - 2.0).abs() < 1e-5);
+
+        let ray = camera.get_ray(100.0, 50.0);
+        assert!((ray.direction.2 - 1.0).abs() < 1e-5);
+        assert!(camera.left_top.0 < camera.right_top.0);
+        assert!(camera.left_top.1 > camera.left_bottom.1);
+    }
+
+    #[test]
+    fn projections_look_forward_at_center() {
+        let projections = [
+            Projection::Perspective,
+            Projection::Orthographic { view_width: 10.0 },
+            Projection::Equirectangular,
+            Projection::Fisheye {
+                field_of_view: PI / 2.0,
+            },
+        ];
+        for projection in projections {
+            let mut camera = Camera::look_at(
+                Point(0.0, 0.0, -10.0),
+                Point(0.0, 0.0, 0.0),
+                Vector(0.0, 1.0, 0.0),
+                FieldOfView::Vertical(PI / 2.0),
+            );
+            camera.projection = projection;
+            camera.init(200.0, 100.0);
+
+            let Normal(x, y, z) = camera.get_ray(100.0, 50.0).direction;
+            assert!(x.abs() < 1e-5 && y.abs() < 1e-5 && (z - 1.0).abs() < 1e-5);
+            let right = camera.get_ray(150.0, 50.0);
+            assert!(right.direction.0 > 0.0 || right.origin.0 > 0.0);
+            let top = camera.get_ray(100.0, 25.0);
+            assert!(top.direction.1 > 0.0 || top.origin.1 > 0.0);
+        }
+    }
+
+    #[test]
+    fn stereo_eyes_converge() {
+        let mut camera = Camera::look_at(
+            Point(0.0, 0.0, -10.0),
+            Point(0.0, 0.0, 0.0),
+            Vector(0.0, 1.0, 0.0),
+            FieldOfView::Vertical(PI / 2.0),
+        );
+        camera.init(200.0, 100.0);
+        let left = camera.stereo_eye(-0.5, 10.0);
+        let right = camera.stereo_eye(0.5, 10.0);
+
+        let at_convergence = |eye: &Camera| {
+            let ray = eye.get_ray(100.0, 50.0);
+            let t = (0.0 - ray.origin.2) / ray.direction.2;
+            ray.origin.0 + ray.direction.0 * t
+        };
+        assert!(left.eye.0 < camera.eye.0 && right.eye.0 > camera.eye.0);
+        assert!(at_convergence(&left).abs() < 1e-4);
+        assert!(at_convergence(&right).abs() < 1e-4);
+    }
+}
@@ -79,4 +79,10 @@ impl Color {
     pub fn white() -> Color {
         WHITE
     }
+
+    // Perceived brightness, using the Rec. 709 weights
+    pub fn luminance(&self) -> Float {
+        let Color(red, green, blue) = self;
+        0.2126 * red + 0.7152 * green + 0.0722 * blue
+    }
 }
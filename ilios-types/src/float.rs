@@ -1,5 +1,5 @@
 pub type Float = f32;
-pub const MIN: f32 = std::f32::MIN;
-pub const MAX: f32 = std::f32::MAX;
+pub const MIN: f32 = f32::MIN;
+pub const MAX: f32 = f32::MAX;
 pub const PI: f32 = std::f32::consts::PI;
-pub const EPSILON: f32 = std::f32::EPSILON * 10.0;
+pub const EPSILON: f32 = f32::EPSILON * 10.0;
@@ -8,5 +8,5 @@ mod vector;
 pub use axis::Axis;
 pub use normal::Normal;
 pub use point::Point;
-pub use triangle::Triangle;
+pub use triangle::{Triangle, TriangleMotion};
 pub use vector::Vector;
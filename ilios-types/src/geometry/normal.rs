@@ -90,14 +90,17 @@ impl ops::Neg for &Normal {
     }
 }
 
+impl Default for Normal {
+    fn default() -> Normal {
+        Normal(1.0, 0.0, 0.0)
+    }
+}
+
 impl Normal {
     pub fn new(x: Float, y: Float, z: Float) -> Normal {
         let norm = (x * x + y * y + z * z).sqrt();
         Normal(x / norm, y / norm, z / norm)
     }
-    pub fn default() -> Normal {
-        Normal(1.0, 0.0, 0.0)
-    }
     pub fn dot(&self, rhs: &Vector) -> Float {
         self.0 * rhs.0 + self.1 * rhs.1 + self.2 * rhs.2
     }
@@ -10,6 +10,16 @@ use crate::{
 
 use super::Normal;
 
+// Position of a moving triangle when the shutter closes (time 1.0),
+// vertices are linearly interpolated from the triangle's own position at time 0.0
+#[derive(Debug, Clone, Copy)]
+pub struct TriangleMotion {
+    pub origin: Point,
+    pub pt2: Point,
+    pub pt3: Point,
+    pub normal: Normal,
+}
+
 #[derive(Debug, Clone)]
 pub struct Triangle {
     pub origin: Point,
@@ -19,6 +29,7 @@ pub struct Triangle {
     pub material: Arc<Material>,
     pub pt2: Point,
     pub pt3: Point,
+    pub motion: Option<TriangleMotion>,
 }
 
 impl Triangle {
@@ -35,28 +46,68 @@ impl Triangle {
             material,
             pt2,
             pt3,
+            motion: None,
         }
     }
 
+    // Builds a triangle that moves from the start to the end positions while the shutter is open
+    pub fn moving(start: &Triangle, end: &Triangle) -> Triangle {
+        let mut triangle = start.clone();
+        triangle.motion = Some(TriangleMotion {
+            origin: end.origin,
+            pt2: end.pt2,
+            pt3: end.pt3,
+            normal: end.normal,
+        });
+        triangle
+    }
+
     pub fn normal(&self) -> Normal {
         self.normal
     }
 
+    pub fn normal_at(&self, time: Float) -> Normal {
+        match &self.motion {
+            Some(motion) => (self.normal * (1.0 - time) + motion.normal * time).unit(),
+            None => self.normal,
+        }
+    }
+
+    // Returns the origin and edges of the triangle at the given time
+    pub fn at(&self, time: Float) -> (Point, Vector, Vector) {
+        match &self.motion {
+            Some(motion) => {
+                let lerp = |a: &Point, b: &Point| a + (b - a) * time;
+                let origin = lerp(&self.origin, &motion.origin);
+                let pt2 = lerp(&self.pt2, &motion.pt2);
+                let pt3 = lerp(&self.pt3, &motion.pt3);
+                (origin, &pt2 - &origin, &pt3 - &origin)
+            }
+            None => (self.origin, self.edge1, self.edge2),
+        }
+    }
+
     pub fn intersect(&self, ray: &Ray) -> Option<Float> {
-        let Triangle {
-            origin,
-            edge1,
-            edge2,
-            ..
-        } = self;
-        triangle_intersect((origin, edge1, edge2), ray)
+        let (origin, edge1, edge2) = self.at(ray.time);
+        triangle_intersect((&origin, &edge1, &edge2), ray)
     }
 
     pub fn bounding_box(&self) -> BoundingBox {
         let Triangle {
-            origin, pt2, pt3, ..
+            origin,
+            pt2,
+            pt3,
+            motion,
+            ..
         } = self;
 
+        // Linear motion stays within the box enclosing both the start and end positions
+        if let Some(motion) = motion {
+            let start = Triangle::new(*origin, *pt2, *pt3, self.material.clone());
+            let end = Triangle::new(motion.origin, motion.pt2, motion.pt3, self.material.clone());
+            return start.bounding_box().combine(&end.bounding_box());
+        }
+
         BoundingBox::new(
             Point(
                 origin.0.min(pt2.0).min(pt3.0),
@@ -80,6 +131,7 @@ fn triangle_intersect(triangle: (&Point, &Vector, &Vector), ray: &Ray) -> Option
         direction_reciprocal: _,
         max_distance: _,
         refraction_index: _,
+        time: _,
     } = ray;
     let pvec = direction.cross(edge2);
 
@@ -94,7 +146,7 @@ fn triangle_intersect(triangle: (&Point, &Vector, &Vector), ray: &Ray) -> Option
     let tvec = origin - v0;
 
     let u = tvec.dot(&pvec) * inv_det;
-    if u < 0.0 || u > 1.0 + EPSILON {
+    if !(0.0..=1.0 + EPSILON).contains(&u) {
         return None;
     }
 
@@ -10,6 +10,7 @@ pub struct Ray {
     pub direction_reciprocal: [Float; 3],
     pub max_distance: Float,
     pub refraction_index: Float,
+    pub time: Float,
 }
 
 impl Ray {
@@ -25,9 +26,16 @@ impl Ray {
             direction_reciprocal: [1.0 / direction.0, 1.0 / direction.1, 1.0 / direction.2],
             max_distance,
             refraction_index,
+            time: 0.0,
         }
     }
 
+    // Sets the instant within the shutter interval this ray was cast at
+    pub fn with_time(mut self, time: Float) -> Ray {
+        self.time = time;
+        self
+    }
+
     pub fn point(&self, rhs: Float) -> Point {
         let Ray {
             origin,
@@ -35,6 +43,7 @@ impl Ray {
             direction_reciprocal: _,
             max_distance: _,
             refraction_index: _,
+            time: _,
         } = self;
         origin + (direction * rhs)
     }
@@ -9,22 +9,30 @@ use crate::{
 
 #[derive(Clone, Debug)]
 pub enum Solid {
-    Triangle(Point, Point, Point, Arc<Material>),
+    // A triangle given by its points, placed with a transform applied to them
+    Triangle(Point, Point, Point, Transform, Arc<Material>),
     ColoredCube(Transform),
     Cube(Transform, Arc<Material>),
     CornellBox(Transform),
     Sphere(usize, Transform, Arc<Material>),
     Torus(Float, Float, usize, usize, Transform, Arc<Material>),
     Mesh(Transform, Vec<Triangle>),
+    // A mesh shared with other instances, placed with its own transform
+    Instance(Transform, Arc<Vec<Triangle>>),
     Plane(Transform, Arc<Material>),
+    // A solid that moves to the end transform by the time the shutter closes
+    Moving(Box<Solid>, Transform),
 }
 
 impl Solid {
     pub fn primitives(&self) -> Vec<Triangle> {
         match self {
-            Solid::Triangle(pt1, pt2, pt3, material) => {
-                vec![Triangle::new(*pt1, *pt2, *pt3, material.clone())]
-            }
+            Solid::Triangle(pt1, pt2, pt3, transform, material) => vec![Triangle::new(
+                transform.apply(pt1),
+                transform.apply(pt2),
+                transform.apply(pt3),
+                material.clone(),
+            )],
             Solid::ColoredCube(transform) => colored_cube(transform),
             Solid::Cube(transform, material) => cube(transform, material.clone()),
             Solid::CornellBox(transform) => cornell_box(transform),
@@ -35,7 +43,54 @@ impl Solid {
                 torus(*rd1, *rd2, *sc1, *sc2, transform, material.clone())
             }
             Solid::Mesh(transform, triangles) => mesh(transform, triangles),
+            Solid::Instance(transform, triangles) => mesh(transform, triangles),
             Solid::Plane(transform, material) => plane(transform, material.clone()),
+            Solid::Moving(solid, end) => {
+                let start = solid.primitives();
+                let end = solid.with_transform(end).primitives();
+                start
+                    .iter()
+                    .zip(end.iter())
+                    .map(|(start, end)| Triangle::moving(start, end))
+                    .collect()
+            }
+        }
+    }
+
+    // Transform placing the solid, where it starts for moving ones
+    pub fn transform(&self) -> Option<&Transform> {
+        match self {
+            Solid::Triangle(_, _, _, transform, _)
+            | Solid::ColoredCube(transform)
+            | Solid::Cube(transform, _)
+            | Solid::CornellBox(transform)
+            | Solid::Sphere(_, transform, _)
+            | Solid::Torus(_, _, _, _, transform, _)
+            | Solid::Mesh(transform, _)
+            | Solid::Instance(transform, _)
+            | Solid::Plane(transform, _) => Some(transform),
+            Solid::Moving(solid, _) => solid.transform(),
+        }
+    }
+
+    // Returns the same solid placed with a different transform
+    pub fn with_transform(&self, transform: &Transform) -> Solid {
+        let transform = transform.clone();
+        match self {
+            Solid::Triangle(pt1, pt2, pt3, _, material) => {
+                Solid::Triangle(*pt1, *pt2, *pt3, transform, material.clone())
+            }
+            Solid::ColoredCube(_) => Solid::ColoredCube(transform),
+            Solid::Cube(_, material) => Solid::Cube(transform, material.clone()),
+            Solid::CornellBox(_) => Solid::CornellBox(transform),
+            Solid::Sphere(sc1, _, material) => Solid::Sphere(*sc1, transform, material.clone()),
+            Solid::Torus(rd1, rd2, sc1, sc2, _, material) => {
+                Solid::Torus(*rd1, *rd2, *sc1, *sc2, transform, material.clone())
+            }
+            Solid::Mesh(_, triangles) => Solid::Mesh(transform, triangles.clone()),
+            Solid::Instance(_, triangles) => Solid::Instance(transform, triangles.clone()),
+            Solid::Plane(_, material) => Solid::Plane(transform, material.clone()),
+            Solid::Moving(solid, _) => solid.with_transform(&transform),
         }
     }
 }
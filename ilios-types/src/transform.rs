@@ -75,15 +75,19 @@ fn multiply(mts: &[Matrix]) -> Matrix {
     )
 }
 
-impl Transform {
-    pub fn default() -> Transform {
+impl Default for Transform {
+    fn default() -> Transform {
         Transform(IDENTITY)
     }
+}
+
+impl Transform {
     pub fn combine(trs: &[Transform]) -> Transform {
         let mts: Vec<Matrix> = trs.iter().map(|t| t.0).collect();
         Transform(multiply(&mts))
     }
 
+    // Rotates around the x, y and z axes, angles are counterclockwise looking down each axis
     pub fn rotate(x: Float, y: Float, z: Float) -> Transform {
         let (sx, cx) = x.sin_cos();
         let (sy, cy) = y.sin_cos();
@@ -105,6 +109,7 @@ impl Transform {
         Transform(matrix)
     }
 
+    // Rotates around a unit vector, counterclockwise like rotate
     pub fn rotate_around_vector(o: Float, v: Vector) -> Transform {
         // https://en.wikipedia.org/wiki/Rotation_matrix#Rotation_matrix_from_axis_and_angle
         // https://mathworld.wolfram.com/RodriguesRotationFormula.html
@@ -146,6 +151,90 @@ impl Transform {
         Transform(matrix)
     }
 
+    // Moves a direction, translations leave it unchanged
+    pub fn apply_vector(&self, vector: &Vector) -> Vector {
+        let Vector(x, y, z) = vector;
+        let Transform(m) = self;
+        Vector(
+            m[0] * x + m[1] * y + m[2] * z,
+            m[4] * x + m[5] * y + m[6] * z,
+            m[8] * x + m[9] * y + m[10] * z,
+        )
+    }
+
+    // Inverse of an affine transform, none when it flattens space
+    pub fn inverse(&self) -> Option<Transform> {
+        let Transform(m) = self;
+        let cofactor = |r1: usize, c1: usize, r2: usize, c2: usize| {
+            m[r1 * 4 + c1] * m[r2 * 4 + c2] - m[r1 * 4 + c2] * m[r2 * 4 + c1]
+        };
+        // adjugate of the 3x3 part, transposed
+        let adjugate = [
+            cofactor(1, 1, 2, 2),
+            -cofactor(0, 1, 2, 2),
+            cofactor(0, 1, 1, 2),
+            -cofactor(1, 0, 2, 2),
+            cofactor(0, 0, 2, 2),
+            -cofactor(0, 0, 1, 2),
+            cofactor(1, 0, 2, 1),
+            -cofactor(0, 0, 2, 1),
+            cofactor(0, 0, 1, 1),
+        ];
+        let determinant = m[0] * adjugate[0] + m[1] * adjugate[3] + m[2] * adjugate[6];
+        if determinant == 0.0 {
+            return None;
+        }
+        let a = adjugate.map(|value| value / determinant);
+        let translation =
+            |r: usize| -(a[r * 3] * m[3] + a[r * 3 + 1] * m[7] + a[r * 3 + 2] * m[11]);
+        Some(Transform([
+            a[0],
+            a[1],
+            a[2],
+            translation(0),
+            a[3],
+            a[4],
+            a[5],
+            translation(1),
+            a[6],
+            a[7],
+            a[8],
+            translation(2),
+            0.0,
+            0.0,
+            0.0,
+            1.0,
+        ]))
+    }
+
+    // Transform for the normals of the geometry placed with this one, the transposed
+    // inverse. Mirroring transforms flip it so normals keep facing the same side of the
+    // vertices, like the normals of transformed triangles do
+    pub fn normal_transform(&self) -> Option<Transform> {
+        let Transform(m) = self.inverse()?;
+        let determinant = m[0] * (m[5] * m[10] - m[6] * m[9]) - m[1] * (m[4] * m[10] - m[6] * m[8])
+            + m[2] * (m[4] * m[9] - m[5] * m[8]);
+        let sign = determinant.signum();
+        Some(Transform([
+            m[0] * sign,
+            m[4] * sign,
+            m[8] * sign,
+            0.0,
+            m[1] * sign,
+            m[5] * sign,
+            m[9] * sign,
+            0.0,
+            m[2] * sign,
+            m[6] * sign,
+            m[10] * sign,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            1.0,
+        ]))
+    }
+
     pub fn apply(&self, pt: &Point) -> Point {
         let Point(x, y, z) = pt;
         let Transform(m) = self;
@@ -170,7 +259,31 @@ mod tests {
         let dg = PI / 100.0;
         let pt = Point(1.0, 1.0, 0.0);
         let expected = Transform::rotate(dg, 0.0, 0.0).apply(&pt);
-        let result = Transform::rotate_around_vector(-dg, Vector(1.0, 0.0, 0.0)).apply(&pt);
+        let result = Transform::rotate_around_vector(dg, Vector(1.0, 0.0, 0.0)).apply(&pt);
         assert_eq!(expected, result);
+
+        // a quarter turn around x takes y to z
+        for transform in [
+            Transform::rotate(PI / 2.0, 0.0, 0.0),
+            Transform::rotate_around_vector(PI / 2.0, Vector(1.0, 0.0, 0.0)),
+        ] {
+            let Point(x, y, z) = transform.apply(&Point(0.0, 1.0, 0.0));
+            assert!(x.abs() < 1e-6 && y.abs() < 1e-6 && (z - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        let transform = Transform::combine(&[
+            Transform::scale(2.0, -3.0, 0.5),
+            Transform::rotate(0.3, 1.2, -0.7),
+            Transform::translate(4.0, -1.0, 2.5),
+        ]);
+        let pt = Point(1.5, -2.0, 3.0);
+        let back = transform.inverse().unwrap().apply(&transform.apply(&pt));
+        assert!((back.0 - pt.0).abs() < 1e-4);
+        assert!((back.1 - pt.1).abs() < 1e-4);
+        assert!((back.2 - pt.2).abs() < 1e-4);
+        assert!(Transform::scale(1.0, 0.0, 1.0).inverse().is_none());
     }
 }
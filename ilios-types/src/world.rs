@@ -1,6 +1,12 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
-use crate::{geometry::Triangle, light_source::LightSource, material::Material, solids::Solid};
+use crate::{
+    geometry::Triangle, light_source::LightSource, material::Material, solids::Solid,
+    transform::Transform,
+};
+
+// A shared mesh placed with its own transform
+pub type MeshInstance = (Transform, Arc<Vec<Triangle>>);
 
 #[derive(Clone, Debug, Default)]
 pub struct World {
@@ -1,12 +1,12 @@
 use std::sync::Arc;
 
-use ilios::{Accelerator, Algorithm, RenderMethod, Renderer};
+use ilios::{Accelerator, Algorithm, BvhBuildMethod, Progressive, RenderMethod, Renderer};
 use ilios_types::{
-    camera::Camera,
+    camera::{Camera, Projection},
     color::Color,
+    float::PI,
     geometry::{Point, Vector},
     material::Material,
-    section::Section,
     solids::Solid,
     transform::Transform,
     world::World,
@@ -16,11 +16,12 @@ use kosmos::SceneDescriptor;
 static mut WIDTH: i32 = 0;
 static mut HEIGHT: i32 = 0;
 static mut LEN: usize = 0;
-static mut RENDERER: Option<Renderer> = None;
-static mut FRAMES_ACC: Option<Vec<Color>> = None;
-static mut TOTAL_FRAMES: f32 = 0.0;
+static mut SESSION: Option<Progressive> = None;
 
-#[no_mangle]
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+/// # Safety
+///
+/// This function is unsafe because it hands out memory that must be released with `free`.
 pub unsafe fn alloc(size_in_bytes: usize) -> *mut u8 {
     let mut memory = Vec::with_capacity(size_in_bytes);
     let ptr = memory.as_mut_ptr();
@@ -28,7 +29,10 @@ pub unsafe fn alloc(size_in_bytes: usize) -> *mut u8 {
     ptr
 }
 
-#[no_mangle]
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+/// # Safety
+///
+/// This function is unsafe because it takes ownership of a raw pointer.
 pub unsafe fn free(n: usize, ptr: *mut f32) {
     let _bytes: Vec<f32> = Vec::from_raw_parts(ptr, n, n);
 }
@@ -48,22 +52,13 @@ pub unsafe fn render(ptr: *mut u8) {
 unsafe fn render_frame(ptr: *mut u8) {
     let mut bytes: Vec<u8> = Vec::from_raw_parts(ptr, LEN * 3, LEN * 3);
 
-    let section = Section::new(0, 0, WIDTH as u32, HEIGHT as u32);
-
-    if let Some(renderer) = &mut RENDERER {
-        let pixels = renderer.render(&section);
-        TOTAL_FRAMES = TOTAL_FRAMES + 1.0;
-        if let Some(frames_acc) = &mut FRAMES_ACC {
-            let mut offset = 0;
-            for (idx, color) in pixels.iter().enumerate() {
-                frames_acc[idx] = frames_acc[idx] + *color;
-                let output_color = frames_acc[idx] / TOTAL_FRAMES;
-                let (r, g, b) = output_color.as_gamma_corrected_rgb_u8();
-                bytes[offset] = r;
-                bytes[offset + 1] = g;
-                bytes[offset + 2] = b;
-                offset += 3;
-            }
+    if let Some(session) = &mut SESSION {
+        session.render_pass();
+        for (idx, color) in session.image().iter().enumerate() {
+            let (r, g, b) = color.as_gamma_corrected_rgb_u8();
+            bytes[idx * 3] = r;
+            bytes[idx * 3 + 1] = g;
+            bytes[idx * 3 + 2] = b;
         }
     }
 
@@ -83,9 +78,7 @@ pub unsafe fn init(width: i32, height: i32) -> *mut u8 {
     std::mem::forget(buffer);
     let w = 4.0;
     let h = 3.0;
-    FRAMES_ACC = Some(vec![Color::default(); LEN]);
-    TOTAL_FRAMES = 0.0;
-    RENDERER = Some(
+    SESSION = Some(Progressive::new(
         Renderer::builder()
             .width(width as u32)
             .height(height as u32)
@@ -98,6 +91,8 @@ pub unsafe fn init(width: i32, height: i32) -> *mut u8 {
             .algorithm(Algorithm::PathTracing)
             .render_method(RenderMethod::Tiles)
             .accelerator(Accelerator::BoundingVolumeHierarchy)
+            // scenes are rebuilt while editing
+            .bvh_build_method(BvhBuildMethod::Lbvh)
             .world(
                 World::builder()
                     .add_solid(Solid::Torus(
@@ -105,7 +100,7 @@ pub unsafe fn init(width: i32, height: i32) -> *mut u8 {
                         4.0,
                         12,
                         24,
-                        Transform::combine(&[Transform::rotate(3.1415926 / 2.0, 0.0, 0.0)]),
+                        Transform::combine(&[Transform::rotate(PI / 2.0, 0.0, 0.0)]),
                         Material::green(),
                     ))
                     .add_solid(Solid::Plane(
@@ -118,7 +113,7 @@ pub unsafe fn init(width: i32, height: i32) -> *mut u8 {
                     .add_solid(Solid::Plane(
                         Transform::combine(&[
                             Transform::scale(1000.0, 0.0, 1000.0),
-                            Transform::rotate(3.1415926, 0.0, 0.0),
+                            Transform::rotate(PI, 0.0, 0.0),
                             Transform::translate(0.0, -5.0, 0.0),
                         ]),
                         Arc::new(Material::Diffuse(Color(3.0, 3.0, 3.0))),
@@ -126,7 +121,7 @@ pub unsafe fn init(width: i32, height: i32) -> *mut u8 {
                     .build(),
             )
             .build(),
-    );
+    ));
     ptr
 }
 
@@ -161,40 +156,107 @@ pub unsafe fn init(width: i32, height: i32) -> *mut u8 {
 
 #[allow(static_mut_refs)]
 #[no_mangle]
+/// # Safety
+///
+/// This function is unsafe because it mutates the global renderer state.
 pub unsafe fn camera_rotate(x: f32, y: f32, z: f32) {
-    if let Some(renderer) = &mut RENDERER {
+    if let Some(session) = &mut SESSION {
         let transform = Transform::rotate(x, y, z);
-        renderer.camera.apply_transform(&transform);
-        TOTAL_FRAMES = 0.0;
-        FRAMES_ACC = Some(vec![Color::default(); LEN]);
+        session.camera_mut().apply_transform(&transform);
     }
 }
 
 #[allow(static_mut_refs)]
 #[no_mangle]
+/// # Safety
+///
+/// This function is unsafe because it mutates the global renderer state.
 pub unsafe fn camera_rotate_orbital(x: f32, y: f32, z: f32) {
-    if let Some(renderer) = &mut RENDERER {
+    if let Some(session) = &mut SESSION {
+        let camera = session.camera_mut();
         let transform = Transform::combine(&[
-            Transform::rotate_around_vector(x, renderer.camera.coordinate_system.u),
-            Transform::rotate_around_vector(y, renderer.camera.coordinate_system.v),
-            Transform::rotate_around_vector(z, renderer.camera.coordinate_system.w),
+            Transform::rotate_around_vector(x, camera.coordinate_system.u),
+            Transform::rotate_around_vector(y, camera.coordinate_system.v),
+            Transform::rotate_around_vector(z, camera.coordinate_system.w),
         ]);
-        renderer.camera.apply_transform(&transform);
-        TOTAL_FRAMES = 0.0;
-        FRAMES_ACC = Some(vec![Color::default(); LEN]);
+        camera.apply_transform(&transform);
     }
 }
 
 #[allow(static_mut_refs)]
 #[no_mangle]
+/// # Safety
+///
+/// This function is unsafe because it mutates the global renderer state.
 pub unsafe fn camera_zoom(delta: f32) {
-    if let Some(renderer) = &mut RENDERER {
-        let algo: Vector = renderer.camera.eye.into();
-        let algo = algo.unit() * delta;
-        let transform = Transform::translate(algo.0, algo.1, algo.2);
-        renderer.camera.apply_transform(&transform);
-        TOTAL_FRAMES = 0.0;
-        FRAMES_ACC = Some(vec![Color::default(); LEN]);
+    if let Some(session) = &mut SESSION {
+        let camera = session.camera_mut();
+        match camera.projection {
+            // moving an orthographic camera along its view does not change the image,
+            // so zooming resizes the visible area instead
+            Projection::Orthographic { view_width } => {
+                camera.projection = Projection::Orthographic {
+                    view_width: (view_width + delta).max(0.1),
+                };
+            }
+            Projection::Fisheye { field_of_view } => {
+                camera.projection = Projection::Fisheye {
+                    field_of_view: (field_of_view + delta / 10.0).clamp(0.1, 2.0 * PI),
+                };
+            }
+            Projection::Perspective | Projection::Equirectangular => {
+                let algo: Vector = camera.eye.into();
+                let algo = algo.unit() * delta;
+                let transform = Transform::translate(algo.0, algo.1, algo.2);
+                camera.apply_transform(&transform);
+            }
+        }
+    }
+}
+
+#[allow(static_mut_refs)]
+#[no_mangle]
+/// # Safety
+///
+/// This function is unsafe because it mutates the global renderer state.
+///
+/// Kinds: 0 perspective, 1 orthographic (value is the view width),
+/// 2 equirectangular, 3 fisheye (value is the field of view in radians).
+pub unsafe fn camera_projection(kind: i32, value: f32) {
+    if let Some(session) = &mut SESSION {
+        session.camera_mut().projection = match kind {
+            1 => Projection::Orthographic { view_width: value },
+            2 => Projection::Equirectangular,
+            3 => Projection::Fisheye {
+                field_of_view: value,
+            },
+            _ => Projection::Perspective,
+        };
+    }
+}
+
+#[allow(static_mut_refs)]
+#[no_mangle]
+/// # Safety
+///
+/// This function is unsafe because it mutates the global renderer state.
+///
+/// Moves the solid at the index of the scene objects, the accelerator is refitted around it
+/// instead of built again while that keeps it fast to trace.
+pub unsafe fn solid_translate(index: i32, x: f32, y: f32, z: f32) {
+    if let Some(session) = &mut SESSION {
+        let index = index as usize;
+        let Some(transform) = session
+            .renderer()
+            .world
+            .objects
+            .get(index)
+            .and_then(|solid| solid.transform())
+        else {
+            return;
+        };
+        let transform = Transform::combine(&[transform.clone(), Transform::translate(x, y, z)]);
+        session.set_transform(index, &transform);
     }
 }
 
@@ -208,14 +270,14 @@ pub unsafe fn deinit(ptr: *mut u8) {
 
 #[allow(static_mut_refs)]
 #[no_mangle]
+/// # Safety
+///
+/// This function is unsafe because it takes ownership of a raw pointer.
 pub unsafe fn load_scene_zip(zip_lenght: i32, zip_ptr: *mut u8) {
     let bytes: Vec<u8> = Vec::from_raw_parts(zip_ptr, zip_lenght as usize, zip_lenght as usize);
     let SceneDescriptor { camera, world } = kosmos::load_zip_data(bytes).unwrap();
 
-    let renderer = RENDERER.take();
-    let mut builder = renderer.unwrap().into_builder();
-
-    builder.camera(camera);
-    builder.world(world);
-    RENDERER = Some(builder.build());
+    if let Some(session) = &mut SESSION {
+        session.set_scene(camera, world);
+    }
 }
@@ -4,18 +4,32 @@ use ilios_types::bounding_box::BoundingBox;
 use ilios_types::float::Float;
 use ilios_types::geometry::{Axis, Point, Triangle, Vector};
 use ilios_types::ray::Ray;
+use rayon::prelude::*;
 
+use crate::closest_primitive::ClosestPrimitive;
 use crate::geometry::PackedTriangles;
-use crate::simd;
 use crate::trace::Trace;
 
+// The tree is flattened depth first into a node array once built, its leaves point into
+// an array of packed triangles
 #[derive(Clone, Debug)]
 pub struct BoundingVolumeHierarchy {
-    root: BvhElement,
+    nodes: Vec<FlatNode>,
+    packs: Vec<PackedTriangles>,
+    // position of each packed triangle among the primitives the tree was built with
+    sources: Vec<[u32; 4]>,
+    // longest path from the root, bounds the traversal stack
+    depth: usize,
+    // relative cost of the tree as built, refits compare theirs against it
+    cost: Float,
 }
 
+// Traversals of deeper trees allocate their stack
+const STACK_SIZE: usize = 64;
+
+// Tree used while building, flattened once done
 #[derive(Clone, Debug)]
-enum BvhElement {
+pub(super) enum BvhElement {
     Empty,
     Node {
         bounding_box: BoundingBox,
@@ -23,198 +37,386 @@ enum BvhElement {
         right: Box<BvhElement>,
     },
     Leaf {
-        primitives: Vec<Arc<Triangle>>,
         packed_primitives: Box<PackedTriangles>,
         bounding_box: BoundingBox,
+        // position of each packed triangle among the primitives
+        sources: [u32; 4],
     },
 }
 
+// The first child of an interior node is the node following it, only the second child
+// needs an offset
+#[derive(Clone, Copy, Debug)]
+pub(super) struct FlatNode {
+    pub(super) min: [Float; 3],
+    pub(super) max: [Float; 3],
+    // second child of interior nodes, first pack of leaves
+    pub(super) offset: u32,
+    // packs of a leaf, zero for interior nodes
+    pub(super) count: u16,
+    // axis separating the children, rays going down it visit the second child first
+    pub(super) axis: u8,
+}
+
+impl FlatNode {
+    pub(super) fn new(
+        bounding_box: &BoundingBox,
+        offset: usize,
+        count: usize,
+        axis: usize,
+    ) -> FlatNode {
+        let BoundingBox { min, max, .. } = bounding_box;
+        FlatNode {
+            min: [min.0, min.1, min.2],
+            max: [max.0, max.1, max.2],
+            offset: offset as u32,
+            count: count as u16,
+            axis: axis as u8,
+        }
+    }
+
+    // Slab test limited to the part of the ray before the max distance
+    pub(super) fn intersect(
+        &self,
+        origin: &[Float; 3],
+        reciprocal: &[Float; 3],
+        max_distance: Float,
+    ) -> bool {
+        let mut near: Float = 0.0;
+        let mut far = max_distance;
+        for axis in 0..3 {
+            let t1 = (self.min[axis] - origin[axis]) * reciprocal[axis];
+            let t2 = (self.max[axis] - origin[axis]) * reciprocal[axis];
+            near = near.max(t1.min(t2));
+            far = far.min(t1.max(t2));
+        }
+        near <= far
+    }
+
+    pub(super) fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::new(
+            Point(self.min[0], self.min[1], self.min[2]),
+            Point(self.max[0], self.max[1], self.max[2]),
+        )
+    }
+
+    fn fit(&mut self, bounding_box: &BoundingBox) {
+        let BoundingBox { min, max, .. } = bounding_box;
+        self.min = [min.0, min.1, min.2];
+        self.max = [max.0, max.1, max.2];
+    }
+}
+
+// Fits the interior nodes around their children again once the leaf function gave the new
+// boxes of the leaves that changed. Children are stored after their parent, going through the
+// nodes backwards fits them first
+pub(super) fn refit_nodes(nodes: &mut [FlatNode], leaf: impl Fn(&FlatNode) -> Option<BoundingBox>) {
+    for idx in (0..nodes.len()).rev() {
+        let node = &nodes[idx];
+        let bounding_box = if node.count > 0 {
+            match leaf(node) {
+                Some(bounding_box) => bounding_box,
+                None => continue,
+            }
+        } else {
+            nodes[idx + 1]
+                .bounding_box()
+                .combine(&nodes[node.offset as usize].bounding_box())
+        };
+        nodes[idx].fit(&bounding_box);
+    }
+}
+
+// Surface area heuristic cost of the tree relative to the area of its root. Refitting keeps
+// the tree valid but lets its nodes grow and overlap, which shows in this cost
+pub(super) fn relative_cost(nodes: &[FlatNode]) -> Float {
+    let Some(root) = nodes.first() else {
+        return 0.0;
+    };
+    let area = root.bounding_box().surface_area();
+    if area <= 0.0 {
+        return 0.0;
+    }
+    nodes
+        .iter()
+        .map(|node| node.bounding_box().surface_area() * node.count.max(1) as Float)
+        .sum::<Float>()
+        / area
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum BvhBuildMethod {
     Octree,
     Sah,
+    // linear BVH splitting the primitives sorted along a Morton curve, fast to rebuild
+    Lbvh,
+    // SAH that also splits space, referencing the primitives crossing the split from both
+    // sides. Spatial splits are only tried where the children of the best object split overlap
+    // more than alpha times the surface of the whole scene, alpha 0 tries them wherever they
+    // overlap. Negative and NaN alphas count as 0
+    SpatialSplit { alpha: Float },
 }
 
-fn rec_trace<'a>(bvh: &'a BvhElement, ray: &Ray, prm_vec: &mut Vec<&'a PackedTriangles>) {
-    match bvh {
-        BvhElement::Empty => (),
-        BvhElement::Node {
-            bounding_box,
-            left,
-            right,
-        } => {
-            if bounding_box.intersect(ray) {
-                rec_trace(left, ray, prm_vec);
-                rec_trace(right, ray, prm_vec);
-            }
+pub(super) fn bounding_box(element: &BvhElement) -> Option<&BoundingBox> {
+    match element {
+        BvhElement::Empty => None,
+        BvhElement::Node { bounding_box, .. } | BvhElement::Leaf { bounding_box, .. } => {
+            Some(bounding_box)
         }
+    }
+}
+
+// Appends the element depth first, returns the depth of its subtree.
+// Nodes with an empty child are replaced by the other child
+fn flatten(
+    element: BvhElement,
+    nodes: &mut Vec<FlatNode>,
+    packs: &mut Vec<PackedTriangles>,
+    sources: &mut Vec<[u32; 4]>,
+) -> usize {
+    match element {
+        BvhElement::Empty => 0,
         BvhElement::Leaf {
-            primitives: _,
-            bounding_box,
             packed_primitives,
+            bounding_box,
+            sources: leaf_sources,
         } => {
-            if bounding_box.intersect(ray) {
-                prm_vec.push(packed_primitives)
-            }
+            nodes.push(FlatNode::new(&bounding_box, packs.len(), 1, 0));
+            packs.push(*packed_primitives);
+            sources.push(leaf_sources);
+            1
+        }
+        BvhElement::Node {
+            bounding_box: node_box,
+            left,
+            right,
+        } => {
+            let (left_box, right_box) = match (bounding_box(&left), bounding_box(&right)) {
+                (Some(left_box), Some(right_box)) => (*left_box, *right_box),
+                (Some(_), None) => return flatten(*left, nodes, packs, sources),
+                (None, _) => return flatten(*right, nodes, packs, sources),
+            };
+            // the children are ordered along the axis their centroids are furthest apart
+            let gap = &right_box.centroid - &left_box.centroid;
+            let gap = [gap.0, gap.1, gap.2];
+            let axis = (0..3).fold(0, |best, axis| {
+                if gap[axis].abs() > gap[best].abs() {
+                    axis
+                } else {
+                    best
+                }
+            });
+            let first = gap[axis] >= 0.0;
+            let (first, second) = if first { (left, right) } else { (right, left) };
+
+            let idx = nodes.len();
+            nodes.push(FlatNode::new(&node_box, 0, 0, axis));
+            let first_depth = flatten(*first, nodes, packs, sources);
+            nodes[idx].offset = nodes.len() as u32;
+            let second_depth = flatten(*second, nodes, packs, sources);
+            1 + first_depth.max(second_depth)
         }
     }
 }
 
 impl Trace for BoundingVolumeHierarchy {
-    fn trace(&self, ray: &Ray) -> Option<Vec<&PackedTriangles>> {
-        let mut idx_vec: Vec<&PackedTriangles> = Vec::with_capacity(256);
-
-        rec_trace(&self.root, ray, &mut idx_vec);
+    fn closest_hit(&self, ray: &Ray) -> Option<ClosestPrimitive<'_>> {
+        self.with_stack(|stack| self.traverse(ray, stack))
+    }
 
-        if idx_vec.is_empty() {
-            None
-        } else {
-            Some(idx_vec)
-        }
+    fn occluded(&self, ray: &Ray, max_distance: Float) -> bool {
+        self.with_stack(|stack| self.any_hit(ray, max_distance, stack))
     }
 }
 
-fn octree_grouping(items: &[Arc<Triangle>]) -> BvhElement {
-    if items.is_empty() {
-        return BvhElement::Empty;
-    }
+// Subtrees with fewer primitives are built on the current thread
+const PARALLEL_THRESHOLD: usize = 4096;
 
-    // Termination condition, checks the size of the items for this group
-    // and returns a leaf node, which has no left/right children
-    if items.len() <= 4 {
-        let mut pt = PackedTriangles::default();
-        for (i, triangle) in items.iter().enumerate() {
-            pt.triangles.push(triangle.clone());
-            pt.origin_x = simd::set(pt.origin_x, triangle.origin.0, i);
-            pt.origin_y = simd::set(pt.origin_y, triangle.origin.1, i);
-            pt.origin_z = simd::set(pt.origin_z, triangle.origin.2, i);
-            pt.edge1_x = simd::set(pt.edge1_x, triangle.edge1.0, i);
-            pt.edge1_y = simd::set(pt.edge1_y, triangle.edge1.1, i);
-            pt.edge1_z = simd::set(pt.edge1_z, triangle.edge1.2, i);
-            pt.edge2_x = simd::set(pt.edge2_x, triangle.edge2.0, i);
-            pt.edge2_y = simd::set(pt.edge2_y, triangle.edge2.1, i);
-            pt.edge2_z = simd::set(pt.edge2_z, triangle.edge2.2, i);
-        }
-        return BvhElement::Leaf {
-            primitives: items.to_vec(),
-            packed_primitives: Box::new(pt),
-            bounding_box: BoundingBox::default(),
-        };
-    }
+const BUCKETS_COUNT: usize = 12;
 
-    // Calculate the center for all the items
-    let center: Vector = items
-        .iter()
-        .fold(Point(0.0, 0.0, 0.0), |acc, prm| {
-            &prm.bounding_box().centroid + &acc
-        })
-        .into();
-    let center = center / (items.len() as Float);
+// Primitives and their bounds computed once, the build partitions indices into them
+struct BuildContext {
+    triangles: Vec<Arc<Triangle>>,
+    bounds: Vec<BoundingBox>,
+    // Morton codes of the centroids, only computed for lbvh builds
+    codes: Vec<u64>,
+}
 
-    // Return the section a particular point is in
-    // The section is one of the eight octree regions
-    let sector = |c: &Point| if c.0 >= center.0 { 1 } else { 0 } +
-        if c.1 >= center.1 { 2 } else { 0 } +
-        if c.2 >= center.2 { 4 } else { 0 };
+impl BuildContext {
+    fn leaf(&self, indices: &[u32]) -> BvhElement {
+        self.bounded_leaf(indices, self.bounding_box(indices))
+    }
 
-    // Collects all the points for a given sector
-    let in_sector = |s| {
-        items
+    // Leaf with a box of its own, references of split primitives only cover part of them
+    fn bounded_leaf(&self, indices: &[u32], bounding_box: BoundingBox) -> BvhElement {
+        let triangles: Vec<Arc<Triangle>> = indices
             .iter()
-            .filter_map(|x| {
-                if sector(&x.bounding_box().centroid) == s {
-                    Some(x.clone())
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<Arc<Triangle>>>()
-    };
+            .map(|&idx| self.triangles[idx as usize].clone())
+            .collect();
+        let mut sources = [u32::MAX; 4];
+        sources[..indices.len()].copy_from_slice(indices);
+        BvhElement::Leaf {
+            packed_primitives: Box::new(PackedTriangles::new(&triangles)),
+            bounding_box,
+            sources,
+        }
+    }
 
-    // All the sectors with their corresponding points
-    let sectors = [
-        in_sector(0),
-        in_sector(1),
-        in_sector(2),
-        in_sector(3),
-        in_sector(4),
-        in_sector(5),
-        in_sector(6),
-        in_sector(7),
-    ];
+    fn centroid(&self, idx: u32) -> &Point {
+        &self.bounds[idx as usize].centroid
+    }
 
-    // Gets the number of points on each sector
-    let lens = sectors.iter().map(|s| s.len() as i64).collect::<Vec<i64>>();
+    fn bounding_box(&self, indices: &[u32]) -> BoundingBox {
+        self.fold(indices, |bb, idx| bb.combine(&self.bounds[idx as usize]))
+    }
 
-    // Calculates the difference between opposite sectors on a given axis
-    let xdiff =
-        ((lens[0] + lens[2] + lens[4] + lens[6]) - (lens[1] + lens[3] + lens[5] + lens[7])).abs();
-    let ydiff =
-        ((lens[0] + lens[1] + lens[4] + lens[5]) - (lens[2] + lens[3] + lens[6] + lens[7])).abs();
-    let zdiff =
-        ((lens[0] + lens[1] + lens[2] + lens[3]) - (lens[4] + lens[5] + lens[6] + lens[7])).abs();
+    // Box around the centroids, splits are chosen within it
+    fn centroid_bounds(&self, indices: &[u32]) -> BoundingBox {
+        self.fold(indices, |bb, idx| {
+            let centroid = self.centroid(idx);
+            BoundingBox::new(bb.min.min(centroid), bb.max.max(centroid))
+        })
+    }
 
-    let box_for_sectors = |sectors: [usize; 4]| {
-        octree_grouping(
-            &items
+    // Combines the boxes given by the primitives, in parallel for large groups
+    fn fold<F>(&self, indices: &[u32], op: F) -> BoundingBox
+    where
+        F: Fn(BoundingBox, u32) -> BoundingBox + Sync,
+    {
+        if indices.len() < PARALLEL_THRESHOLD {
+            return indices
                 .iter()
-                .filter_map(|x| {
-                    if sectors.contains(&sector(&x.bounding_box().centroid)) {
-                        Some(x.clone())
-                    } else {
-                        None
-                    }
-                })
-                .collect::<Vec<Arc<Triangle>>>(),
-        )
-    };
+                .fold(BoundingBox::default(), |bb, &idx| op(bb, idx));
+        }
+        indices
+            .par_chunks(PARALLEL_THRESHOLD)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .fold(BoundingBox::default(), |bb, &idx| op(bb, idx))
+            })
+            .reduce(BoundingBox::default, |a, b| a.combine(&b))
+    }
+}
+
+// Moves the indices matching the predicate to the front, returns how many they are
+fn partition(indices: &mut [u32], predicate: impl Fn(u32) -> bool) -> usize {
+    let mut mid = 0;
+    for idx in 0..indices.len() {
+        if predicate(indices[idx]) {
+            indices.swap(mid, idx);
+            mid += 1;
+        }
+    }
+    mid
+}
 
-    // Determinies which axis contains the smallest difference of points
-    // This indicates what would be the best splitting position
-    // Returns two boxes for this BVH node
-    let (left, right) = if xdiff <= ydiff && xdiff <= zdiff {
-        (box_for_sectors([0, 2, 4, 6]), box_for_sectors([1, 3, 5, 7]))
-    } else if ydiff <= xdiff && ydiff <= zdiff {
-        (box_for_sectors([0, 1, 4, 5]), box_for_sectors([2, 3, 6, 7]))
+// Builds both halves, on separate threads when there are enough primitives
+fn join(
+    indices: &mut [u32],
+    mid: usize,
+    context: &BuildContext,
+    group: fn(&mut [u32], &BuildContext) -> BvhElement,
+) -> BvhElement {
+    // a split leaving a side empty would recurse forever, identical centroids are halved
+    let mid = if mid == 0 || mid == indices.len() {
+        indices.len() / 2
+    } else {
+        mid
+    };
+    let parallel = indices.len() >= PARALLEL_THRESHOLD;
+    let (left, right) = indices.split_at_mut(mid);
+    let (left, right) = if parallel {
+        rayon::join(|| group(left, context), || group(right, context))
     } else {
-        (box_for_sectors([0, 1, 2, 3]), box_for_sectors([4, 5, 6, 7]))
+        (group(left, context), group(right, context))
     };
+    node(left, right)
+}
 
-    // The new BVH node with no primitives yet, but a split of space
-    // The bounding box has no meaning right now, this is just a grouping of indexes
+// Node around both children
+fn node(left: BvhElement, right: BvhElement) -> BvhElement {
+    let bounding_box = [&left, &right]
+        .into_iter()
+        .filter_map(bounding_box)
+        .fold(BoundingBox::default(), |bb, child| bb.combine(child));
     BvhElement::Node {
-        bounding_box: BoundingBox::default(),
+        bounding_box,
         left: Box::new(left),
         right: Box::new(right),
     }
 }
 
-fn sah_grouping(primitives: &[Arc<Triangle>], total_nodes: &mut usize) -> BvhElement {
-    if primitives.is_empty() {
+fn octree_grouping(indices: &mut [u32], context: &BuildContext) -> BvhElement {
+    if indices.is_empty() {
         return BvhElement::Empty;
     }
 
-    *total_nodes += 1;
+    // Termination condition, checks the size of the items for this group
+    // and returns a leaf node, which has no left/right children
+    if indices.len() <= 4 {
+        return context.leaf(indices);
+    }
 
-    if primitives.len() <= 4 {
-        let pt = PackedTriangles::new(primitives);
-        return BvhElement::Leaf {
-            primitives: primitives.to_vec(),
-            packed_primitives: Box::new(pt),
-            bounding_box: primitives.iter().fold(BoundingBox::default(), |bb, prm| {
-                bb.combine(&prm.bounding_box())
-            }),
-        };
+    // Calculate the center for all the items
+    let center: Vector = indices
+        .iter()
+        .fold(Point(0.0, 0.0, 0.0), |acc, &idx| {
+            context.centroid(idx) + &acc
+        })
+        .into();
+    let center = center / (indices.len() as Float);
+    let center = [center.0, center.1, center.2];
+
+    // Gets the number of points under the center on each axis
+    let mut below = [0; 3];
+    for &idx in indices.iter() {
+        let centroid = context.centroid(idx);
+        for (axis, value) in [centroid.0, centroid.1, centroid.2].into_iter().enumerate() {
+            if value < center[axis] {
+                below[axis] += 1;
+            }
+        }
     }
 
-    let mut min_point = primitives[0].bounding_box().centroid;
-    let mut max_point = min_point.clone();
+    // The axis with the smallest difference of points on each side of the center
+    // is the best splitting position
+    let len = indices.len() as i64;
+    let diff = below.map(|below: i64| (len - 2 * below).abs());
+    let axis = if diff[0] <= diff[1] && diff[0] <= diff[2] {
+        Axis::X
+    } else if diff[1] <= diff[2] {
+        Axis::Y
+    } else {
+        Axis::Z
+    };
+    let split = match axis {
+        Axis::X => center[0],
+        Axis::Y => center[1],
+        Axis::Z => center[2],
+    };
+
+    let mid = partition(indices, |idx| {
+        context.centroid(idx).get_component(axis) < split
+    });
+    join(indices, mid, context, octree_grouping)
+}
+
+fn sah_grouping(indices: &mut [u32], context: &BuildContext) -> BvhElement {
+    if indices.is_empty() {
+        return BvhElement::Empty;
+    }
 
-    for primitive in primitives {
-        min_point = min_point.min(&primitive.bounding_box().centroid);
-        max_point = max_point.max(&primitive.bounding_box().centroid);
+    if indices.len() <= 4 {
+        return context.leaf(indices);
     }
 
+    let BoundingBox {
+        min: min_point,
+        max: max_point,
+        ..
+    } = context.centroid_bounds(indices);
+
     let extents = &max_point - &min_point;
 
     let selected_axis = if extents.get_component(Axis::X) > extents.get_component(Axis::Y)
@@ -228,170 +430,1021 @@ fn sah_grouping(primitives: &[Arc<Triangle>], total_nodes: &mut usize) -> BvhEle
     };
 
     let extent = extents.get_component(selected_axis);
+    if extent == 0.0 {
+        return join(indices, 0, context, sah_grouping);
+    }
 
     // Put all primitives in the different buckets
-    const BUCKETS_COUNT: usize = 12;
-    let mut buckets: [Vec<Arc<Triangle>>; BUCKETS_COUNT] = Default::default();
+    let min = min_point.get_component(selected_axis);
     let bucket_size = extent / ((BUCKETS_COUNT - 1) as Float);
+    let bucket = |idx: u32| {
+        let offset = context.centroid(idx).get_component(selected_axis) - min;
+        ((offset / bucket_size).floor() as usize).min(BUCKETS_COUNT - 1)
+    };
 
-    for primitive in primitives {
-        let bucket_id = ((primitive
-            .bounding_box()
-            .centroid
-            .get_component(selected_axis)
-            - min_point.get_component(selected_axis))
-            / bucket_size)
-            .floor() as usize;
-        buckets[bucket_id].push(primitive.clone());
-    }
-
-    // Calculate each bucket bounding box
-    let buckets_bb = buckets
-        .iter()
-        .map(|primitives| {
-            primitives.iter().fold(BoundingBox::default(), |bb, prm| {
-                bb.combine(&prm.bounding_box())
-            })
-        })
-        .collect::<Vec<BoundingBox>>();
+    // Count the primitives and calculate the bounding box of each bucket
+    let bin = |chunk: &[u32]| {
+        let mut buckets = [(0, BoundingBox::default()); BUCKETS_COUNT];
+        for &idx in chunk {
+            let (count, bb) = &mut buckets[bucket(idx)];
+            *count += 1;
+            *bb = bb.combine(&context.bounds[idx as usize]);
+        }
+        buckets
+    };
+    let buckets = if indices.len() < PARALLEL_THRESHOLD {
+        bin(indices)
+    } else {
+        indices.par_chunks(PARALLEL_THRESHOLD).map(bin).reduce(
+            || [(0, BoundingBox::default()); BUCKETS_COUNT],
+            |mut a, b| {
+                for (a, b) in a.iter_mut().zip(b) {
+                    *a = (a.0 + b.0, a.1.combine(&b.1));
+                }
+                a
+            },
+        )
+    };
 
-    let partitions_costs = (1..BUCKETS_COUNT)
-        .map(|pid| {
-            let left_cost = buckets_bb[0..pid]
-                .iter()
-                .fold(BoundingBox::default(), |a, b| a.combine(b))
-                .surface_area()
-                * (buckets[0..pid]
-                    .iter()
-                    .fold(0, |acc, primitives| acc + primitives.len()) as Float);
-            let right_cost = buckets_bb[pid..]
-                .iter()
-                .fold(BoundingBox::default(), |a, b| a.combine(b))
-                .surface_area()
-                * (buckets[pid..]
-                    .iter()
-                    .fold(0, |acc, primitives| acc + primitives.len()) as Float);
-            left_cost + right_cost
-        })
-        .collect::<Vec<Float>>();
+    let cost = |buckets: &[(usize, BoundingBox)]| {
+        let (count, bb) = buckets
+            .iter()
+            .fold((0, BoundingBox::default()), |(count, bb), (c, b)| {
+                (count + c, bb.combine(b))
+            });
+        bb.surface_area() * count as Float
+    };
 
     let mut split_bucket = 0;
     let mut min_cost = Float::MAX;
-    for idx in 0..(BUCKETS_COUNT - 1) {
-        let cost = partitions_costs[idx];
+    for pid in 1..BUCKETS_COUNT {
+        let cost = cost(&buckets[0..pid]) + cost(&buckets[pid..]);
         if cost < min_cost {
             min_cost = cost;
-            split_bucket = idx + 1;
+            split_bucket = pid;
         }
     }
 
-    let left_group: Vec<Arc<Triangle>> =
-        buckets[0..split_bucket]
-            .into_iter()
-            .fold(Vec::new(), |mut acc, el| {
-                acc.append(&mut el.clone());
-                acc
-            });
+    let mid = partition(indices, |idx| bucket(idx) < split_bucket);
+    join(indices, mid, context, sah_grouping)
+}
 
-    let right_group: Vec<Arc<Triangle>> =
-        buckets[split_bucket..]
-            .into_iter()
-            .fold(Vec::new(), |mut acc, el| {
-                acc.append(&mut el.clone());
-                acc
-            });
+// Spreads the lowest 21 bits of the value so there are two zero bits between each of them
+fn spread_bits(value: u64) -> u64 {
+    let value = value & 0x1fffff;
+    let value = (value | value << 32) & 0x1f00000000ffff;
+    let value = (value | value << 16) & 0x1f0000ff0000ff;
+    let value = (value | value << 8) & 0x100f00f00f00f00f;
+    let value = (value | value << 4) & 0x10c30c30c30c30c3;
+    (value | value << 2) & 0x1249249249249249
+}
 
-    BvhElement::Node {
-        bounding_box: primitives.iter().fold(BoundingBox::default(), |bb, prm| {
-            bb.combine(&prm.bounding_box())
-        }),
-        left: Box::new(sah_grouping(&left_group, total_nodes)),
-        right: Box::new(sah_grouping(&right_group, total_nodes)),
-    }
-}
-
-// Will simply recourse the BVH and update the building boxes accordingly
-fn rebuild(prms: &[Arc<Triangle>], root: BvhElement, total_nodes: &mut usize) -> BvhElement {
-    match root {
-        BvhElement::Empty => BvhElement::Empty,
-        BvhElement::Node { left, right, .. } => {
-            // let t = Arc::get_mut(&mut total_nodes).unwrap();
-            *total_nodes += 1;
-            let left = rebuild(prms, *left, total_nodes);
-            let right = rebuild(prms, *right, total_nodes);
-            let mut bounding_box = BoundingBox::default();
-            match &left {
-                BvhElement::Empty => (),
-                BvhElement::Node {
-                    bounding_box: bb, ..
-                } => bounding_box = bounding_box.combine(bb),
-                BvhElement::Leaf {
-                    bounding_box: bb, ..
-                } => bounding_box = bounding_box.combine(bb),
-            }
+// 63 bit Morton codes of the centroids, 21 bits per axis within the box around them.
+// 30 bit codes leave too many primitives of large scans with the same code
+fn morton_codes(indices: &[u32], context: &BuildContext) -> Vec<u64> {
+    let BoundingBox { min, max, .. } = context.centroid_bounds(indices);
+    let scale = |axis: Axis| {
+        let extent = max.get_component(axis) - min.get_component(axis);
+        if extent > 0.0 {
+            ((1 << 21) - 1) as Float / extent
+        } else {
+            0.0
+        }
+    };
+    let scale = [scale(Axis::X), scale(Axis::Y), scale(Axis::Z)];
+    context
+        .bounds
+        .par_iter()
+        .map(|bb| {
+            let centroid = &bb.centroid;
+            let x = ((centroid.0 - min.0) * scale[0]) as u64;
+            let y = ((centroid.1 - min.1) * scale[1]) as u64;
+            let z = ((centroid.2 - min.2) * scale[2]) as u64;
+            spread_bits(x) << 2 | spread_bits(y) << 1 | spread_bits(z)
+        })
+        .collect()
+}
+
+// Splits the primitives, sorted by their codes, where the highest bit that differs in the
+// group changes
+fn lbvh_grouping(indices: &mut [u32], context: &BuildContext) -> BvhElement {
+    if indices.is_empty() {
+        return BvhElement::Empty;
+    }
+
+    if indices.len() <= 4 {
+        return context.leaf(indices);
+    }
+
+    let code = |idx: u32| context.codes[idx as usize];
+    let first = code(indices[0]);
+    let last = code(indices[indices.len() - 1]);
+    // primitives sharing a code are split in halves
+    let mid = if first == last {
+        0
+    } else {
+        let bit = 1 << (63 - (first ^ last).leading_zeros());
+        indices.partition_point(|&idx| code(idx) & bit == 0)
+    };
+    join(indices, mid, context, lbvh_grouping)
+}
+
+// Bins spatial splits are evaluated at
+const SPATIAL_BINS: usize = 32;
+
+// Spatial splits may reference every primitive from both sides, past this depth only objects
+// are split so the build always ends
+const MAX_SPATIAL_DEPTH: usize = 48;
+
+// Primitive within a node of a spatial split build, the box only covers the part of the
+// primitive inside the node once it has been split
+#[derive(Clone, Copy, Debug)]
+struct Reference {
+    idx: u32,
+    bounding_box: BoundingBox,
+}
 
-            match &right {
-                BvhElement::Empty => (),
-                BvhElement::Node {
-                    bounding_box: bb, ..
-                } => bounding_box = bounding_box.combine(bb),
-                BvhElement::Leaf {
-                    bounding_box: bb, ..
-                } => bounding_box = bounding_box.combine(bb),
+fn coordinates(point: &Point) -> [Float; 3] {
+    [point.0, point.1, point.2]
+}
+
+fn extend(bounding_box: &BoundingBox, point: [Float; 3]) -> BoundingBox {
+    let point = Point(point[0], point[1], point[2]);
+    BoundingBox::new(bounding_box.min.min(&point), bounding_box.max.max(&point))
+}
+
+// Part of the box within the other one, none when they don't overlap
+fn clip(bounding_box: &BoundingBox, other: &BoundingBox) -> Option<BoundingBox> {
+    let min = bounding_box.min.max(&other.min);
+    let max = bounding_box.max.min(&other.max);
+    if min.0 > max.0 || min.1 > max.1 || min.2 > max.2 {
+        return None;
+    }
+    Some(BoundingBox::new(min, max))
+}
+
+// Boxes of the parts of the reference below and above the plane at the position on the axis.
+// Triangles are clipped by the plane, moving ones only have their box cut
+fn split_reference(
+    context: &BuildContext,
+    reference: &Reference,
+    axis: usize,
+    position: Float,
+) -> (Option<BoundingBox>, Option<BoundingBox>) {
+    let triangle = &context.triangles[reference.idx as usize];
+    let bounding_box = &reference.bounding_box;
+    let (below, above) = if triangle.motion.is_some() {
+        let mut max = coordinates(&bounding_box.max);
+        let mut min = coordinates(&bounding_box.min);
+        max[axis] = position;
+        min[axis] = position;
+        (
+            extend(&BoundingBox::new(bounding_box.min, bounding_box.min), max),
+            extend(&BoundingBox::new(bounding_box.max, bounding_box.max), min),
+        )
+    } else {
+        let vertices = [&triangle.origin, &triangle.pt2, &triangle.pt3].map(coordinates);
+        let (mut below, mut above) = (BoundingBox::default(), BoundingBox::default());
+        for (idx, a) in vertices.iter().enumerate() {
+            let b = vertices[(idx + 1) % 3];
+            if a[axis] <= position {
+                below = extend(&below, *a);
             }
+            if a[axis] >= position {
+                above = extend(&above, *a);
+            }
+            // edges crossing the plane add the point where they do to both sides
+            if (a[axis] < position && b[axis] > position)
+                || (a[axis] > position && b[axis] < position)
+            {
+                let t = (position - a[axis]) / (b[axis] - a[axis]);
+                let mut point = [0, 1, 2].map(|axis| a[axis] + (b[axis] - a[axis]) * t);
+                point[axis] = position;
+                below = extend(&below, point);
+                above = extend(&above, point);
+            }
+        }
+        (below, above)
+    };
+    (clip(&below, bounding_box), clip(&above, bounding_box))
+}
 
-            BvhElement::Node {
-                left: Box::new(left),
-                right: Box::new(right),
-                bounding_box,
+// Split of the references of a node
+enum Split {
+    // references with their centroid below the position go to the first child
+    Object {
+        axis: usize,
+        position: Float,
+    },
+    // references crossing the plane at the position go to both children, unless adding them
+    // whole to the expected boxes and counts of one child costs less
+    Spatial {
+        axis: usize,
+        position: Float,
+        bounds: [BoundingBox; 2],
+        counts: [usize; 2],
+    },
+    Halves,
+}
+
+// Cost of the children made of the groups on each side of every boundary between them,
+// returns the lowest with its boundary and the children boxes
+fn best_boundary(
+    groups: &[(usize, usize, BoundingBox)],
+    total: usize,
+) -> Option<(Float, usize, BoundingBox, BoundingBox)> {
+    let side = |groups: &[(usize, usize, BoundingBox)],
+                count: fn(&(usize, usize, BoundingBox)) -> usize| {
+        groups
+            .iter()
+            .fold((0, BoundingBox::default()), |(sum, bb), group| {
+                (sum + count(group), bb.combine(&group.2))
+            })
+    };
+    let mut best: Option<(Float, usize, BoundingBox, BoundingBox)> = None;
+    for boundary in 1..groups.len() {
+        let (left_count, left) = side(&groups[..boundary], |group| group.0);
+        let (right_count, right) = side(&groups[boundary..], |group| group.1);
+        if left_count == 0 || right_count == 0 || (left_count == total && right_count == total) {
+            continue;
+        }
+        let cost =
+            left.surface_area() * left_count as Float + right.surface_area() * right_count as Float;
+        if best.is_none_or(|(min_cost, ..)| cost < min_cost) {
+            best = Some((cost, boundary, left, right));
+        }
+    }
+    best
+}
+
+// SAH over buckets of the reference centroids along every axis, returns the split with its cost and the
+// surface area where its children overlap
+fn object_split(references: &[Reference]) -> Option<(Split, Float, Float)> {
+    let centroid_bounds = references
+        .iter()
+        .fold(BoundingBox::default(), |bb, reference| {
+            extend(&bb, coordinates(&reference.bounding_box.centroid))
+        });
+    (0..3)
+        .filter_map(|axis| object_split_on(references, &centroid_bounds, axis))
+        .min_by(|(_, a, _), (_, b, _)| a.total_cmp(b))
+}
+
+fn object_split_on(
+    references: &[Reference],
+    centroid_bounds: &BoundingBox,
+    axis: usize,
+) -> Option<(Split, Float, Float)> {
+    let extent = coordinates(&centroid_bounds.max)[axis] - coordinates(&centroid_bounds.min)[axis];
+    if extent == 0.0 {
+        return None;
+    }
+
+    let min = coordinates(&centroid_bounds.min)[axis];
+    let bucket_size = extent / ((BUCKETS_COUNT - 1) as Float);
+    let mut buckets = [(0, 0, BoundingBox::default()); BUCKETS_COUNT];
+    for reference in references {
+        let offset = coordinates(&reference.bounding_box.centroid)[axis] - min;
+        let bucket = &mut buckets[((offset / bucket_size) as usize).min(BUCKETS_COUNT - 1)];
+        *bucket = (
+            bucket.0 + 1,
+            bucket.1 + 1,
+            bucket.2.combine(&reference.bounding_box),
+        );
+    }
+
+    let (cost, boundary, left, right) = best_boundary(&buckets, references.len())?;
+    let overlap = clip(&left, &right).map_or(0.0, |bb| bb.surface_area());
+    let position = min + bucket_size * boundary as Float;
+    Some((Split::Object { axis, position }, cost, overlap))
+}
+
+// SAH over slices of the node along every axis, the references are clipped to every slice
+// they cross. Returns the split with its cost
+fn spatial_split(
+    references: &[Reference],
+    bounding_box: &BoundingBox,
+    context: &BuildContext,
+) -> Option<(Split, Float)> {
+    (0..3)
+        .filter_map(|axis| spatial_split_on(references, bounding_box, context, axis))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+}
+
+fn spatial_split_on(
+    references: &[Reference],
+    bounding_box: &BoundingBox,
+    context: &BuildContext,
+    axis: usize,
+) -> Option<(Split, Float)> {
+    let extent = coordinates(&bounding_box.max)[axis] - coordinates(&bounding_box.min)[axis];
+    if extent == 0.0 {
+        return None;
+    }
+
+    // references entering and leaving each bin and the box of their parts inside it
+    let min = coordinates(&bounding_box.min)[axis];
+    let bin_size = extent / SPATIAL_BINS as Float;
+    let bin = |value: Float| (((value - min) / bin_size) as usize).min(SPATIAL_BINS - 1);
+    let mut bins = [(0, 0, BoundingBox::default()); SPATIAL_BINS];
+    for reference in references {
+        let first = bin(coordinates(&reference.bounding_box.min)[axis]);
+        let last = bin(coordinates(&reference.bounding_box.max)[axis]);
+        bins[first].0 += 1;
+        bins[last].1 += 1;
+        let mut part = Some(*reference);
+        for (idx, bin) in bins.iter_mut().enumerate().take(last).skip(first) {
+            let Some(current) = part else {
+                break;
+            };
+            let position = min + bin_size * (idx + 1) as Float;
+            let (below, above) = split_reference(context, &current, axis, position);
+            if let Some(below) = below {
+                bin.2 = bin.2.combine(&below);
             }
+            part = above.map(|bounding_box| Reference {
+                idx: current.idx,
+                bounding_box,
+            });
         }
-        BvhElement::Leaf {
-            primitives,
-            packed_primitives,
-            ..
+        if let Some(part) = part {
+            bins[last].2 = bins[last].2.combine(&part.bounding_box);
+        }
+    }
+
+    let (cost, boundary, left, right) = best_boundary(&bins, references.len())?;
+    let position = min + bin_size * boundary as Float;
+    let counts = [
+        bins[..boundary].iter().map(|bin| bin.0).sum(),
+        bins[boundary..].iter().map(|bin| bin.1).sum(),
+    ];
+    let split = Split::Spatial {
+        axis,
+        position,
+        bounds: [left, right],
+        counts,
+    };
+    Some((split, cost))
+}
+
+// Splits the references like sah_grouping, or splits space when the children of the object
+// split overlap more than the min overlap and splitting space costs less
+fn sbvh_grouping(
+    mut references: Vec<Reference>,
+    context: &BuildContext,
+    min_overlap: Float,
+    depth: usize,
+) -> BvhElement {
+    if references.is_empty() {
+        return BvhElement::Empty;
+    }
+
+    let bounding_box = references
+        .iter()
+        .fold(BoundingBox::default(), |bb, reference| {
+            bb.combine(&reference.bounding_box)
+        });
+    if references.len() <= 4 {
+        let indices: Vec<u32> = references.iter().map(|reference| reference.idx).collect();
+        return context.bounded_leaf(&indices, bounding_box);
+    }
+
+    let (object, object_cost, overlap) =
+        object_split(&references).unwrap_or((Split::Halves, Float::MAX, Float::MAX));
+    let spatial = if depth < MAX_SPATIAL_DEPTH && overlap > min_overlap {
+        spatial_split(&references, &bounding_box, context)
+    } else {
+        None
+    };
+    let split = match spatial {
+        Some((spatial, cost)) if cost < object_cost => spatial,
+        _ => object,
+    };
+
+    let len = references.len();
+    let (mut left, mut right) = match split {
+        Split::Object { axis, position } => references
+            .into_iter()
+            .partition(|reference| coordinates(&reference.bounding_box.centroid)[axis] < position),
+        Split::Spatial {
+            axis,
+            position,
+            mut bounds,
+            mut counts,
         } => {
-            let bounding_box = primitives.iter().fold(BoundingBox::default(), |acc, p| {
-                acc.combine(&p.bounding_box())
-            });
+            let (mut left, mut right) = (vec![], vec![]);
+            for reference in references {
+                if coordinates(&reference.bounding_box.max)[axis] <= position {
+                    left.push(reference);
+                    continue;
+                }
+                if coordinates(&reference.bounding_box.min)[axis] >= position {
+                    right.push(reference);
+                    continue;
+                }
+                // unsplit when moving the whole reference to one side costs less
+                let area =
+                    |side: usize| bounds[side].combine(&reference.bounding_box).surface_area();
+                let [left_area, right_area] = bounds.map(|bb| bb.surface_area());
+                let [left_count, right_count] = counts.map(|count| count as Float);
+                let split_cost = left_area * left_count + right_area * right_count;
+                let left_cost = area(0) * left_count + right_area * (right_count - 1.0);
+                let right_cost = left_area * (left_count - 1.0) + area(1) * right_count;
+                if left_cost < split_cost && left_cost <= right_cost {
+                    bounds[0] = bounds[0].combine(&reference.bounding_box);
+                    counts[1] = counts[1].saturating_sub(1);
+                    left.push(reference);
+                } else if right_cost < split_cost {
+                    bounds[1] = bounds[1].combine(&reference.bounding_box);
+                    counts[0] = counts[0].saturating_sub(1);
+                    right.push(reference);
+                } else {
+                    let (below, above) = split_reference(context, &reference, axis, position);
+                    let part = |bounding_box| Reference {
+                        idx: reference.idx,
+                        bounding_box,
+                    };
+                    left.extend(below.map(part));
+                    right.extend(above.map(part));
+                }
+            }
+            (left, right)
+        }
+        Split::Halves => {
+            let right = references.split_off(len / 2);
+            (references, right)
+        }
+    };
+    // identical centroids are halved
+    if left.is_empty() || right.is_empty() {
+        left.append(&mut right);
+        right = left.split_off(left.len() / 2);
+    }
 
-            BvhElement::Leaf {
-                primitives,
-                packed_primitives,
-                bounding_box,
+    let (left, right) = if len >= PARALLEL_THRESHOLD {
+        rayon::join(
+            || sbvh_grouping(left, context, min_overlap, depth + 1),
+            || sbvh_grouping(right, context, min_overlap, depth + 1),
+        )
+    } else {
+        (
+            sbvh_grouping(left, context, min_overlap, depth + 1),
+            sbvh_grouping(right, context, min_overlap, depth + 1),
+        )
+    };
+    node(left, right)
+}
+
+// Figures of a built tree
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct BuildStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    pub depth: usize,
+}
+
+impl BuildStats {
+    fn new(element: &BvhElement) -> BuildStats {
+        match element {
+            BvhElement::Empty => BuildStats::default(),
+            BvhElement::Leaf { .. } => BuildStats {
+                nodes: 1,
+                leaves: 1,
+                depth: 1,
+            },
+            BvhElement::Node { left, right, .. } => {
+                let left = BuildStats::new(left);
+                let right = BuildStats::new(right);
+                BuildStats {
+                    nodes: left.nodes + right.nodes + 1,
+                    leaves: left.leaves + right.leaves,
+                    depth: left.depth.max(right.depth) + 1,
+                }
             }
         }
     }
 }
 
+// Builds the binary tree and reports its figures, returns it with them
+pub(super) fn build(
+    build_method: BvhBuildMethod,
+    primitives: Vec<Triangle>,
+) -> (BvhElement, BuildStats) {
+    // wasm32 has no clock
+    #[cfg(not(target_arch = "wasm32"))]
+    let start = std::time::Instant::now();
+
+    let triangles: Vec<Arc<Triangle>> = primitives.into_par_iter().map(Arc::new).collect();
+    let bounds = triangles
+        .par_iter()
+        .map(|triangle| triangle.bounding_box())
+        .collect();
+    let mut context = BuildContext {
+        triangles,
+        bounds,
+        codes: vec![],
+    };
+    let mut indices: Vec<u32> = (0..context.triangles.len() as u32).collect();
+    let root = match build_method {
+        BvhBuildMethod::Octree => octree_grouping(&mut indices, &context),
+        BvhBuildMethod::Sah => sah_grouping(&mut indices, &context),
+        BvhBuildMethod::Lbvh => {
+            context.codes = morton_codes(&indices, &context);
+            indices.par_sort_unstable_by_key(|&idx| context.codes[idx as usize]);
+            lbvh_grouping(&mut indices, &context)
+        }
+        BvhBuildMethod::SpatialSplit { alpha } => {
+            let references = context
+                .bounds
+                .iter()
+                .enumerate()
+                .map(|(idx, bounding_box)| Reference {
+                    idx: idx as u32,
+                    bounding_box: *bounding_box,
+                })
+                .collect();
+            let min_overlap = alpha.max(0.0) * context.bounding_box(&indices).surface_area();
+            sbvh_grouping(references, &context, min_overlap, 0)
+        }
+    };
+
+    let stats = BuildStats::new(&root);
+    if stats.nodes > 0 {
+        eprintln!(
+            "total_triangles: {}\ntotal_nodes: {}\ntotal_leaves: {}\nbvh_depth: {}",
+            indices.len(),
+            stats.nodes,
+            stats.leaves,
+            stats.depth
+        );
+        #[cfg(not(target_arch = "wasm32"))]
+        eprintln!(
+            "bvh_build_time: {}ms",
+            start.elapsed().as_secs_f32() * 1000.0
+        );
+    }
+    (root, stats)
+}
+
 impl BoundingVolumeHierarchy {
     pub fn new(build_method: BvhBuildMethod, primitives: Vec<Triangle>) -> BoundingVolumeHierarchy {
-        let len = primitives.len();
-        if len == 0 {
-            return BoundingVolumeHierarchy {
-                // primitives,
-                root: BvhElement::Empty,
-            };
+        let (bvh, stats) = build(build_method, primitives);
+
+        let mut nodes = Vec::with_capacity(stats.nodes);
+        let mut packs = Vec::with_capacity(stats.leaves);
+        let mut sources = Vec::with_capacity(stats.leaves);
+        let depth = flatten(bvh, &mut nodes, &mut packs, &mut sources);
+        let cost = relative_cost(&nodes);
+        BoundingVolumeHierarchy {
+            nodes,
+            packs,
+            sources,
+            depth,
+            cost,
+        }
+    }
+
+    // Replaces the primitives from the first one on, counted in the order the tree was built
+    // with, and fits the nodes around them again instead of building a new tree. Returns
+    // whether the refitted tree costs no more than the threshold times the built one, trees
+    // degrading past it should be built again
+    pub(super) fn refit(
+        &mut self,
+        first: usize,
+        primitives: Vec<Triangle>,
+        threshold: Float,
+    ) -> bool {
+        let moved: Vec<Arc<Triangle>> = primitives.into_iter().map(Arc::new).collect();
+        let replacement = |source: u32| moved.get((source as usize).wrapping_sub(first));
+        let changed: Vec<bool> = self
+            .packs
+            .iter_mut()
+            .zip(self.sources.iter())
+            .map(|(pack, sources)| {
+                if !sources.iter().any(|&source| replacement(source).is_some()) {
+                    return false;
+                }
+                let triangles: Vec<Arc<Triangle>> = pack
+                    .triangles
+                    .iter()
+                    .zip(sources)
+                    .map(|(triangle, &source)| replacement(source).unwrap_or(triangle).clone())
+                    .collect();
+                *pack = PackedTriangles::new(&triangles);
+                true
+            })
+            .collect();
+
+        let packs = &self.packs;
+        refit_nodes(&mut self.nodes, |node| {
+            let range = node.offset as usize..node.offset as usize + node.count as usize;
+            changed[range.clone()]
+                .iter()
+                .any(|&changed| changed)
+                .then(|| {
+                    packs[range]
+                        .iter()
+                        .flat_map(|pack| pack.triangles.iter())
+                        .fold(BoundingBox::default(), |bb, triangle| {
+                            bb.combine(&triangle.bounding_box())
+                        })
+                })
+        });
+        relative_cost(&self.nodes) <= threshold * self.cost
+    }
+
+    // Nodes, triangle order and cost of the tree as little endian bytes
+    pub(super) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(12 + self.nodes.len() * 32 + self.sources.len() * 16);
+        bytes.extend(self.cost.to_le_bytes());
+        bytes.extend((self.nodes.len() as u32).to_le_bytes());
+        for node in self.nodes.iter() {
+            for value in node.min.iter().chain(node.max.iter()) {
+                bytes.extend(value.to_le_bytes());
+            }
+            bytes.extend(node.offset.to_le_bytes());
+            bytes.extend(node.count.to_le_bytes());
+            bytes.extend([node.axis, 0]);
+        }
+        bytes.extend((self.sources.len() as u32).to_le_bytes());
+        for source in self.sources.iter().flatten() {
+            bytes.extend(source.to_le_bytes());
         }
+        bytes
+    }
 
-        let mut total_nodes: usize = 0;
-        let total_primitives = primitives.len();
+    // Tree saved with to_bytes over the same primitives, none when the bytes do not hold a
+    // valid tree for them
+    pub(super) fn from_bytes(
+        mut bytes: &[u8],
+        primitives: Vec<Triangle>,
+    ) -> Option<BoundingVolumeHierarchy> {
+        fn take<const N: usize>(bytes: &mut &[u8]) -> Option<[u8; N]> {
+            let (head, tail) = bytes.split_first_chunk::<N>()?;
+            *bytes = tail;
+            Some(*head)
+        }
+        let u32 = |bytes: &mut &[u8]| take(bytes).map(u32::from_le_bytes);
+        let float = |bytes: &mut &[u8]| take(bytes).map(Float::from_le_bytes);
 
-        let bvh = {
-            let prms: Vec<Arc<Triangle>> = primitives.into_iter().map(Arc::new).collect();
+        let cost = float(&mut bytes)?;
+        let node_count = u32(&mut bytes)? as usize;
+        let mut nodes = Vec::with_capacity(node_count.min(bytes.len() / 32));
+        for _ in 0..node_count {
+            let mut values = [0.0; 6];
+            for value in values.iter_mut() {
+                *value = float(&mut bytes)?;
+            }
+            let offset = u32(&mut bytes)?;
+            let count = take(&mut bytes).map(u16::from_le_bytes)?;
+            let [axis, _] = take(&mut bytes)?;
+            nodes.push(FlatNode {
+                min: [values[0], values[1], values[2]],
+                max: [values[3], values[4], values[5]],
+                offset,
+                count,
+                axis,
+            });
+        }
+        let pack_count = u32(&mut bytes)? as usize;
+        let mut sources = Vec::with_capacity(pack_count.min(bytes.len() / 16));
+        for _ in 0..pack_count {
+            let mut pack = [u32::MAX; 4];
+            for source in pack.iter_mut() {
+                *source = u32(&mut bytes)?;
+            }
+            sources.push(pack);
+        }
 
-            match build_method {
-                BvhBuildMethod::Octree => {
-                    let root = octree_grouping(&prms);
-                    rebuild(&prms, root, &mut total_nodes)
+        // children after their parents and packs within the array, so traversals end, and
+        // every source among the primitives
+        let valid = bytes.is_empty()
+            && nodes.iter().enumerate().all(|(idx, node)| {
+                let offset = node.offset as usize;
+                if node.count == 0 {
+                    offset > idx + 1 && offset < nodes.len() && node.axis < 3
+                } else {
+                    offset + node.count as usize <= sources.len()
                 }
-                BvhBuildMethod::Sah => sah_grouping(&prms, &mut total_nodes),
+            })
+            && sources
+                .iter()
+                .flatten()
+                .all(|&source| source == u32::MAX || (source as usize) < primitives.len());
+        if !valid {
+            return None;
+        }
+
+        // the depth of a node is one more than the one of its parent
+        let mut depths = vec![1; nodes.len()];
+        for (idx, node) in nodes.iter().enumerate() {
+            if node.count == 0 {
+                depths[idx + 1] = depths[idx] + 1;
+                depths[node.offset as usize] = depths[idx] + 1;
             }
-        };
-        eprintln!(
-            "total_triangles: {}\ntotal_nodes: {}",
-            total_primitives, total_nodes
+        }
+        let depth = depths.into_iter().max().unwrap_or(0);
+
+        let triangles: Vec<Arc<Triangle>> = primitives.into_iter().map(Arc::new).collect();
+        let packs = sources
+            .iter()
+            .map(|pack| {
+                let pack: Vec<Arc<Triangle>> = pack
+                    .iter()
+                    .take_while(|&&source| source != u32::MAX)
+                    .map(|&source| triangles[source as usize].clone())
+                    .collect();
+                PackedTriangles::new(&pack)
+            })
+            .collect();
+        Some(BoundingVolumeHierarchy {
+            nodes,
+            packs,
+            sources,
+            depth,
+            cost,
+        })
+    }
+
+    // Box around every primitive, none for empty trees
+    pub(super) fn bounds(&self) -> Option<BoundingBox> {
+        self.nodes.first().map(FlatNode::bounding_box)
+    }
+
+    fn with_stack<T>(&self, traversal: impl FnOnce(&mut [u32]) -> T) -> T {
+        if self.depth <= STACK_SIZE {
+            traversal(&mut [0; STACK_SIZE])
+        } else {
+            traversal(&mut vec![0; self.depth])
+        }
+    }
+
+    // Visits the nodes front to back, the nearer child first, skipping the ones beyond the
+    // closest hit found so far
+    fn traverse(&self, ray: &Ray, stack: &mut [u32]) -> Option<ClosestPrimitive<'_>> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let origin = [ray.origin.0, ray.origin.1, ray.origin.2];
+        let reciprocal = &ray.direction_reciprocal;
+        let negative = [
+            reciprocal[0] < 0.0,
+            reciprocal[1] < 0.0,
+            reciprocal[2] < 0.0,
+        ];
+
+        let mut closest = None;
+        let mut max_distance = ray.max_distance;
+        let mut top = 0;
+        let mut idx = 0;
+        loop {
+            let node = &self.nodes[idx];
+            if node.intersect(&origin, reciprocal, max_distance) {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    for pack in &self.packs[first..first + node.count as usize] {
+                        if let Some(hit) = pack.closest_hit(ray, max_distance) {
+                            max_distance = hit.distance;
+                            closest = Some(hit);
+                        }
+                    }
+                } else {
+                    let (near, far) = if negative[node.axis as usize] {
+                        (node.offset as usize, idx + 1)
+                    } else {
+                        (idx + 1, node.offset as usize)
+                    };
+                    stack[top] = far as u32;
+                    top += 1;
+                    idx = near;
+                    continue;
+                }
+            }
+            if top == 0 {
+                return closest;
+            }
+            top -= 1;
+            idx = stack[top] as usize;
+        }
+    }
+
+    // Returns as soon as a leaf has a hit within the max distance, the order of the children
+    // does not matter
+    fn any_hit(&self, ray: &Ray, max_distance: Float, stack: &mut [u32]) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let origin = [ray.origin.0, ray.origin.1, ray.origin.2];
+        let reciprocal = &ray.direction_reciprocal;
+
+        let mut top = 0;
+        let mut idx = 0;
+        loop {
+            let node = &self.nodes[idx];
+            if node.intersect(&origin, reciprocal, max_distance) {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    if self.packs[first..first + node.count as usize]
+                        .iter()
+                        .any(|pack| pack.hit_within(ray, max_distance))
+                    {
+                        return true;
+                    }
+                } else {
+                    stack[top] = node.offset;
+                    top += 1;
+                    idx += 1;
+                    continue;
+                }
+            }
+            if top == 0 {
+                return false;
+            }
+            top -= 1;
+            idx = stack[top] as usize;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ilios_types::{
+        float::Float,
+        geometry::{Normal, Point, Triangle},
+        material::Material,
+        ray::Ray,
+        solids::Solid,
+        transform::Transform,
+    };
+
+    use super::{BoundingVolumeHierarchy, BvhBuildMethod, relative_cost};
+    use crate::{
+        accelerators::{brute_force::BruteForce, tests::rays, wide_bvh::WideBvh},
+        demos,
+        trace::Trace,
+    };
+
+    #[test]
+    fn closest_hits_match_brute_force() {
+        let primitives = demos::cornell().primitives();
+        let brute_force = BruteForce::new(primitives.clone());
+        for build_method in [
+            BvhBuildMethod::Octree,
+            BvhBuildMethod::Sah,
+            BvhBuildMethod::Lbvh,
+            BvhBuildMethod::SpatialSplit { alpha: 0.0 },
+        ] {
+            let tracers: [Box<dyn Trace>; 3] = [
+                Box::new(BoundingVolumeHierarchy::new(
+                    build_method,
+                    primitives.clone(),
+                )),
+                Box::new(WideBvh::<1>::new(build_method, primitives.clone())),
+                Box::new(WideBvh::<2>::new(build_method, primitives.clone())),
+            ];
+            for ray in rays() {
+                let expected = brute_force.closest_hit(&ray).map(|hit| hit.distance);
+                for bvh in tracers.iter() {
+                    let found = bvh.closest_hit(&ray).map(|hit| hit.distance);
+                    match (expected, found) {
+                        (Some(expected), Some(found)) => assert!((expected - found).abs() < 1e-4),
+                        (expected, found) => assert_eq!(expected.is_some(), found.is_some()),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lbvh_hits_match_brute_force() {
+        // a small dense torus around the ray origins packs many triangles into few morton cells
+        let mut world = demos::cornell();
+        world.objects.push(Solid::Torus(
+            0.05,
+            0.2,
+            40,
+            80,
+            Transform::translate(0.3, -0.2, 0.1),
+            Material::green(),
+        ));
+        let primitives = world.primitives();
+        let brute_force = BruteForce::new(primitives.clone());
+        let tracers: [Box<dyn Trace>; 3] = [
+            Box::new(BoundingVolumeHierarchy::new(
+                BvhBuildMethod::Lbvh,
+                primitives.clone(),
+            )),
+            Box::new(WideBvh::<1>::new(BvhBuildMethod::Lbvh, primitives.clone())),
+            Box::new(WideBvh::<2>::new(BvhBuildMethod::Lbvh, primitives)),
+        ];
+        for ray in rays() {
+            let expected = brute_force.closest_hit(&ray).map(|hit| hit.distance);
+            for bvh in tracers.iter() {
+                assert_eq!(bvh.closest_hit(&ray).map(|hit| hit.distance), expected);
+                if let Some(distance) = expected {
+                    assert!(bvh.occluded(&ray, distance * 1.01));
+                    assert!(!bvh.occluded(&ray, distance * 0.99));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hits_beyond_the_max_distance_are_ignored() {
+        let bvh = BoundingVolumeHierarchy::new(BvhBuildMethod::Sah, demos::cornell().primitives());
+        for ray in rays() {
+            let Some(hit) = bvh.closest_hit(&ray) else {
+                continue;
+            };
+            let short = Ray::new(ray.origin, ray.direction, hit.distance * 0.5, 1.0);
+            assert!(bvh.closest_hit(&short).is_none());
+        }
+    }
+
+    #[test]
+    fn occlusion_stops_at_the_max_distance() {
+        let primitives = demos::cornell().primitives();
+        let brute_force = BruteForce::new(primitives.clone());
+        let bvh = BoundingVolumeHierarchy::new(BvhBuildMethod::Sah, primitives.clone());
+        let bvh4 = WideBvh::<1>::new(BvhBuildMethod::Sah, primitives.clone());
+        let bvh8 = WideBvh::<2>::new(BvhBuildMethod::Sah, primitives);
+        for ray in rays() {
+            let Some(hit) = bvh.closest_hit(&ray) else {
+                continue;
+            };
+            for tracer in [&bvh as &dyn Trace, &bvh4, &bvh8, &brute_force] {
+                assert!(tracer.occluded(&ray, hit.distance * 1.01));
+                assert!(!tracer.occluded(&ray, hit.distance * 0.99));
+            }
+        }
+    }
+
+    #[test]
+    fn identical_triangles_are_split_in_halves() {
+        let triangle = Triangle::new(
+            Point(-1.0, -1.0, 0.0),
+            Point(1.0, -1.0, 0.0),
+            Point(0.0, 1.0, 0.0),
+            Arc::new(Material::default()),
         );
-        BoundingVolumeHierarchy { root: bvh }
+        let ray = Ray::new(
+            Point(0.0, 0.0, -5.0),
+            Normal(0.0, 0.0, 1.0),
+            Float::INFINITY,
+            1.0,
+        );
+        for build_method in [
+            BvhBuildMethod::Octree,
+            BvhBuildMethod::Sah,
+            BvhBuildMethod::Lbvh,
+            BvhBuildMethod::SpatialSplit { alpha: 0.0 },
+        ] {
+            let bvh = BoundingVolumeHierarchy::new(build_method, vec![triangle.clone(); 100]);
+            let triangles: usize = bvh.packs.iter().map(|pack| pack.triangles.len()).sum();
+            assert_eq!(triangles, 100);
+            assert_eq!(bvh.closest_hit(&ray).map(|hit| hit.distance), Some(5.0));
+        }
+    }
+
+    #[test]
+    fn spatial_splits_reference_triangles_from_several_leaves() {
+        let primitives = demos::cornell().primitives();
+        let count = primitives.len();
+        let references = |alpha| {
+            let build_method = BvhBuildMethod::SpatialSplit { alpha };
+            let bvh = BoundingVolumeHierarchy::new(build_method, primitives.clone());
+            bvh.packs
+                .iter()
+                .map(|pack| pack.triangles.len())
+                .sum::<usize>()
+        };
+        assert!(references(0.0) > count);
+        assert_eq!(references(Float::INFINITY), count);
+    }
+
+    #[test]
+    fn refitted_trees_follow_moved_primitives() {
+        let world = demos::cornell();
+        let mut primitives = world.primitives();
+        let mut bvh = BoundingVolumeHierarchy::new(BvhBuildMethod::Sah, primitives.clone());
+
+        // the sphere is the first solid, moved across the box
+        let sphere = world.objects[0].with_transform(&Transform::combine(&[
+            Transform::scale(5.0, 5.0, 5.0),
+            Transform::translate(-12.0, 3.0, -6.0),
+        ]));
+        let moved = sphere.primitives();
+        primitives.splice(0..moved.len(), moved.clone());
+        assert!(bvh.refit(0, moved, Float::INFINITY));
+
+        let brute_force = BruteForce::new(primitives);
+        for ray in rays() {
+            let expected = brute_force.closest_hit(&ray).map(|hit| hit.distance);
+            let found = bvh.closest_hit(&ray).map(|hit| hit.distance);
+            assert_eq!(expected, found);
+        }
+        assert!(relative_cost(&bvh.nodes) > bvh.cost);
     }
 }
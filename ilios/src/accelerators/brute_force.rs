@@ -1,8 +1,8 @@
 use std::sync::Arc;
 
-use ilios_types::{geometry::Triangle, ray::Ray};
+use ilios_types::{float::Float, geometry::Triangle, ray::Ray};
 
-use crate::{geometry::PackedTriangles, simd, trace::Trace};
+use crate::{closest_primitive::ClosestPrimitive, geometry::PackedTriangles, trace::Trace};
 
 #[derive(Clone, Debug)]
 pub struct BruteForce {
@@ -18,17 +18,7 @@ impl BruteForce {
                 packs.push(pt);
                 pt = PackedTriangles::default();
             }
-            let i = pt.triangles.len();
-            pt.triangles.push(Arc::new(triangle.clone()));
-            pt.origin_x = simd::set(pt.origin_x, triangle.origin.0, i);
-            pt.origin_y = simd::set(pt.origin_y, triangle.origin.1, i);
-            pt.origin_z = simd::set(pt.origin_z, triangle.origin.2, i);
-            pt.edge1_x = simd::set(pt.edge1_x, triangle.edge1.0, i);
-            pt.edge1_y = simd::set(pt.edge1_y, triangle.edge1.1, i);
-            pt.edge1_z = simd::set(pt.edge1_z, triangle.edge1.2, i);
-            pt.edge2_x = simd::set(pt.edge2_x, triangle.edge2.0, i);
-            pt.edge2_y = simd::set(pt.edge2_y, triangle.edge2.1, i);
-            pt.edge2_z = simd::set(pt.edge2_z, triangle.edge2.2, i);
+            pt.push(Arc::new(triangle.clone()));
         }
         packs.push(pt);
         BruteForce {
@@ -38,7 +28,21 @@ impl BruteForce {
 }
 
 impl Trace for BruteForce {
-    fn trace(&self, _ray: &Ray) -> Option<Vec<&PackedTriangles>> {
-        Some(self.packed_triangles.iter().collect())
+    fn closest_hit(&self, ray: &Ray) -> Option<ClosestPrimitive<'_>> {
+        let mut closest = None;
+        let mut max_distance = ray.max_distance;
+        for pack in self.packed_triangles.iter() {
+            if let Some(hit) = pack.closest_hit(ray, max_distance) {
+                max_distance = hit.distance;
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+
+    fn occluded(&self, ray: &Ray, max_distance: Float) -> bool {
+        self.packed_triangles
+            .iter()
+            .any(|pack| pack.hit_within(ray, max_distance))
     }
 }
@@ -0,0 +1,145 @@
+use std::{fs, io, path::Path};
+
+use ilios_types::geometry::{Point, Triangle};
+
+use super::bounding_volume_hierarchy::{BoundingVolumeHierarchy, BvhBuildMethod};
+
+// Files start with the magic and the version of their layout, changing the layout of the
+// tree needs a new version so older files are built again
+const MAGIC: &[u8; 4] = b"IBVH";
+const VERSION: u32 = 1;
+
+// FNV-1a over the bytes written to it
+struct Hasher(u64);
+
+impl Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn point(&mut self, point: &Point) {
+        for value in [point.0, point.1, point.2] {
+            self.write(&value.to_le_bytes());
+        }
+    }
+}
+
+// Hash of what the shape of the tree depends on, the build method and the vertices of the
+// primitives in order. Materials can change without building the tree again
+fn key(build_method: &BvhBuildMethod, primitives: &[Triangle]) -> u64 {
+    let mut hasher = Hasher(0xcbf29ce484222325);
+    hasher.write(format!("{:?}", build_method).as_bytes());
+    hasher.write(&(primitives.len() as u64).to_le_bytes());
+    for triangle in primitives {
+        hasher.point(&triangle.origin);
+        hasher.point(&triangle.pt2);
+        hasher.point(&triangle.pt3);
+        if let Some(motion) = &triangle.motion {
+            hasher.point(&motion.origin);
+            hasher.point(&motion.pt2);
+            hasher.point(&motion.pt3);
+        }
+    }
+    hasher.0
+}
+
+fn load(path: &Path, key: u64, primitives: Vec<Triangle>) -> Option<BoundingVolumeHierarchy> {
+    let bytes = fs::read(path).ok()?;
+    let (magic, bytes) = bytes.split_first_chunk::<4>()?;
+    let (version, bytes) = bytes.split_first_chunk::<4>()?;
+    let (file_key, bytes) = bytes.split_first_chunk::<8>()?;
+    if magic != MAGIC
+        || u32::from_le_bytes(*version) != VERSION
+        || u64::from_le_bytes(*file_key) != key
+    {
+        return None;
+    }
+    BoundingVolumeHierarchy::from_bytes(bytes, primitives)
+}
+
+// Written next to the file and moved over it, readers never see half a tree
+fn save(path: &Path, key: u64, bvh: &BoundingVolumeHierarchy) -> io::Result<()> {
+    let mut bytes = Vec::from(*MAGIC);
+    bytes.extend(VERSION.to_le_bytes());
+    bytes.extend(key.to_le_bytes());
+    bytes.extend(bvh.to_bytes());
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".tmp");
+    fs::write(&partial, bytes)?;
+    fs::rename(&partial, path)
+}
+
+// Tree of the primitives loaded from the cache file when it was built from the same ones
+// with the same method, built and saved to the file otherwise
+pub(super) fn cached(
+    build_method: BvhBuildMethod,
+    primitives: Vec<Triangle>,
+    path: &Path,
+) -> BoundingVolumeHierarchy {
+    let key = key(&build_method, &primitives);
+    if let Some(bvh) = load(path, key, primitives.clone()) {
+        eprintln!("bvh_cache: loaded {}", path.display());
+        return bvh;
+    }
+    let bvh = BoundingVolumeHierarchy::new(build_method, primitives);
+    match save(path, key, &bvh) {
+        Ok(()) => eprintln!("bvh_cache: saved {}", path.display()),
+        Err(error) => eprintln!("Could not save the BVH to {}: {}", path.display(), error),
+    }
+    bvh
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{cached, key, save};
+    use crate::{
+        accelerators::{
+            bounding_volume_hierarchy::{BoundingVolumeHierarchy, BvhBuildMethod},
+            brute_force::BruteForce,
+            tests::rays,
+        },
+        demos,
+        trace::Trace,
+    };
+
+    #[test]
+    fn cached_trees_are_only_loaded_for_their_primitives() {
+        let dir = std::env::temp_dir().join(format!("ilios-bvh-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cornell.bvh");
+        let primitives = demos::cornell().primitives();
+        let build_method = BvhBuildMethod::SpatialSplit { alpha: 0.0 };
+
+        let built = cached(build_method, primitives.clone(), &path);
+        let saved = fs::read(&path).unwrap();
+        let loaded = cached(build_method, primitives.clone(), &path);
+        assert_eq!(fs::read(&path).unwrap(), saved);
+        assert_eq!(loaded.to_bytes(), built.to_bytes());
+
+        let brute_force = BruteForce::new(primitives.clone());
+        for ray in rays() {
+            let expected = brute_force.closest_hit(&ray).map(|hit| hit.distance);
+            assert_eq!(loaded.closest_hit(&ray).map(|hit| hit.distance), expected);
+        }
+
+        // other primitives or build methods replace the file, so do corrupt ones
+        let mut moved = primitives.clone();
+        moved.truncate(primitives.len() - 1);
+        cached(build_method, moved, &path);
+        assert_ne!(fs::read(&path).unwrap(), saved);
+        let bvh = BoundingVolumeHierarchy::new(BvhBuildMethod::Sah, primitives.clone());
+        save(&path, key(&build_method, &primitives), &bvh).unwrap();
+        let mut truncated = fs::read(&path).unwrap();
+        truncated.pop();
+        fs::write(&path, truncated).unwrap();
+        cached(build_method, primitives, &path);
+        assert_eq!(fs::read(&path).unwrap(), saved);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+use std::sync::Arc;
+
+use ilios_types::bounding_box::BoundingBox;
+use ilios_types::float::Float;
+use ilios_types::geometry::Triangle;
+use ilios_types::ray::Ray;
+
+use super::ray_range;
+use crate::closest_primitive::ClosestPrimitive;
+use crate::geometry::PackedTriangles;
+use crate::trace::Trace;
+
+// Cells along the longest axis per cube root of the triangles, about 27 cells per triangle
+// for the grid to fit tightly around each of them
+const CELLS_PER_ROOT: Float = 3.0;
+
+const MAX_RESOLUTION: usize = 128;
+
+// Uniform grid over the scene, every cell references the triangles whose box overlaps it.
+// Rays step through the cells they cross in order with a 3D-DDA
+#[derive(Clone, Debug)]
+pub struct Grid {
+    min: [Float; 3],
+    max: [Float; 3],
+    resolution: [usize; 3],
+    cell_size: [Float; 3],
+    // first pack and packs of each cell, x changes fastest
+    cells: Vec<(u32, u32)>,
+    packs: Vec<PackedTriangles>,
+}
+
+impl Grid {
+    pub fn new(primitives: Vec<Triangle>) -> Grid {
+        // wasm32 has no clock
+        #[cfg(not(target_arch = "wasm32"))]
+        let start = std::time::Instant::now();
+
+        let triangles: Vec<Arc<Triangle>> = primitives.into_iter().map(Arc::new).collect();
+        let bounds: Vec<BoundingBox> = triangles
+            .iter()
+            .map(|triangle| triangle.bounding_box())
+            .collect();
+        let BoundingBox { min, max, .. } = bounds
+            .iter()
+            .fold(BoundingBox::default(), |bb, triangle| bb.combine(triangle));
+        let (min, max) = ([min.0, min.1, min.2], [max.0, max.1, max.2]);
+
+        let extents = [0, 1, 2].map(|axis| (max[axis] - min[axis]).max(0.0));
+        let longest = extents[0].max(extents[1]).max(extents[2]);
+        let cells_per_unit = if longest > 0.0 {
+            CELLS_PER_ROOT * (triangles.len() as Float).cbrt() / longest
+        } else {
+            0.0
+        };
+        let resolution =
+            extents.map(|extent| ((extent * cells_per_unit) as usize).clamp(1, MAX_RESOLUTION));
+        let cell_size = [0, 1, 2].map(|axis| extents[axis] / resolution[axis] as Float);
+
+        let mut grid = Grid {
+            min,
+            max,
+            resolution,
+            cell_size,
+            cells: vec![],
+            packs: vec![],
+        };
+        if triangles.is_empty() {
+            return grid;
+        }
+
+        // cells covered by the box of each triangle, counted first to lay out the references
+        let ranges: Vec<([usize; 3], [usize; 3])> = bounds
+            .iter()
+            .map(|BoundingBox { min, max, .. }| {
+                let first = grid.cell(&[min.0, min.1, min.2]);
+                let last = grid.cell(&[max.0, max.1, max.2]);
+                (
+                    first.map(|value| value as usize),
+                    last.map(|value| value as usize),
+                )
+            })
+            .collect();
+        let cell_count = resolution[0] * resolution[1] * resolution[2];
+        let mut offsets = vec![0; cell_count + 1];
+        let cells_of = |&(first, last): &([usize; 3], [usize; 3])| {
+            (first[2]..=last[2]).flat_map(move |z| {
+                (first[1]..=last[1]).flat_map(move |y| {
+                    (first[0]..=last[0]).map(move |x| (z * resolution[1] + y) * resolution[0] + x)
+                })
+            })
+        };
+        for range in ranges.iter() {
+            for cell in cells_of(range) {
+                offsets[cell + 1] += 1;
+            }
+        }
+        for cell in 0..cell_count {
+            offsets[cell + 1] += offsets[cell];
+        }
+        let mut references = vec![0; offsets[cell_count]];
+        let mut filled = offsets.clone();
+        for (idx, range) in ranges.iter().enumerate() {
+            for cell in cells_of(range) {
+                references[filled[cell]] = idx;
+                filled[cell] += 1;
+            }
+        }
+
+        grid.cells = Vec::with_capacity(cell_count);
+        for cell in 0..cell_count {
+            let first = grid.packs.len();
+            for chunk in references[offsets[cell]..offsets[cell + 1]].chunks(4) {
+                let chunk: Vec<Arc<Triangle>> =
+                    chunk.iter().map(|&idx| triangles[idx].clone()).collect();
+                grid.packs.push(PackedTriangles::new(&chunk));
+            }
+            grid.cells
+                .push((first as u32, (grid.packs.len() - first) as u32));
+        }
+
+        eprintln!(
+            "total_triangles: {}\ngrid_resolution: {}x{}x{}\ngrid_references: {}",
+            triangles.len(),
+            resolution[0],
+            resolution[1],
+            resolution[2],
+            references.len()
+        );
+        #[cfg(not(target_arch = "wasm32"))]
+        eprintln!(
+            "grid_build_time: {}ms",
+            start.elapsed().as_secs_f32() * 1000.0
+        );
+        grid
+    }
+
+    // Cell containing the point, points outside the grid are moved to its closest cell
+    fn cell(&self, point: &[Float; 3]) -> [isize; 3] {
+        [0, 1, 2].map(|axis| {
+            if self.cell_size[axis] <= 0.0 {
+                return 0;
+            }
+            let cell = ((point[axis] - self.min[axis]) / self.cell_size[axis]) as isize;
+            cell.clamp(0, self.resolution[axis] as isize - 1)
+        })
+    }
+
+    // Visits the cells along the ray in order until the cell function returns true, the
+    // function can shorten the ray to skip the cells behind a hit
+    fn walk<'a>(
+        &'a self,
+        ray: &Ray,
+        max_distance: &mut Float,
+        mut visit: impl FnMut(&'a [PackedTriangles], &mut Float) -> bool,
+    ) {
+        if self.cells.is_empty() {
+            return;
+        }
+        let Some((near, _)) = ray_range(&self.min, &self.max, ray, *max_distance) else {
+            return;
+        };
+        let origin = [ray.origin.0, ray.origin.1, ray.origin.2];
+        let direction = [ray.direction.0, ray.direction.1, ray.direction.2];
+        let reciprocal = ray.direction_reciprocal;
+        let entry = [0, 1, 2].map(|axis| origin[axis] + direction[axis] * near);
+        let mut cell = self.cell(&entry);
+
+        // distance to the next cell boundary on each axis, how far apart they are and the
+        // cell past the last one the ray goes into
+        let mut next = [Float::INFINITY; 3];
+        let mut delta = [Float::INFINITY; 3];
+        let mut step = [0; 3];
+        let mut out = [0; 3];
+        for axis in 0..3 {
+            let boundary = |cell: isize| self.min[axis] + cell as Float * self.cell_size[axis];
+            if direction[axis] > 0.0 {
+                next[axis] = near + (boundary(cell[axis] + 1) - entry[axis]) * reciprocal[axis];
+                delta[axis] = self.cell_size[axis] * reciprocal[axis];
+                step[axis] = 1;
+                out[axis] = self.resolution[axis] as isize;
+            } else if direction[axis] < 0.0 {
+                next[axis] = near + (boundary(cell[axis]) - entry[axis]) * reciprocal[axis];
+                delta[axis] = -self.cell_size[axis] * reciprocal[axis];
+                step[axis] = -1;
+                out[axis] = -1;
+            }
+        }
+
+        loop {
+            let idx = (cell[2] as usize * self.resolution[1] + cell[1] as usize)
+                * self.resolution[0]
+                + cell[0] as usize;
+            let (first, count) = self.cells[idx];
+            if count > 0 {
+                let first = first as usize;
+                if visit(&self.packs[first..first + count as usize], max_distance) {
+                    return;
+                }
+            }
+
+            let axis = if next[0] < next[1] && next[0] < next[2] {
+                0
+            } else if next[1] < next[2] {
+                1
+            } else {
+                2
+            };
+            if next[axis] > *max_distance || next[axis].is_infinite() {
+                return;
+            }
+            cell[axis] += step[axis];
+            if cell[axis] == out[axis] {
+                return;
+            }
+            next[axis] += delta[axis];
+        }
+    }
+}
+
+impl Trace for Grid {
+    fn closest_hit(&self, ray: &Ray) -> Option<ClosestPrimitive<'_>> {
+        let mut closest = None;
+        let mut max_distance = ray.max_distance;
+        self.walk(ray, &mut max_distance, |packs, max_distance| {
+            for pack in packs {
+                if let Some(hit) = pack.closest_hit(ray, *max_distance) {
+                    *max_distance = hit.distance;
+                    closest = Some(hit);
+                }
+            }
+            false
+        });
+        closest
+    }
+
+    fn occluded(&self, ray: &Ray, max_distance: Float) -> bool {
+        let mut occluded = false;
+        let mut max_distance = max_distance;
+        self.walk(ray, &mut max_distance, |packs, max_distance| {
+            occluded = packs.iter().any(|pack| pack.hit_within(ray, *max_distance));
+            occluded
+        });
+        occluded
+    }
+}
@@ -0,0 +1,349 @@
+use std::sync::Arc;
+
+use ilios_types::bounding_box::BoundingBox;
+use ilios_types::float::Float;
+use ilios_types::geometry::{Point, Triangle};
+use ilios_types::ray::Ray;
+
+use super::ray_range;
+use crate::closest_primitive::ClosestPrimitive;
+use crate::geometry::PackedTriangles;
+use crate::trace::Trace;
+
+// Enough for the max depth of trees with billions of triangles
+const STACK_SIZE: usize = 64;
+
+// Cost of intersecting a triangle relative to visiting a node
+const INTERSECTION_COST: Float = 20.0;
+
+// Splits leaving a side empty cost less, rays going through the empty side skip it
+const EMPTY_BONUS: Float = 0.2;
+
+// Planes evaluated on each axis
+const BINS: usize = 32;
+
+// Nodes with this many triangles or fewer become leaves
+const MAX_LEAF_SIZE: usize = 4;
+
+// Splits costing more than their leaf allowed along a path, later splits may pay them off
+const BAD_REFINES: usize = 3;
+
+const LEAF: u8 = 3;
+
+// SAH k-d tree, triangles crossing a plane are referenced from both of its sides. Nodes are
+// stored depth first like the BVH nodes, leaves point into an array of packed triangles
+#[derive(Clone, Debug)]
+pub struct KdTree {
+    min: [Float; 3],
+    max: [Float; 3],
+    nodes: Vec<KdNode>,
+    packs: Vec<PackedTriangles>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct KdNode {
+    // position of the plane of interior nodes
+    split: Float,
+    // second child of interior nodes, the first one follows them. First pack of leaves
+    offset: u32,
+    // packs of a leaf
+    count: u32,
+    // axis of the plane, LEAF for leaves
+    axis: u8,
+}
+
+struct Builder {
+    triangles: Vec<Arc<Triangle>>,
+    bounds: Vec<BoundingBox>,
+    nodes: Vec<KdNode>,
+    packs: Vec<PackedTriangles>,
+    max_depth: usize,
+    // figures of the tree built so far
+    depth: usize,
+    leaves: usize,
+    references: usize,
+}
+
+fn coordinates(bounding_box: &BoundingBox) -> ([Float; 3], [Float; 3]) {
+    let BoundingBox { min, max, .. } = bounding_box;
+    ([min.0, min.1, min.2], [max.0, max.1, max.2])
+}
+
+impl Builder {
+    fn leaf(&mut self, indices: &[u32]) {
+        let first = self.packs.len();
+        for chunk in indices.chunks(4) {
+            let triangles: Vec<Arc<Triangle>> = chunk
+                .iter()
+                .map(|&idx| self.triangles[idx as usize].clone())
+                .collect();
+            self.packs.push(PackedTriangles::new(&triangles));
+        }
+        self.nodes.push(KdNode {
+            split: 0.0,
+            offset: first as u32,
+            count: (self.packs.len() - first) as u32,
+            axis: LEAF,
+        });
+        self.leaves += 1;
+        self.references += indices.len();
+    }
+
+    // Plane with the lowest cost among the bins of every axis, returns its axis, position
+    // and cost
+    fn split(&self, indices: &[u32], bounding_box: &BoundingBox) -> Option<(usize, Float, Float)> {
+        let (min, max) = coordinates(bounding_box);
+        let area = bounding_box.surface_area();
+        if area <= 0.0 {
+            return None;
+        }
+        let mut best: Option<(usize, Float, Float)> = None;
+        for axis in 0..3 {
+            let extent = max[axis] - min[axis];
+            if extent <= 0.0 {
+                continue;
+            }
+            // triangles starting and ending in each bin
+            let bin_size = extent / BINS as Float;
+            let bin = |value: Float| {
+                ((value - min[axis]) / bin_size).clamp(0.0, (BINS - 1) as Float) as usize
+            };
+            let mut starts = [0; BINS];
+            let mut ends = [0; BINS];
+            for &idx in indices {
+                let (start, end) = coordinates(&self.bounds[idx as usize]);
+                starts[bin(start[axis])] += 1;
+                ends[bin(end[axis])] += 1;
+            }
+
+            let (other, another) = ((axis + 1) % 3, (axis + 2) % 3);
+            let side = max[other] - min[other];
+            let depth = max[another] - min[another];
+            let mut below = 0;
+            let mut above = indices.len();
+            for boundary in 1..BINS {
+                below += starts[boundary - 1];
+                above -= ends[boundary - 1];
+                let position = min[axis] + bin_size * boundary as Float;
+                let below_extent = position - min[axis];
+                let above_extent = max[axis] - position;
+                let below_area = 2.0 * (side * depth + (side + depth) * below_extent);
+                let above_area = 2.0 * (side * depth + (side + depth) * above_extent);
+                let bonus = if below == 0 || above == 0 {
+                    EMPTY_BONUS
+                } else {
+                    0.0
+                };
+                let cost = 1.0
+                    + INTERSECTION_COST
+                        * (1.0 - bonus)
+                        * (below_area * below as Float + above_area * above as Float)
+                        / area;
+                if best.is_none_or(|(.., min_cost)| cost < min_cost) {
+                    best = Some((axis, position, cost));
+                }
+            }
+        }
+        best
+    }
+
+    // Appends the node and the nodes below it depth first
+    fn build(
+        &mut self,
+        indices: Vec<u32>,
+        bounding_box: BoundingBox,
+        depth: usize,
+        bad_refines: usize,
+    ) {
+        self.depth = self.depth.max(depth + 1);
+        if indices.len() <= MAX_LEAF_SIZE || depth == self.max_depth {
+            return self.leaf(&indices);
+        }
+        let Some((axis, position, cost)) = self.split(&indices, &bounding_box) else {
+            return self.leaf(&indices);
+        };
+        let leaf_cost = INTERSECTION_COST * indices.len() as Float;
+        let bad_refines = if cost > leaf_cost {
+            bad_refines + 1
+        } else {
+            bad_refines
+        };
+        if (cost > 4.0 * leaf_cost && indices.len() < 16) || bad_refines == BAD_REFINES {
+            return self.leaf(&indices);
+        }
+
+        // triangles lying on the plane go below it
+        let (mut below, mut above) = (vec![], vec![]);
+        for &idx in indices.iter() {
+            let (start, end) = coordinates(&self.bounds[idx as usize]);
+            if start[axis] < position || end[axis] <= position {
+                below.push(idx);
+            }
+            if end[axis] > position {
+                above.push(idx);
+            }
+        }
+        drop(indices);
+
+        let (min, max) = coordinates(&bounding_box);
+        let (mut below_max, mut above_min) = (max, min);
+        below_max[axis] = position;
+        above_min[axis] = position;
+        let point = |value: [Float; 3]| Point(value[0], value[1], value[2]);
+        let below_box = BoundingBox::new(bounding_box.min, point(below_max));
+        let above_box = BoundingBox::new(point(above_min), bounding_box.max);
+
+        let idx = self.nodes.len();
+        self.nodes.push(KdNode {
+            split: position,
+            offset: 0,
+            count: 0,
+            axis: axis as u8,
+        });
+        self.build(below, below_box, depth + 1, bad_refines);
+        self.nodes[idx].offset = self.nodes.len() as u32;
+        self.build(above, above_box, depth + 1, bad_refines);
+    }
+}
+
+impl KdTree {
+    pub fn new(primitives: Vec<Triangle>) -> KdTree {
+        // wasm32 has no clock
+        #[cfg(not(target_arch = "wasm32"))]
+        let start = std::time::Instant::now();
+
+        let triangles: Vec<Arc<Triangle>> = primitives.into_iter().map(Arc::new).collect();
+        let bounds: Vec<BoundingBox> = triangles
+            .iter()
+            .map(|triangle| triangle.bounding_box())
+            .collect();
+        let bounding_box = bounds
+            .iter()
+            .fold(BoundingBox::default(), |bb, triangle| bb.combine(triangle));
+        let count = triangles.len();
+        let mut builder = Builder {
+            triangles,
+            bounds,
+            nodes: vec![],
+            packs: vec![],
+            max_depth: (8.0 + 1.3 * (count.max(1) as Float).log2()).min(STACK_SIZE as Float)
+                as usize,
+            depth: 0,
+            leaves: 0,
+            references: 0,
+        };
+        if count > 0 {
+            builder.build((0..count as u32).collect(), bounding_box, 0, 0);
+            eprintln!(
+                "total_triangles: {}\nkd_nodes: {}\nkd_leaves: {}\nkd_references: {}\nkd_depth: {}",
+                count,
+                builder.nodes.len(),
+                builder.leaves,
+                builder.references,
+                builder.depth
+            );
+            #[cfg(not(target_arch = "wasm32"))]
+            eprintln!(
+                "kd_build_time: {}ms",
+                start.elapsed().as_secs_f32() * 1000.0
+            );
+        }
+
+        let (min, max) = coordinates(&bounding_box);
+        KdTree {
+            min,
+            max,
+            nodes: builder.nodes,
+            packs: builder.packs,
+        }
+    }
+
+    // Visits the leaves along the ray front to back until the leaf function returns true,
+    // the function can shorten the ray to skip the leaves behind a hit
+    fn walk<'a>(
+        &'a self,
+        ray: &Ray,
+        max_distance: &mut Float,
+        mut leaf: impl FnMut(&'a [PackedTriangles], &mut Float) -> bool,
+    ) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let Some((mut near, mut far)) = ray_range(&self.min, &self.max, ray, *max_distance) else {
+            return;
+        };
+        let origin = [ray.origin.0, ray.origin.1, ray.origin.2];
+        let reciprocal = ray.direction_reciprocal;
+        let mut stack = [(0, 0.0, 0.0); STACK_SIZE];
+        let mut top = 0;
+        let mut idx = 0;
+        loop {
+            if near <= *max_distance {
+                let node = &self.nodes[idx];
+                if node.axis == LEAF {
+                    let first = node.offset as usize;
+                    if leaf(
+                        &self.packs[first..first + node.count as usize],
+                        max_distance,
+                    ) {
+                        return;
+                    }
+                } else {
+                    let axis = node.axis as usize;
+                    let plane = (node.split - origin[axis]) * reciprocal[axis];
+                    let below_first = origin[axis] < node.split
+                        || (origin[axis] == node.split && reciprocal[axis] <= 0.0);
+                    let (first, second) = if below_first {
+                        (idx + 1, node.offset as usize)
+                    } else {
+                        (node.offset as usize, idx + 1)
+                    };
+                    // rays within the plane only visit the side it belongs to
+                    if plane > far || plane <= 0.0 || plane.is_nan() {
+                        idx = first;
+                    } else if plane < near {
+                        idx = second;
+                    } else {
+                        stack[top] = (second, plane, far);
+                        top += 1;
+                        idx = first;
+                        far = plane;
+                    }
+                    continue;
+                }
+            }
+            if top == 0 {
+                return;
+            }
+            top -= 1;
+            (idx, near, far) = stack[top];
+        }
+    }
+}
+
+impl Trace for KdTree {
+    fn closest_hit(&self, ray: &Ray) -> Option<ClosestPrimitive<'_>> {
+        let mut closest = None;
+        let mut max_distance = ray.max_distance;
+        self.walk(ray, &mut max_distance, |packs, max_distance| {
+            for pack in packs {
+                if let Some(hit) = pack.closest_hit(ray, *max_distance) {
+                    *max_distance = hit.distance;
+                    closest = Some(hit);
+                }
+            }
+            false
+        });
+        closest
+    }
+
+    fn occluded(&self, ray: &Ray, max_distance: Float) -> bool {
+        let mut occluded = false;
+        let mut max_distance = max_distance;
+        self.walk(ray, &mut max_distance, |packs, max_distance| {
+            occluded = packs.iter().any(|pack| pack.hit_within(ray, *max_distance));
+            occluded
+        });
+        occluded
+    }
+}
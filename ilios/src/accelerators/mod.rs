@@ -1,15 +1,30 @@
+use std::path::Path;
+
 pub use bounding_volume_hierarchy::BvhBuildMethod;
-use ilios_types::{geometry::Triangle, ray::Ray};
+use ilios_types::{
+    float::Float, geometry::Triangle, ray::Ray, transform::Transform, world::MeshInstance,
+};
+pub use wide_bvh::BvhWidth;
 
-use self::{bounding_volume_hierarchy::BoundingVolumeHierarchy, brute_force::BruteForce};
-use super::{geometry::PackedTriangles, trace::Trace};
+use self::{
+    bounding_volume_hierarchy::BoundingVolumeHierarchy, brute_force::BruteForce, grid::Grid,
+    kd_tree::KdTree, two_level::TwoLevelBvh, wide_bvh::WideBvh,
+};
+use super::{closest_primitive::ClosestPrimitive, trace::Trace};
 mod bounding_volume_hierarchy;
 mod brute_force;
+mod bvh_cache;
+mod grid;
+mod kd_tree;
+mod two_level;
+mod wide_bvh;
 
 #[derive(Clone, Debug)]
 pub enum Accelerator {
     BruteForce,
     BoundingVolumeHierarchy,
+    KdTree,
+    Grid,
 }
 
 #[derive(Clone, Debug)]
@@ -17,6 +32,31 @@ pub enum AcceleratorInstance {
     None,
     BruteForce(BruteForce),
     BoundingVolumeHierarchy(BoundingVolumeHierarchy),
+    Bvh4(WideBvh<1>),
+    Bvh8(WideBvh<2>),
+    TwoLevel(TwoLevelBvh),
+    KdTree(KdTree),
+    Grid(Grid),
+}
+
+// Distances where the ray enters and leaves the box, limited to the part of the ray before
+// the max distance
+fn ray_range(
+    min: &[Float; 3],
+    max: &[Float; 3],
+    ray: &Ray,
+    max_distance: Float,
+) -> Option<(Float, Float)> {
+    let origin = [ray.origin.0, ray.origin.1, ray.origin.2];
+    let mut near: Float = 0.0;
+    let mut far = max_distance;
+    for axis in 0..3 {
+        let t1 = (min[axis] - origin[axis]) * ray.direction_reciprocal[axis];
+        let t2 = (max[axis] - origin[axis]) * ray.direction_reciprocal[axis];
+        near = near.max(t1.min(t2));
+        far = far.min(t1.max(t2));
+    }
+    (near <= far).then_some((near, far))
 }
 
 impl AcceleratorInstance {
@@ -25,17 +65,163 @@ impl AcceleratorInstance {
         AcceleratorInstance::BruteForce(tracer)
     }
     pub fn new_bounding_volume_hierarchy(
+        build_method: BvhBuildMethod,
+        width: BvhWidth,
+        primitives: Vec<Triangle>,
+    ) -> AcceleratorInstance {
+        match width {
+            BvhWidth::Two => AcceleratorInstance::BoundingVolumeHierarchy(
+                BoundingVolumeHierarchy::new(build_method, primitives),
+            ),
+            BvhWidth::Four => AcceleratorInstance::Bvh4(WideBvh::new(build_method, primitives)),
+            BvhWidth::Eight => AcceleratorInstance::Bvh8(WideBvh::new(build_method, primitives)),
+        }
+    }
+    // Binary BVH loaded from the cache file when it holds the tree of the same primitives built
+    // with the same method, built and saved to the file otherwise
+    pub fn new_cached_bounding_volume_hierarchy(
         build_method: BvhBuildMethod,
         primitives: Vec<Triangle>,
+        path: &Path,
     ) -> AcceleratorInstance {
-        let tracer = BoundingVolumeHierarchy::new(build_method, primitives);
-        AcceleratorInstance::BoundingVolumeHierarchy(tracer)
+        AcceleratorInstance::BoundingVolumeHierarchy(bvh_cache::cached(
+            build_method,
+            primitives,
+            path,
+        ))
+    }
+    pub fn new_two_level(
+        build_method: BvhBuildMethod,
+        primitives: Vec<Triangle>,
+        instances: Vec<MeshInstance>,
+    ) -> AcceleratorInstance {
+        AcceleratorInstance::TwoLevel(TwoLevelBvh::new(build_method, primitives, instances))
+    }
+    pub fn new_kd_tree(primitives: Vec<Triangle>) -> AcceleratorInstance {
+        AcceleratorInstance::KdTree(KdTree::new(primitives))
+    }
+    pub fn new_grid(primitives: Vec<Triangle>) -> AcceleratorInstance {
+        AcceleratorInstance::Grid(Grid::new(primitives))
+    }
+    // Updates the accelerator to the primitives replaced from the first one on and the instances
+    // moved to new transforms, without building it again. Returns false when the accelerator
+    // cannot refit, or degraded past the threshold times its cost as built, and needs building
+    pub fn refit(
+        &mut self,
+        first: usize,
+        primitives: Vec<Triangle>,
+        moved: Vec<(usize, Transform)>,
+        threshold: Float,
+    ) -> bool {
+        match self {
+            AcceleratorInstance::BoundingVolumeHierarchy(tracer) if moved.is_empty() => {
+                tracer.refit(first, primitives, threshold)
+            }
+            AcceleratorInstance::TwoLevel(tracer) => {
+                tracer.refit(first, primitives, moved, threshold)
+            }
+            _ => false,
+        }
     }
-    pub fn trace(&self, ray: &Ray) -> Option<Vec<&PackedTriangles>> {
+    pub fn closest_hit(&self, ray: &Ray) -> Option<ClosestPrimitive<'_>> {
         match self {
-            AcceleratorInstance::BruteForce(tracer) => tracer.trace(ray),
-            AcceleratorInstance::BoundingVolumeHierarchy(tracer) => tracer.trace(ray),
+            AcceleratorInstance::BruteForce(tracer) => tracer.closest_hit(ray),
+            AcceleratorInstance::BoundingVolumeHierarchy(tracer) => tracer.closest_hit(ray),
+            AcceleratorInstance::Bvh4(tracer) => tracer.closest_hit(ray),
+            AcceleratorInstance::Bvh8(tracer) => tracer.closest_hit(ray),
+            AcceleratorInstance::TwoLevel(tracer) => tracer.closest_hit(ray),
+            AcceleratorInstance::KdTree(tracer) => tracer.closest_hit(ray),
+            AcceleratorInstance::Grid(tracer) => tracer.closest_hit(ray),
             AcceleratorInstance::None => None,
         }
     }
+    pub fn occluded(&self, ray: &Ray, max_distance: Float) -> bool {
+        match self {
+            AcceleratorInstance::BruteForce(tracer) => tracer.occluded(ray, max_distance),
+            AcceleratorInstance::BoundingVolumeHierarchy(tracer) => {
+                tracer.occluded(ray, max_distance)
+            }
+            AcceleratorInstance::Bvh4(tracer) => tracer.occluded(ray, max_distance),
+            AcceleratorInstance::Bvh8(tracer) => tracer.occluded(ray, max_distance),
+            AcceleratorInstance::TwoLevel(tracer) => tracer.occluded(ray, max_distance),
+            AcceleratorInstance::KdTree(tracer) => tracer.occluded(ray, max_distance),
+            AcceleratorInstance::Grid(tracer) => tracer.occluded(ray, max_distance),
+            AcceleratorInstance::None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ilios_types::{
+        float::Float,
+        geometry::{Point, Vector},
+        ray::Ray,
+    };
+
+    use super::{AcceleratorInstance, brute_force::BruteForce, grid::Grid, kd_tree::KdTree};
+    use crate::{demos, trace::Trace};
+
+    // Rays from a few points inside the cornell box in directions all around them
+    pub(super) fn rays() -> Vec<Ray> {
+        let mut rays = vec![];
+        for origin in [
+            Point(0.0, 0.0, 0.0),
+            Point(-6.0, 4.0, 5.0),
+            Point(5.0, -4.0, -8.0),
+        ] {
+            for theta in 0..12 {
+                for phi in 0..24 {
+                    let theta = (theta as Float + 0.5) / 12.0 * std::f32::consts::PI;
+                    let phi = phi as Float / 24.0 * 2.0 * std::f32::consts::PI;
+                    let direction = Vector(
+                        theta.sin() * phi.cos(),
+                        theta.cos(),
+                        theta.sin() * phi.sin(),
+                    );
+                    rays.push(Ray::new(origin, direction.unit(), Float::INFINITY, 1.0));
+                }
+            }
+        }
+        rays
+    }
+
+    #[test]
+    fn kd_tree_and_grid_match_brute_force() {
+        let primitives = demos::cornell().primitives();
+        let brute_force = BruteForce::new(primitives.clone());
+        let kd_tree = KdTree::new(primitives.clone());
+        let grid = Grid::new(primitives);
+        for ray in rays() {
+            let expected = brute_force.closest_hit(&ray).map(|hit| hit.distance);
+            for tracer in [&kd_tree as &dyn Trace, &grid] {
+                let found = tracer.closest_hit(&ray).map(|hit| hit.distance);
+                match (expected, found) {
+                    (Some(expected), Some(found)) => assert!((expected - found).abs() < 1e-4),
+                    (expected, found) => assert_eq!(expected.is_some(), found.is_some()),
+                }
+                if let Some(distance) = expected {
+                    assert!(tracer.occluded(&ray, distance * 1.01));
+                    assert!(!tracer.occluded(&ray, distance * 0.99));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_scenes_have_no_hits() {
+        let ray = Ray::new(
+            Point(0.0, 0.0, 0.0),
+            Vector(0.0, 0.0, 1.0).unit(),
+            Float::INFINITY,
+            1.0,
+        );
+        for accelerator in [
+            AcceleratorInstance::new_kd_tree(vec![]),
+            AcceleratorInstance::new_grid(vec![]),
+        ] {
+            assert!(accelerator.closest_hit(&ray).is_none());
+            assert!(!accelerator.occluded(&ray, Float::INFINITY));
+        }
+    }
 }
@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ilios_types::bounding_box::BoundingBox;
+use ilios_types::float::Float;
+use ilios_types::geometry::{Normal, Point, Triangle};
+use ilios_types::ray::Ray;
+use ilios_types::transform::Transform;
+use ilios_types::world::MeshInstance;
+
+use super::bounding_volume_hierarchy::{
+    BoundingVolumeHierarchy, BvhBuildMethod, FlatNode, refit_nodes, relative_cost,
+};
+use crate::closest_primitive::ClosestPrimitive;
+use crate::trace::Trace;
+
+// Enough for the depth of a top level tree over billions of instances
+const STACK_SIZE: usize = 64;
+
+// A BVH per shared mesh, built once in the space of the mesh, and a top level BVH over the
+// instances placing them in the world. Rays reaching an instance are moved into the space of
+// its mesh to go down the mesh BVH. The primitives that are not instanced get a BVH of their
+// own, placed where they are
+#[derive(Clone, Debug)]
+pub struct TwoLevelBvh {
+    meshes: Vec<BoundingVolumeHierarchy>,
+    // in the order of the top level leaves, each leaf holds one of them
+    instances: Vec<Instance>,
+    nodes: Vec<FlatNode>,
+    // where each of the instances the tree was built with ended up, none for the ones left out
+    slots: Vec<Option<usize>>,
+    // where the primitives that are not instanced ended up
+    direct: Option<usize>,
+    // relative cost of the top level as built
+    cost: Float,
+}
+
+#[derive(Clone, Debug)]
+struct Instance {
+    // position among the instances the tree was built with, none for the primitives that are
+    // not instanced
+    source: Option<usize>,
+    mesh: usize,
+    // world to mesh space, none for the primitives that are not instanced
+    to_mesh: Option<Transform>,
+    // mesh to world space for the normals
+    normals: Option<Transform>,
+    bounding_box: BoundingBox,
+}
+
+impl Instance {
+    // The ray in the space of the mesh. Its direction is left as long as the transform makes
+    // it, so distances along it are the same as along the world ray
+    fn ray(&self, ray: &Ray, max_distance: Float) -> Ray {
+        let (origin, direction) = match &self.to_mesh {
+            Some(transform) => {
+                let direction = transform.apply_vector(&ray.direction.into());
+                (
+                    transform.apply(&ray.origin),
+                    Normal(direction.0, direction.1, direction.2),
+                )
+            }
+            None => (ray.origin, ray.direction),
+        };
+        Ray::new(origin, direction, max_distance, ray.refraction_index).with_time(ray.time)
+    }
+}
+
+// Box around the mesh box once placed in the world
+fn placed_bounds(bounds: &BoundingBox, transform: &Transform) -> BoundingBox {
+    let BoundingBox { min, max, .. } = bounds;
+    let mut placed = BoundingBox::default();
+    for corner in 0..8 {
+        let point = transform.apply(&Point(
+            if corner & 1 == 0 { min.0 } else { max.0 },
+            if corner & 2 == 0 { min.1 } else { max.1 },
+            if corner & 4 == 0 { min.2 } else { max.2 },
+        ));
+        placed = placed.combine(&BoundingBox::new(point, point));
+    }
+    placed
+}
+
+// Appends the node over the instances and the nodes below it depth first, splitting them at
+// the median of their centroids along the axis they spread the most. Offset is the position
+// of the first instance, returns the depth of the subtree
+fn flatten(instances: &mut [Instance], offset: usize, nodes: &mut Vec<FlatNode>) -> usize {
+    let bounding_box = instances
+        .iter()
+        .fold(BoundingBox::default(), |bb, instance| {
+            bb.combine(&instance.bounding_box)
+        });
+    if instances.len() == 1 {
+        nodes.push(FlatNode::new(&bounding_box, offset, 1, 0));
+        return 1;
+    }
+
+    let centroids = instances
+        .iter()
+        .fold(BoundingBox::default(), |bb, instance| {
+            let centroid = instance.bounding_box.centroid;
+            bb.combine(&BoundingBox::new(centroid, centroid))
+        });
+    let extent = &centroids.max - &centroids.min;
+    let axis = if extent.0 >= extent.1 && extent.0 >= extent.2 {
+        0
+    } else if extent.1 >= extent.2 {
+        1
+    } else {
+        2
+    };
+    let coordinate = |instance: &Instance| {
+        let Point(x, y, z) = instance.bounding_box.centroid;
+        [x, y, z][axis]
+    };
+    let half = instances.len() / 2;
+    instances.select_nth_unstable_by(half, |a, b| coordinate(a).total_cmp(&coordinate(b)));
+    let (left, right) = instances.split_at_mut(half);
+
+    let idx = nodes.len();
+    nodes.push(FlatNode::new(&bounding_box, 0, 0, axis));
+    let left_depth = flatten(left, offset, nodes);
+    nodes[idx].offset = nodes.len() as u32;
+    let right_depth = flatten(right, offset + half, nodes);
+    1 + left_depth.max(right_depth)
+}
+
+impl TwoLevelBvh {
+    pub fn new(
+        build_method: BvhBuildMethod,
+        primitives: Vec<Triangle>,
+        instances: Vec<MeshInstance>,
+    ) -> TwoLevelBvh {
+        let mut meshes = vec![];
+        let mut placed = vec![];
+        if !primitives.is_empty() {
+            let bvh = BoundingVolumeHierarchy::new(build_method, primitives);
+            if let Some(bounding_box) = bvh.bounds() {
+                placed.push(Instance {
+                    source: None,
+                    mesh: 0,
+                    to_mesh: None,
+                    normals: None,
+                    bounding_box,
+                });
+            }
+            meshes.push(bvh);
+        }
+
+        // instances sharing a mesh share its BVH too
+        let mut ids: HashMap<*const Vec<Triangle>, usize> = HashMap::new();
+        let instance_count = instances.len();
+        for (source, (transform, triangles)) in instances.into_iter().enumerate() {
+            // transforms flattening the mesh leave nothing to hit
+            let (Some(to_mesh), Some(normals)) =
+                (transform.inverse(), transform.normal_transform())
+            else {
+                continue;
+            };
+            let mesh = *ids.entry(Arc::as_ptr(&triangles)).or_insert_with(|| {
+                meshes.push(BoundingVolumeHierarchy::new(
+                    build_method,
+                    triangles.as_ref().clone(),
+                ));
+                meshes.len() - 1
+            });
+            if let Some(bounds) = meshes[mesh].bounds() {
+                placed.push(Instance {
+                    source: Some(source),
+                    mesh,
+                    to_mesh: Some(to_mesh),
+                    normals: Some(normals),
+                    bounding_box: placed_bounds(&bounds, &transform),
+                });
+            }
+        }
+
+        let mut nodes = vec![];
+        if !placed.is_empty() {
+            let depth = flatten(&mut placed, 0, &mut nodes);
+            eprintln!(
+                "total_instances: {}\ninstanced_meshes: {}\ntop_level_nodes: {}\ntop_level_depth: {}",
+                instance_count,
+                ids.len(),
+                nodes.len(),
+                depth
+            );
+        }
+        let mut slots = vec![None; instance_count];
+        let mut direct = None;
+        for (slot, instance) in placed.iter().enumerate() {
+            match instance.source {
+                Some(source) => slots[source] = Some(slot),
+                None => direct = Some(slot),
+            }
+        }
+        let cost = relative_cost(&nodes);
+        TwoLevelBvh {
+            meshes,
+            instances: placed,
+            nodes,
+            slots,
+            direct,
+            cost,
+        }
+    }
+
+    // Refits the tree of the primitives that are not instanced to the ones replaced from the
+    // first one on, places the moved instances with their new transforms and fits the top level
+    // around them. Returns false when either level degrades past the threshold or the changes
+    // need a tree built again, like instances that were left out or that get flattened
+    pub(super) fn refit(
+        &mut self,
+        first: usize,
+        primitives: Vec<Triangle>,
+        moved: Vec<(usize, Transform)>,
+        threshold: Float,
+    ) -> bool {
+        if !primitives.is_empty() {
+            let Some(direct) = self.direct else {
+                return false;
+            };
+            let mesh = self.instances[direct].mesh;
+            if !self.meshes[mesh].refit(first, primitives, threshold) {
+                return false;
+            }
+            match self.meshes[mesh].bounds() {
+                Some(bounding_box) => self.instances[direct].bounding_box = bounding_box,
+                None => return false,
+            }
+        }
+        for (source, transform) in moved {
+            let Some(&Some(slot)) = self.slots.get(source) else {
+                return false;
+            };
+            let (Some(to_mesh), Some(normals)) =
+                (transform.inverse(), transform.normal_transform())
+            else {
+                return false;
+            };
+            let instance = &mut self.instances[slot];
+            let Some(bounds) = self.meshes[instance.mesh].bounds() else {
+                return false;
+            };
+            instance.to_mesh = Some(to_mesh);
+            instance.normals = Some(normals);
+            instance.bounding_box = placed_bounds(&bounds, &transform);
+        }
+
+        let instances = &self.instances;
+        refit_nodes(&mut self.nodes, |node| {
+            Some(instances[node.offset as usize].bounding_box)
+        });
+        relative_cost(&self.nodes) <= threshold * self.cost
+    }
+
+    // Visits the instances along the ray front to back until the instance function returns
+    // true, the function can shorten the ray to skip the instances behind a hit
+    fn walk<'a>(
+        &'a self,
+        ray: &Ray,
+        max_distance: &mut Float,
+        mut visit: impl FnMut(&'a Instance, &mut Float) -> bool,
+    ) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let origin = [ray.origin.0, ray.origin.1, ray.origin.2];
+        let reciprocal = &ray.direction_reciprocal;
+        let mut stack = [0; STACK_SIZE];
+        let mut top = 0;
+        let mut idx = 0;
+        loop {
+            let node = &self.nodes[idx];
+            if node.intersect(&origin, reciprocal, *max_distance) {
+                if node.count > 0 {
+                    if visit(&self.instances[node.offset as usize], max_distance) {
+                        return;
+                    }
+                } else {
+                    let (near, far) = if reciprocal[node.axis as usize] < 0.0 {
+                        (node.offset as usize, idx + 1)
+                    } else {
+                        (idx + 1, node.offset as usize)
+                    };
+                    stack[top] = far;
+                    top += 1;
+                    idx = near;
+                    continue;
+                }
+            }
+            if top == 0 {
+                return;
+            }
+            top -= 1;
+            idx = stack[top];
+        }
+    }
+}
+
+impl Trace for TwoLevelBvh {
+    fn closest_hit(&self, ray: &Ray) -> Option<ClosestPrimitive<'_>> {
+        let mut closest = None;
+        let mut max_distance = ray.max_distance;
+        self.walk(ray, &mut max_distance, |instance, max_distance| {
+            let mesh_ray = instance.ray(ray, *max_distance);
+            if let Some(hit) = self.meshes[instance.mesh].closest_hit(&mesh_ray) {
+                *max_distance = hit.distance;
+                closest = Some(ClosestPrimitive {
+                    instance: instance.normals.as_ref(),
+                    ..hit
+                });
+            }
+            false
+        });
+        closest
+    }
+
+    fn occluded(&self, ray: &Ray, max_distance: Float) -> bool {
+        let mut max_distance = max_distance;
+        let mut occluded = false;
+        self.walk(ray, &mut max_distance, |instance, max_distance| {
+            let mesh_ray = instance.ray(ray, *max_distance);
+            occluded = self.meshes[instance.mesh].occluded(&mesh_ray, *max_distance);
+            occluded
+        });
+        occluded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ilios_types::{material::Material, solids::Solid, transform::Transform};
+
+    use super::{BvhBuildMethod, TwoLevelBvh};
+    use crate::{
+        accelerators::{brute_force::BruteForce, tests::rays},
+        demos,
+        trace::Trace,
+    };
+
+    #[test]
+    fn instances_match_their_copies() {
+        let mesh = Arc::new(
+            Solid::Torus(
+                1.5,
+                0.5,
+                16,
+                8,
+                Transform::scale(1.0, 1.0, 1.0),
+                Material::red(),
+            )
+            .primitives(),
+        );
+        // mirrored and stretched ones included
+        let transforms = [
+            Transform::translate(0.5, 0.3, 5.2),
+            Transform::combine(&[
+                Transform::rotate(0.4, 1.0, 0.0),
+                Transform::translate(-4.0, 2.0, 0.0),
+            ]),
+            Transform::combine(&[
+                Transform::scale(-1.0, 2.0, 0.5),
+                Transform::translate(3.0, -3.0, -2.0),
+            ]),
+            Transform::combine(&[
+                Transform::scale(0.5, 0.5, 0.5),
+                Transform::translate(0.0, 6.0, -6.0),
+            ]),
+        ];
+        let world = demos::cornell();
+        let mut copies = world.primitives();
+        let primitives = copies.clone();
+        let mut instances = vec![];
+        for transform in transforms {
+            copies.extend(Solid::Instance(transform.clone(), mesh.clone()).primitives());
+            instances.push((transform, mesh.clone()));
+        }
+
+        let brute_force = BruteForce::new(copies);
+        let two_level = TwoLevelBvh::new(BvhBuildMethod::Sah, primitives, instances);
+        assert_eq!(two_level.meshes.len(), 2);
+        for ray in rays() {
+            let expected = brute_force.closest_hit(&ray);
+            let found = two_level.closest_hit(&ray);
+            assert_eq!(expected.is_some(), found.is_some());
+            if let (Some(expected), Some(found)) = (expected, found) {
+                assert!((expected.distance - found.distance).abs() < 1e-3);
+                let expected_normal = expected.normal_at(0.0);
+                let found_normal = found.normal_at(0.0);
+                assert!(expected_normal.dot(&found_normal.into()) > 0.999);
+                assert!(two_level.occluded(&ray, expected.distance * 1.01));
+                assert!(!two_level.occluded(&ray, expected.distance * 0.99));
+            }
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use ilios_types::float::Float;
+use ilios_types::geometry::Triangle;
+use ilios_types::ray::Ray;
+
+use super::bounding_volume_hierarchy::{self, BvhBuildMethod, BvhElement};
+use crate::closest_primitive::ClosestPrimitive;
+use crate::geometry::PackedTriangles;
+use crate::simd::{self, F32x4};
+use crate::trace::Trace;
+
+// Children per BVH node, wider nodes test their children together with SIMD box tests
+#[derive(Clone, Copy, Debug, Default)]
+pub enum BvhWidth {
+    #[default]
+    Two,
+    Four,
+    Eight,
+}
+
+// Traversals needing a deeper stack allocate it
+const STACK_SIZE: usize = 128;
+
+// BVH with up to four children per group of SIMD lanes in every node, collapsed from the
+// binary tree. A BVH4 has one group and a BVH8 two, all the children of a group are tested
+// at once
+#[derive(Clone, Debug)]
+pub struct WideBvh<const GROUPS: usize> {
+    root: Option<Child>,
+    nodes: Vec<WideNode<GROUPS>>,
+    packs: Vec<PackedTriangles>,
+    // entries the traversal stack can hold at most
+    stack_size: usize,
+}
+
+// A node when count is zero, otherwise a leaf with count packs starting at index
+#[derive(Clone, Copy, Debug, Default)]
+struct Child {
+    index: u32,
+    count: u32,
+}
+
+#[derive(Clone, Debug)]
+struct WideNode<const GROUPS: usize> {
+    // bounds of the children as [min, max][axis][group], empty slots have inverted bounds so
+    // rays never hit them
+    bounds: [[[F32x4; GROUPS]; 3]; 2],
+    children: [[Child; 4]; GROUPS],
+}
+
+impl<const GROUPS: usize> Default for WideNode<GROUPS> {
+    fn default() -> WideNode<GROUPS> {
+        WideNode {
+            bounds: [
+                [[simd::splat(Float::MAX); GROUPS]; 3],
+                [[simd::splat(Float::MIN); GROUPS]; 3],
+            ],
+            children: [[Child::default(); 4]; GROUPS],
+        }
+    }
+}
+
+// Ray values splatted across the lanes once per traversal
+struct RayLanes {
+    origin: [F32x4; 3],
+    reciprocal: [F32x4; 3],
+    // bound each axis is entered through, max when the ray goes down the axis
+    near: [usize; 3],
+}
+
+impl RayLanes {
+    fn new(ray: &Ray) -> RayLanes {
+        let reciprocal = ray.direction_reciprocal;
+        RayLanes {
+            origin: [
+                simd::splat(ray.origin.0),
+                simd::splat(ray.origin.1),
+                simd::splat(ray.origin.2),
+            ],
+            reciprocal: reciprocal.map(simd::splat),
+            near: reciprocal.map(|value| if value < 0.0 { 1 } else { 0 }),
+        }
+    }
+}
+
+impl<const GROUPS: usize> WideNode<GROUPS> {
+    // Slab test of all the children, returns their entry distances and a bit per group lane
+    // for the children hit before the max distance
+    fn intersect(&self, ray: &RayLanes, max_distance: Float) -> ([F32x4; GROUPS], [u32; GROUPS]) {
+        let mut entries = [simd::default(); GROUPS];
+        let mut hits = [0; GROUPS];
+        let max_distance = simd::splat(max_distance);
+        for group in 0..GROUPS {
+            let distance = |bound: usize, axis: usize| {
+                simd::mul(
+                    simd::sub(self.bounds[bound][axis][group], ray.origin[axis]),
+                    ray.reciprocal[axis],
+                )
+            };
+            let near = |axis: usize| distance(ray.near[axis], axis);
+            let far = |axis: usize| distance(1 - ray.near[axis], axis);
+            let entry = simd::max(
+                simd::max(near(0), near(1)),
+                simd::max(near(2), simd::default()),
+            );
+            let exit = simd::min(simd::min(far(0), far(1)), simd::min(far(2), max_distance));
+            entries[group] = entry;
+            hits[group] = simd::bitmask(simd::lte(entry, exit));
+        }
+        (entries, hits)
+    }
+}
+
+// Appends the element and the nodes below it, returns the child pointing to it and the depth
+// of its subtree. Each node opens the binary nodes with the largest surface area until its
+// slots are full
+fn collapse<const GROUPS: usize>(
+    element: BvhElement,
+    nodes: &mut Vec<WideNode<GROUPS>>,
+    packs: &mut Vec<PackedTriangles>,
+) -> Option<(Child, usize)> {
+    match element {
+        BvhElement::Empty => None,
+        BvhElement::Leaf {
+            packed_primitives, ..
+        } => {
+            packs.push(*packed_primitives);
+            let child = Child {
+                index: packs.len() as u32 - 1,
+                count: 1,
+            };
+            Some((child, 0))
+        }
+        BvhElement::Node { left, right, .. } => {
+            let mut slots = vec![*left, *right];
+            while slots.len() < 4 * GROUPS {
+                let largest = slots
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, slot)| matches!(slot, BvhElement::Node { .. }))
+                    .map(|(idx, slot)| {
+                        let area = bounding_volume_hierarchy::bounding_box(slot)
+                            .map_or(0.0, |bounding_box| bounding_box.surface_area());
+                        (idx, area)
+                    })
+                    .max_by(|(_, a), (_, b)| a.total_cmp(b));
+                let Some((idx, _)) = largest else {
+                    break;
+                };
+                if let BvhElement::Node { left, right, .. } = slots.swap_remove(idx) {
+                    slots.push(*left);
+                    slots.push(*right);
+                }
+            }
+            slots.retain(|slot| !matches!(slot, BvhElement::Empty));
+
+            let idx = nodes.len();
+            nodes.push(WideNode::default());
+            let mut depth = 0;
+            for (slot, element) in slots.into_iter().enumerate() {
+                let Some(bounding_box) = bounding_volume_hierarchy::bounding_box(&element).copied()
+                else {
+                    continue;
+                };
+                let Some((child, child_depth)) = collapse(element, nodes, packs) else {
+                    continue;
+                };
+                depth = depth.max(child_depth);
+                let (group, lane) = (slot / 4, slot % 4);
+                let node = &mut nodes[idx];
+                let min = [bounding_box.min.0, bounding_box.min.1, bounding_box.min.2];
+                let max = [bounding_box.max.0, bounding_box.max.1, bounding_box.max.2];
+                for axis in 0..3 {
+                    let bounds = &mut node.bounds;
+                    bounds[0][axis][group] = simd::set(bounds[0][axis][group], min[axis], lane);
+                    bounds[1][axis][group] = simd::set(bounds[1][axis][group], max[axis], lane);
+                }
+                node.children[group][lane] = child;
+            }
+            let child = Child {
+                index: idx as u32,
+                count: 0,
+            };
+            Some((child, depth + 1))
+        }
+    }
+}
+
+impl<const GROUPS: usize> WideBvh<GROUPS> {
+    pub fn new(build_method: BvhBuildMethod, primitives: Vec<Triangle>) -> WideBvh<GROUPS> {
+        let (bvh, stats) = bounding_volume_hierarchy::build(build_method, primitives);
+
+        let mut nodes = vec![];
+        let mut packs = Vec::with_capacity(stats.leaves);
+        let root = collapse(bvh, &mut nodes, &mut packs);
+        if !nodes.is_empty() {
+            eprintln!("wide_nodes: {} ({} wide)", nodes.len(), 4 * GROUPS);
+        }
+        // every node visited leaves at most all its children but one on the stack
+        let depth = root.map_or(0, |(_, depth)| depth);
+        WideBvh {
+            root: root.map(|(child, _)| child),
+            nodes,
+            packs,
+            stack_size: depth * (4 * GROUPS - 1) + 1,
+        }
+    }
+
+    fn with_stack<T>(&self, traversal: impl FnOnce(&mut [(Child, Float)]) -> T) -> T {
+        let empty = (Child::default(), 0.0);
+        if self.stack_size <= STACK_SIZE {
+            traversal(&mut [empty; STACK_SIZE])
+        } else {
+            traversal(&mut vec![empty; self.stack_size])
+        }
+    }
+
+    fn leaf(&self, child: Child) -> &[PackedTriangles] {
+        let first = child.index as usize;
+        &self.packs[first..first + child.count as usize]
+    }
+
+    // Pushes the children hit in the stack, the ones entered first end up on top
+    fn traverse(&self, ray: &Ray, stack: &mut [(Child, Float)]) -> Option<ClosestPrimitive<'_>> {
+        let root = self.root?;
+        let lanes = RayLanes::new(ray);
+        let mut closest = None;
+        let mut max_distance = ray.max_distance;
+        stack[0] = (root, 0.0);
+        let mut top = 1;
+        while top > 0 {
+            top -= 1;
+            let (child, entry) = stack[top];
+            if entry > max_distance {
+                continue;
+            }
+            if child.count > 0 {
+                for pack in self.leaf(child) {
+                    if let Some(hit) = pack.closest_hit(ray, max_distance) {
+                        max_distance = hit.distance;
+                        closest = Some(hit);
+                    }
+                }
+                continue;
+            }
+
+            let node = &self.nodes[child.index as usize];
+            let (entries, hits) = node.intersect(&lanes, max_distance);
+            let first = top;
+            for group in 0..GROUPS {
+                let mut bits = hits[group];
+                while bits != 0 {
+                    let lane = bits.trailing_zeros() as usize;
+                    bits &= bits - 1;
+                    let entry = simd::get(entries[group], lane);
+                    // insertion sort, farthest child at the bottom
+                    let mut idx = top;
+                    while idx > first && stack[idx - 1].1 < entry {
+                        stack[idx] = stack[idx - 1];
+                        idx -= 1;
+                    }
+                    stack[idx] = (node.children[group][lane], entry);
+                    top += 1;
+                }
+            }
+        }
+        closest
+    }
+
+    fn any_hit(&self, ray: &Ray, max_distance: Float, stack: &mut [(Child, Float)]) -> bool {
+        let Some(root) = self.root else {
+            return false;
+        };
+        let lanes = RayLanes::new(ray);
+        stack[0] = (root, 0.0);
+        let mut top = 1;
+        while top > 0 {
+            top -= 1;
+            let (child, _) = stack[top];
+            if child.count > 0 {
+                if self
+                    .leaf(child)
+                    .iter()
+                    .any(|pack| pack.hit_within(ray, max_distance))
+                {
+                    return true;
+                }
+                continue;
+            }
+
+            let node = &self.nodes[child.index as usize];
+            let (_, hits) = node.intersect(&lanes, max_distance);
+            for (children, mut bits) in node.children.iter().zip(hits) {
+                while bits != 0 {
+                    let lane = bits.trailing_zeros() as usize;
+                    bits &= bits - 1;
+                    stack[top] = (children[lane], 0.0);
+                    top += 1;
+                }
+            }
+        }
+        false
+    }
+}
+
+impl<const GROUPS: usize> Trace for WideBvh<GROUPS> {
+    fn closest_hit(&self, ray: &Ray) -> Option<ClosestPrimitive<'_>> {
+        self.with_stack(|stack| self.traverse(ray, stack))
+    }
+
+    fn occluded(&self, ray: &Ray, max_distance: Float) -> bool {
+        self.with_stack(|stack| self.any_hit(ray, max_distance, stack))
+    }
+}
@@ -0,0 +1,165 @@
+use ilios_types::{
+    color::{self, Color},
+    float::Float,
+    section::Section,
+};
+
+use crate::{Renderer, filter, render_method::SampleFn};
+
+// Pixels are grouped in square tiles of this size to decide where to keep sampling
+const TILE_SIZE: u32 = 8;
+
+// Keeps sampling the pixels whose estimate is still noisy, from a minimum up to a maximum
+// number of samples per pixel
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveSampling {
+    pub min_samples: u32,
+    pub max_samples: u32,
+    // relative standard error of the pixel luminance under which a pixel is done
+    pub threshold: Float,
+}
+
+impl AdaptiveSampling {
+    pub fn new(min_samples: u32, max_samples: u32, threshold: Float) -> AdaptiveSampling {
+        AdaptiveSampling {
+            min_samples: min_samples.max(2),
+            max_samples: max_samples.max(min_samples.max(2)),
+            threshold,
+        }
+    }
+}
+
+// Running estimate of a pixel, luminance statistics use Welford's algorithm
+#[derive(Clone, Copy, Debug, Default)]
+struct PixelEstimate {
+    color: Color,
+    weight: Float,
+    samples: u32,
+    mean: Float,
+    m2: Float,
+}
+
+impl PixelEstimate {
+    fn add(&mut self, color: Color, weight: Float) {
+        self.color = self.color + color * weight;
+        self.weight += weight;
+        self.samples += 1;
+        let luminance = color.luminance();
+        let delta = luminance - self.mean;
+        self.mean += delta / self.samples as Float;
+        self.m2 += delta * (luminance - self.mean);
+    }
+
+    // Standard error of the mean luminance relative to the mean
+    fn error(&self) -> Float {
+        if self.samples < 2 {
+            return Float::INFINITY;
+        }
+        let n = self.samples as Float;
+        let standard_error = (self.m2 / (n - 1.0) / n).sqrt();
+        standard_error / self.mean.max(1e-3)
+    }
+
+    fn color(&self) -> Color {
+        if self.weight != 0.0 {
+            self.color / self.weight
+        } else {
+            color::BLACK
+        }
+    }
+}
+
+// Renders the section in passes, the first takes the minimum samples on every pixel and the
+// following ones add the same amount to every pixel of the tiles that still have a pixel above
+// the error threshold. Deciding per tile keeps pixels whose first samples all missed the light
+// from stopping early. Returns the pixels and the samples taken by each one
+pub(crate) fn render(
+    renderer: &Renderer,
+    section: &Section,
+    adaptive: &AdaptiveSampling,
+    sample: SampleFn,
+) -> (Vec<Color>, Vec<u32>) {
+    let AdaptiveSampling {
+        min_samples,
+        max_samples,
+        threshold,
+    } = *adaptive;
+    let tiles_h = section.width.div_ceil(TILE_SIZE);
+    let tiles_v = section.height.div_ceil(TILE_SIZE);
+    let tile_of = |x: u32, y: u32| ((y / TILE_SIZE) * tiles_h + x / TILE_SIZE) as usize;
+
+    let mut estimates = vec![PixelEstimate::default(); (section.width * section.height) as usize];
+    let mut active = vec![true; (tiles_h * tiles_v) as usize];
+    let mut total_samples = 0;
+
+    while active.iter().any(|tile| *tile) && !renderer.control.is_cancelled() {
+        let previous = &estimates;
+        let active_tiles = &active;
+        let pass = renderer
+            .render_method
+            .map_pixels(renderer, section, |rng, pixel| {
+                let (x, y) = (pixel.0 - section.left, pixel.1 - section.top);
+                let mut estimate = previous[(y * section.width + x) as usize];
+                if !active_tiles[tile_of(x, y)] || estimate.samples >= max_samples {
+                    return estimate;
+                }
+                let count = min_samples.min(max_samples - estimate.samples);
+                for index in estimate.samples..estimate.samples + count {
+                    let (color, weight) =
+                        filter::filtered_sample(renderer, rng, pixel, index, sample);
+                    estimate.add(color, weight);
+                }
+                estimate
+            });
+        if renderer.control.is_cancelled() {
+            // cancelled jobs return empty estimates, keep the previous pass for their pixels
+            for (kept, fresh) in estimates.iter_mut().zip(pass) {
+                if fresh.samples > kept.samples {
+                    *kept = fresh;
+                }
+            }
+            break;
+        }
+        estimates = pass;
+
+        // stop once a pass adds nothing
+        let taken: u32 = estimates.iter().map(|estimate| estimate.samples).sum();
+        if taken == total_samples {
+            break;
+        }
+        total_samples = taken;
+
+        active.fill(false);
+        for (idx, estimate) in estimates.iter().enumerate() {
+            let (x, y) = (idx as u32 % section.width, idx as u32 / section.width);
+            if estimate.samples < max_samples && estimate.error() > threshold {
+                active[tile_of(x, y)] = true;
+            }
+        }
+    }
+
+    let pixels = estimates.iter().map(PixelEstimate::color).collect();
+    let samples = estimates.iter().map(|estimate| estimate.samples).collect();
+    (pixels, samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use ilios_types::color::Color;
+
+    use super::PixelEstimate;
+
+    #[test]
+    fn constant_pixels_converge() {
+        let mut flat = PixelEstimate::default();
+        let mut noisy = PixelEstimate::default();
+        for idx in 0..8 {
+            flat.add(Color(0.5, 0.5, 0.5), 1.0);
+            let value = if idx % 2 == 0 { 0.0 } else { 1.0 };
+            noisy.add(Color(value, value, value), 1.0);
+        }
+        assert_eq!(flat.error(), 0.0);
+        assert!(noisy.error() > 0.1);
+        assert!((noisy.color().0 - 0.5).abs() < 1e-6);
+    }
+}
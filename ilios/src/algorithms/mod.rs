@@ -1,8 +1,48 @@
+use ilios_types::{
+    color::Color,
+    float::Float,
+    geometry::{Normal, Point},
+    light_source::LightSource,
+    ray::Ray,
+};
+
+use crate::Renderer;
+
 pub mod path_tracing;
 pub mod whitted;
 
+// Shadow rays start this far from the surface so it does not shadow itself
+const SHADOW_BIAS: Float = 0.0001;
+
 #[derive(Clone, Debug)]
 pub enum Algorithm {
     Whitted,
     PathTracing,
 }
+
+// Light reaching a point from the point lights it can see
+fn direct_lighting(renderer: &Renderer, point: &Point, normal: &Normal, time: Float) -> Color {
+    let incident_lights = renderer.world.lights.iter().filter_map(|ll| {
+        let LightSource::Point(light, intensity) = ll;
+        let direction = light - point;
+        let unit_dir = direction.unit();
+        let dot = normal.dot(&unit_dir.into());
+        if dot <= 0.0 {
+            return None;
+        }
+
+        let light_distance = direction.norm();
+        let origin = *point + unit_dir * SHADOW_BIAS;
+        let ray = Ray::new(origin, unit_dir, light_distance, 1.0).with_time(time);
+        if renderer
+            .accelerator
+            .occluded(&ray, light_distance - SHADOW_BIAS)
+        {
+            return None;
+        }
+        let it = Float::max(0.0, (intensity - light_distance) / intensity);
+        Some(Color(1.0, 1.0, 1.0) * dot * it)
+    });
+
+    incident_lights.fold(Color(0.0, 0.0, 0.0), |acc, col| acc + col)
+}
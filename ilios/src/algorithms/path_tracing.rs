@@ -8,7 +8,7 @@ use ilios_types::{
 
 use crate::{
     Renderer,
-    {closest_primitive::ClosestPrimitive, geometry::PackedTriangles, rng::Rng, simd},
+    {closest_primitive::ClosestPrimitive, filter, rng::Rng},
 };
 const MAX_DEPTH: u8 = 5;
 
@@ -17,53 +17,50 @@ fn trace_ray_internal(renderer: &Renderer, rng: &mut dyn Rng, ray: &Ray, depth:
         return color::BLACK;
     }
     let accelerator = &renderer.accelerator;
-    match accelerator.trace(ray) {
-        Some(prms) => {
-            let closest = find_closest_primitive(&prms, ray);
-            match closest {
-                Some(ClosestPrimitive {
-                    primitive,
-                    distance,
-                }) => {
-                    let point = ray.point(distance);
-                    let prm_material = &primitive.material;
+    match accelerator.closest_hit(ray) {
+        Some(hit) => {
+            let ClosestPrimitive {
+                primitive,
+                distance,
+                ..
+            } = hit;
+            let point = ray.point(distance);
+            let prm_material = &primitive.material;
 
-                    match prm_material.as_ref() {
-                        Material::Diffuse(color) => {
-                            let normal: Vector = primitive.normal().into();
-                            let new_dir = random_dome(rng, &normal);
-                            let path_ray = Ray::new(point, new_dir.unit(), Float::INFINITY, 1.0);
-                            *color * trace_ray_internal(renderer, rng, &path_ray, depth + 1)
-                        }
-                        Material::Reflective(_, idx) => {
-                            let normal: Vector = primitive.normal().into();
-                            let ri: Vector = ray.direction.into();
-                            let dot = ri.dot(&normal) * 2.0;
-                            let new_dir = ri - (normal * dot);
-                            let reflected_ray =
-                                Ray::new(point, new_dir.unit(), Float::INFINITY, 1.0);
-                            trace_ray_internal(renderer, rng, &reflected_ray, depth + 1) * *idx
-                        }
-                        Material::Emissive(color) => *color,
-                        Material::Refractive => {
-                            let previous_index = ray.refraction_index;
-                            let next_index = 1.52;
-                            let mut normal: Vector = primitive.normal().into();
-                            let n = previous_index / next_index;
-                            let dot = normal.dot(&ray.direction.into());
-                            let ta = n * n * (1.0 - (dot * dot));
-                            if previous_index == next_index {
-                                normal = normal * -1.0;
-                            }
-                            let new_dir = (ray.direction * n) - normal * (n + (1.0 - ta).sqrt());
-
-                            let refracted_ray =
-                                Ray::new(point, new_dir.unit(), Float::INFINITY, 1.52);
-                            trace_ray_internal(renderer, rng, &refracted_ray, depth + 1)
-                        }
+            match prm_material.as_ref() {
+                Material::Diffuse(color) => {
+                    let normal: Vector = hit.normal_at(ray.time).into();
+                    let new_dir = random_dome(rng, &normal);
+                    let path_ray =
+                        Ray::new(point, new_dir.unit(), Float::INFINITY, 1.0).with_time(ray.time);
+                    *color * trace_ray_internal(renderer, rng, &path_ray, depth + 1)
+                }
+                Material::Reflective(_, idx) => {
+                    let normal: Vector = hit.normal_at(ray.time).into();
+                    let ri: Vector = ray.direction.into();
+                    let dot = ri.dot(&normal) * 2.0;
+                    let new_dir = ri - (normal * dot);
+                    let reflected_ray =
+                        Ray::new(point, new_dir.unit(), Float::INFINITY, 1.0).with_time(ray.time);
+                    trace_ray_internal(renderer, rng, &reflected_ray, depth + 1) * *idx
+                }
+                Material::Emissive(color) => *color,
+                Material::Refractive => {
+                    let previous_index = ray.refraction_index;
+                    let next_index = 1.52;
+                    let mut normal: Vector = hit.normal_at(ray.time).into();
+                    let n = previous_index / next_index;
+                    let dot = normal.dot(&ray.direction.into());
+                    let ta = n * n * (1.0 - (dot * dot));
+                    if previous_index == next_index {
+                        normal = normal * -1.0;
                     }
+                    let new_dir = (ray.direction * n) - normal * (n + (1.0 - ta).sqrt());
+
+                    let refracted_ray =
+                        Ray::new(point, new_dir.unit(), Float::INFINITY, 1.52).with_time(ray.time);
+                    trace_ray_internal(renderer, rng, &refracted_ray, depth + 1)
                 }
-                None => color::BLACK,
             }
         }
         None => color::BLACK,
@@ -71,50 +68,15 @@ fn trace_ray_internal(renderer: &Renderer, rng: &mut dyn Rng, ray: &Ray, depth:
 }
 
 pub fn trace_ray(renderer: &Renderer, rng: &mut dyn Rng, pixel: (u32, u32)) -> Color {
-    let mut final_color = color::BLACK;
-    let samples = renderer.samples;
-    let (x, y) = pixel;
-    for _ in 0..samples {
-        // let (nx, ny) = rng.gen::<(Float, Float)>();
-        let nx = rng.r#gen();
-        let ny = rng.r#gen();
-        let ray = renderer.camera.get_ray(x as Float + nx, y as Float + ny);
-        let sample_color = trace_ray_internal(renderer, rng, &ray, 1);
-        final_color = final_color + sample_color
-    }
-    final_color / (samples as Float)
+    filter::filter_pixel(renderer, rng, pixel, trace_sample)
 }
 
-fn find_closest_primitive<'a>(
-    primitives: &[&'a PackedTriangles],
-    ray: &Ray,
-) -> Option<ClosestPrimitive<'a>> {
-    primitives
-        .iter()
-        .filter_map(|primitive| {
-            primitive.intersect(&ray).map(|distances| {
-                let mut closest_distance = f32::MAX;
-                let mut closest_idx = 0;
-
-                for idx in 0..4 {
-                    let distance = simd::get(distances, idx);
-                    if distance > 0.0 && distance < closest_distance {
-                        closest_distance = distance;
-                        closest_idx = idx;
-                    }
-                }
-
-                ClosestPrimitive {
-                    primitive: primitive.triangles[closest_idx].as_ref(),
-                    distance: closest_distance,
-                }
-            })
-        })
-        .fold(None, |closest, next| match closest {
-            None => Some(next),
-            Some(current) if next.distance < current.distance => Some(next),
-            _ => closest,
-        })
+// Traces a single camera sample at a position on the image plane, in pixels
+pub fn trace_sample(renderer: &Renderer, rng: &mut dyn Rng, position: (Float, Float)) -> Color {
+    let (x, y) = position;
+    let time = renderer.camera.shutter.sample(rng.r#gen());
+    let ray = renderer.camera.get_ray_at(x, y, time);
+    trace_ray_internal(renderer, rng, &ray, 1)
 }
 
 fn rotate_vector(vector: &Vector, axis: &Vector, angle: Float) -> Vector {
@@ -1,7 +1,24 @@
-use ilios_types::{float::Float, geometry::Triangle};
+use ilios_types::{
+    float::Float,
+    geometry::{Normal, Triangle},
+    transform::Transform,
+};
 
 #[derive(Debug)]
 pub struct ClosestPrimitive<'a> {
     pub primitive: &'a Triangle,
     pub distance: Float,
+    // transform for the normals of instanced primitives, which are stored in mesh space
+    pub instance: Option<&'a Transform>,
+}
+
+impl ClosestPrimitive<'_> {
+    // Normal of the primitive in world space
+    pub fn normal_at(&self, time: Float) -> Normal {
+        let normal = self.primitive.normal_at(time);
+        match self.instance {
+            Some(transform) => transform.apply_vector(&normal.into()).unit(),
+            None => normal,
+        }
+    }
 }
@@ -127,6 +127,7 @@ pub fn simple() -> World {
         Point(-800.0, -7.0, -800.0),
         Point(0.0, -7.0, 800.0),
         Point(800.0, -7.0, -800.0),
+        Transform::default(),
         Arc::new(Material::Diffuse(Color(1.0, 1.0, 1.0))),
     );
     world_builder.add_solid(simple_triangle);
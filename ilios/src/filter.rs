@@ -0,0 +1,322 @@
+use ilios_types::{
+    color::{self, Color},
+    float::{Float, PI},
+    section::Section,
+};
+use rayon::prelude::*;
+
+use crate::{Renderer, render_method::SampleFn, rng::Rng};
+
+// Resolution of the tables used to importance sample a filter
+const SAMPLER_BINS: usize = 64;
+
+// Pixel reconstruction filters, the radius is measured in pixels from the pixel center and
+// must be positive
+#[derive(Clone, Copy, Debug)]
+pub enum Filter {
+    Box { radius: Float },
+    Tent { radius: Float },
+    Gaussian { radius: Float, sigma: Float },
+    Mitchell { radius: Float, b: Float, c: Float },
+    Lanczos { radius: Float },
+}
+
+impl Default for Filter {
+    // averages the samples within the pixel
+    fn default() -> Filter {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+impl Filter {
+    pub fn box_filter(radius: Float) -> Filter {
+        Filter::Box { radius }
+    }
+
+    pub fn tent(radius: Float) -> Filter {
+        Filter::Tent { radius }
+    }
+
+    pub fn gaussian(radius: Float) -> Filter {
+        Filter::Gaussian {
+            radius,
+            sigma: radius / 3.0,
+        }
+    }
+
+    // B = C = 1/3, the values recommended by Mitchell and Netravali
+    pub fn mitchell(radius: Float) -> Filter {
+        Filter::Mitchell {
+            radius,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        }
+    }
+
+    pub fn lanczos(radius: Float) -> Filter {
+        Filter::Lanczos { radius }
+    }
+
+    pub fn radius(&self) -> Float {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius } => radius,
+        }
+    }
+
+    // Weight of a sample at the given offset from the pixel center
+    pub fn evaluate(&self, x: Float, y: Float) -> Float {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: Float) -> Float {
+        let x = x.abs();
+        if x > self.radius() {
+            return 0.0;
+        }
+        match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Tent { radius } => radius - x,
+            Filter::Gaussian { radius, sigma } => {
+                let gaussian = |x: Float| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            Filter::Mitchell { radius, b, c } => {
+                let x = 2.0 * x / radius;
+                if x > 1.0 {
+                    ((-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                } else {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                }
+            }
+            Filter::Lanczos { radius } => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+fn sinc(x: Float) -> Float {
+    if x < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub enum FilterMethod {
+    // offsets each sample proportionally to the filter, the pixel keeps its own samples
+    #[default]
+    ImportanceSampling,
+    // every sample contributes to all the pixels within the filter radius
+    Splatting,
+}
+
+// Draws offsets distributed like the absolute value of a filter, using a tabulated cdf
+#[derive(Clone, Debug)]
+pub(crate) struct FilterSampler {
+    radius: Float,
+    values: Vec<Float>,
+    cdf: Vec<Float>,
+}
+
+impl FilterSampler {
+    pub fn new(filter: &Filter) -> FilterSampler {
+        let radius = filter.radius();
+        let bin_width = 2.0 * radius / SAMPLER_BINS as Float;
+        let values: Vec<Float> = (0..SAMPLER_BINS)
+            .map(|bin| filter.evaluate_1d(-radius + (bin as Float + 0.5) * bin_width))
+            .collect();
+        let mut cdf = Vec::with_capacity(SAMPLER_BINS + 1);
+        cdf.push(0.0);
+        for value in values.iter() {
+            cdf.push(cdf[cdf.len() - 1] + value.abs());
+        }
+        let total = cdf[SAMPLER_BINS];
+        for value in cdf.iter_mut() {
+            *value /= total;
+        }
+        FilterSampler {
+            radius,
+            values,
+            cdf,
+        }
+    }
+
+    // Maps a random number in [0, 1) to an offset from the pixel center and the sign of its weight
+    pub fn sample(&self, random: Float) -> (Float, Float) {
+        let bin = self
+            .cdf
+            .partition_point(|value| *value <= random)
+            .clamp(1, SAMPLER_BINS)
+            - 1;
+        let width = self.cdf[bin + 1] - self.cdf[bin];
+        let within = if width > 0.0 {
+            (random - self.cdf[bin]) / width
+        } else {
+            0.5
+        };
+        let bin_width = 2.0 * self.radius / SAMPLER_BINS as Float;
+        let offset = -self.radius + (bin as Float + within) * bin_width;
+        (offset, self.values[bin].signum())
+    }
+}
+
+// Filtered estimate of a pixel, taking the configured number of samples around its center
+pub(crate) fn filter_pixel(
+    renderer: &Renderer,
+    rng: &mut dyn Rng,
+    pixel: (u32, u32),
+    sample: SampleFn,
+) -> Color {
+    let mut color = color::BLACK;
+    let mut total_weight = 0.0;
+    for index in 0..renderer.samples {
+        let (sample_color, weight) = filtered_sample(renderer, rng, pixel, index, sample);
+        color = color + sample_color * weight;
+        total_weight += weight;
+    }
+    if total_weight != 0.0 {
+        color / total_weight
+    } else {
+        color::BLACK
+    }
+}
+
+// Takes one sample of a pixel, offset from its center by importance sampling the filter,
+// returns the sample color and its weight
+pub(crate) fn filtered_sample(
+    renderer: &Renderer,
+    rng: &mut dyn Rng,
+    pixel: (u32, u32),
+    index: u32,
+    sample: SampleFn,
+) -> (Color, Float) {
+    let (x, y) = pixel;
+    rng.start_sample(pixel, renderer.first_sample + index);
+    let (dx, wx) = renderer.filter_sampler.sample(rng.r#gen());
+    let (dy, wy) = renderer.filter_sampler.sample(rng.r#gen());
+    let position = (x as Float + 0.5 + dx, y as Float + 0.5 + dy);
+    (sample(renderer, rng, position), wx * wy)
+}
+
+// Renders the section taking samples uniformly over each pixel and splatting them into every
+// pixel within the filter radius, the border around the section is sampled as well
+pub(crate) fn render_splatted(
+    renderer: &Renderer,
+    section: &Section,
+    sample: SampleFn,
+) -> Vec<Color> {
+    let filter = renderer.filter;
+    let margin = filter.radius().ceil() as i64;
+    let left = section.left as i64;
+    let top = section.top as i64;
+    let width = section.width as i64;
+    let height = section.height as i64;
+    let band = 2 * margin + 1;
+    let pass = renderer.control.pass((height + 2 * margin) as u32);
+
+    let rows = (top - margin..top + height + margin)
+        .into_par_iter()
+        .map_init(
+            || renderer.sampler.rng(renderer.samples, renderer.seed),
+            |rng, row| {
+                // accumulated color and weight for the rows reached from this one
+                let mut local = vec![(color::BLACK, 0.0); (band * width) as usize];
+                if pass.is_cancelled() {
+                    return (row, local);
+                }
+                for column in left - margin..left + width + margin {
+                    for index in 0..renderer.samples {
+                        rng.start_sample(
+                            (column as u32, row as u32),
+                            renderer.first_sample + index,
+                        );
+                        let x = column as Float + rng.r#gen();
+                        let y = row as Float + rng.r#gen();
+                        let color = sample(renderer, rng, (x, y));
+                        for py in (row - margin).max(top)..(row + margin + 1).min(top + height) {
+                            for px in
+                                (column - margin).max(left)..(column + margin + 1).min(left + width)
+                            {
+                                let weight =
+                                    filter.evaluate(px as Float + 0.5 - x, py as Float + 0.5 - y);
+                                if weight != 0.0 {
+                                    let idx = ((py - row + margin) * width + px - left) as usize;
+                                    let (sum, total) = local[idx];
+                                    local[idx] = (sum + color * weight, total + weight);
+                                }
+                            }
+                        }
+                    }
+                }
+                pass.job_done();
+                (row, local)
+            },
+        )
+        .collect::<Vec<(i64, Vec<(Color, Float)>)>>();
+
+    let mut accumulated = vec![(color::BLACK, 0.0); (width * height) as usize];
+    for (row, local) in rows {
+        for (idx, (color, weight)) in local.into_iter().enumerate() {
+            let y = row - margin + idx as i64 / width - top;
+            if weight != 0.0 && (0..height).contains(&y) {
+                let target = (y * width + idx as i64 % width) as usize;
+                let (sum, total) = accumulated[target];
+                accumulated[target] = (sum + color, total + weight);
+            }
+        }
+    }
+
+    accumulated
+        .into_iter()
+        .map(|(color, weight)| {
+            if weight != 0.0 {
+                color / weight
+            } else {
+                color::BLACK
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Filter, FilterSampler};
+
+    #[test]
+    fn box_sampler_is_uniform() {
+        let sampler = FilterSampler::new(&Filter::default());
+        for random in [0.0, 0.25, 0.5, 0.75, 0.999] {
+            let (offset, sign) = sampler.sample(random);
+            assert!((offset - (random - 0.5)).abs() < 1e-4);
+            assert_eq!(sign, 1.0);
+        }
+    }
+
+    #[test]
+    fn filters_peak_at_center() {
+        let filters = [
+            Filter::box_filter(0.5),
+            Filter::tent(1.0),
+            Filter::gaussian(1.5),
+            Filter::mitchell(2.0),
+            Filter::lanczos(2.0),
+        ];
+        for filter in filters {
+            let center = filter.evaluate(0.0, 0.0);
+            assert!(center > 0.0);
+            assert!(filter.evaluate(0.3, 0.2) <= center);
+            assert_eq!(filter.evaluate(filter.radius() + 0.1, 0.0), 0.0);
+        }
+    }
+}
@@ -1,8 +1,15 @@
 use std::sync::Arc;
 
-use ilios_types::{float::EPSILON, geometry::Triangle, ray::Ray};
+use ilios_types::{
+    float::{EPSILON, Float},
+    geometry::Triangle,
+    ray::Ray,
+};
 
-use crate::simd::{self, F32x4};
+use crate::{
+    closest_primitive::ClosestPrimitive,
+    simd::{self, F32x4},
+};
 
 #[derive(Clone, Debug)]
 pub struct PackedTriangles {
@@ -16,6 +23,37 @@ pub struct PackedTriangles {
     pub edge2_x: F32x4,
     pub edge2_y: F32x4,
     pub edge2_z: F32x4,
+    pub motion: Option<Box<PackedMotion>>,
+}
+
+// Displacement of each packed triangle between shutter open (time 0.0) and close (time 1.0)
+#[derive(Clone, Debug)]
+pub struct PackedMotion {
+    pub origin_x: F32x4,
+    pub origin_y: F32x4,
+    pub origin_z: F32x4,
+    pub edge1_x: F32x4,
+    pub edge1_y: F32x4,
+    pub edge1_z: F32x4,
+    pub edge2_x: F32x4,
+    pub edge2_y: F32x4,
+    pub edge2_z: F32x4,
+}
+
+impl Default for PackedMotion {
+    fn default() -> PackedMotion {
+        PackedMotion {
+            origin_x: simd::default(),
+            origin_y: simd::default(),
+            origin_z: simd::default(),
+            edge1_x: simd::default(),
+            edge1_y: simd::default(),
+            edge1_z: simd::default(),
+            edge2_x: simd::default(),
+            edge2_y: simd::default(),
+            edge2_z: simd::default(),
+        }
+    }
 }
 
 impl Default for PackedTriangles {
@@ -31,6 +69,7 @@ impl Default for PackedTriangles {
             edge2_x: simd::default(),
             edge2_y: simd::default(),
             edge2_z: simd::default(),
+            motion: None,
         }
     }
 }
@@ -38,21 +77,97 @@ impl Default for PackedTriangles {
 impl PackedTriangles {
     pub fn new(triangles: &[Arc<Triangle>]) -> PackedTriangles {
         let mut pt = PackedTriangles::default();
-        for (i, triangle) in triangles.iter().enumerate() {
-            pt.triangles.push(triangle.clone());
-            pt.origin_x = simd::set(pt.origin_x, triangle.origin.0, i);
-            pt.origin_y = simd::set(pt.origin_y, triangle.origin.1, i);
-            pt.origin_z = simd::set(pt.origin_z, triangle.origin.2, i);
-            pt.edge1_x = simd::set(pt.edge1_x, triangle.edge1.0, i);
-            pt.edge1_y = simd::set(pt.edge1_y, triangle.edge1.1, i);
-            pt.edge1_z = simd::set(pt.edge1_z, triangle.edge1.2, i);
-            pt.edge2_x = simd::set(pt.edge2_x, triangle.edge2.0, i);
-            pt.edge2_y = simd::set(pt.edge2_y, triangle.edge2.1, i);
-            pt.edge2_z = simd::set(pt.edge2_z, triangle.edge2.2, i);
+        for triangle in triangles.iter() {
+            pt.push(triangle.clone());
         }
         pt
     }
+
+    // Adds a triangle to the next free lane, a pack holds up to four triangles
+    pub fn push(&mut self, triangle: Arc<Triangle>) {
+        let i = self.triangles.len();
+        self.origin_x = simd::set(self.origin_x, triangle.origin.0, i);
+        self.origin_y = simd::set(self.origin_y, triangle.origin.1, i);
+        self.origin_z = simd::set(self.origin_z, triangle.origin.2, i);
+        self.edge1_x = simd::set(self.edge1_x, triangle.edge1.0, i);
+        self.edge1_y = simd::set(self.edge1_y, triangle.edge1.1, i);
+        self.edge1_z = simd::set(self.edge1_z, triangle.edge1.2, i);
+        self.edge2_x = simd::set(self.edge2_x, triangle.edge2.0, i);
+        self.edge2_y = simd::set(self.edge2_y, triangle.edge2.1, i);
+        self.edge2_z = simd::set(self.edge2_z, triangle.edge2.2, i);
+        if triangle.motion.is_some() {
+            let (origin, edge1, edge2) = triangle.at(1.0);
+            let origin = &origin - &triangle.origin;
+            let edge1 = edge1 - triangle.edge1;
+            let edge2 = edge2 - triangle.edge2;
+            let motion = self.motion.get_or_insert_with(Box::default);
+            motion.origin_x = simd::set(motion.origin_x, origin.0, i);
+            motion.origin_y = simd::set(motion.origin_y, origin.1, i);
+            motion.origin_z = simd::set(motion.origin_z, origin.2, i);
+            motion.edge1_x = simd::set(motion.edge1_x, edge1.0, i);
+            motion.edge1_y = simd::set(motion.edge1_y, edge1.1, i);
+            motion.edge1_z = simd::set(motion.edge1_z, edge1.2, i);
+            motion.edge2_x = simd::set(motion.edge2_x, edge2.0, i);
+            motion.edge2_y = simd::set(motion.edge2_y, edge2.1, i);
+            motion.edge2_z = simd::set(motion.edge2_z, edge2.2, i);
+        }
+        self.triangles.push(triangle);
+    }
+
     pub fn intersect(&self, ray: &Ray) -> Option<F32x4> {
+        match &self.motion {
+            Some(motion) => {
+                let time = simd::splat(ray.time);
+                let at = |start: F32x4, delta: F32x4| simd::mul_add(delta, time, start);
+                let moved = PackedTriangles {
+                    triangles: vec![],
+                    origin_x: at(self.origin_x, motion.origin_x),
+                    origin_y: at(self.origin_y, motion.origin_y),
+                    origin_z: at(self.origin_z, motion.origin_z),
+                    edge1_x: at(self.edge1_x, motion.edge1_x),
+                    edge1_y: at(self.edge1_y, motion.edge1_y),
+                    edge1_z: at(self.edge1_z, motion.edge1_z),
+                    edge2_x: at(self.edge2_x, motion.edge2_x),
+                    edge2_y: at(self.edge2_y, motion.edge2_y),
+                    edge2_z: at(self.edge2_z, motion.edge2_z),
+                    motion: None,
+                };
+                moved.intersect_static(ray)
+            }
+            None => self.intersect_static(ray),
+        }
+    }
+
+    // Closest of the packed triangles hit by the ray nearer than the max distance
+    pub fn closest_hit(&self, ray: &Ray, max_distance: Float) -> Option<ClosestPrimitive<'_>> {
+        let distances = self.intersect(ray)?;
+        let mut closest = None;
+        let mut closest_distance = max_distance;
+        for (idx, triangle) in self.triangles.iter().enumerate() {
+            let distance = simd::get(distances, idx);
+            if distance > 0.0 && distance < closest_distance {
+                closest_distance = distance;
+                closest = Some(triangle.as_ref());
+            }
+        }
+        closest.map(|primitive| ClosestPrimitive {
+            primitive,
+            distance: closest_distance,
+            instance: None,
+        })
+    }
+
+    // Whether any of the packed triangles is hit nearer than the max distance
+    pub fn hit_within(&self, ray: &Ray, max_distance: Float) -> bool {
+        self.intersect(ray).is_some_and(|distances| {
+            (0..self.triangles.len()).any(|idx| {
+                let distance = simd::get(distances, idx);
+                distance > 0.0 && distance < max_distance
+            })
+        })
+    }
+
+    fn intersect_static(&self, ray: &Ray) -> Option<F32x4> {
         let epsilon = simd::splat(EPSILON);
         let epsilon_p1 = simd::splat(1.0 + EPSILON);
         let zero = simd::splat(0.0);
@@ -173,8 +288,11 @@ impl PackedTriangles {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use ilios_types::{
-        geometry::{Normal, Point},
+        geometry::{Normal, Point, Triangle},
+        material::Material,
         ray::Ray,
     };
 
@@ -225,6 +343,7 @@ mod tests {
             edge2_x: simd::new(0.0, 0.0, 0.0, 0.0),
             edge2_y: simd::new(4.7683716e-6, 4.7683716e-6, 4.7683716e-6, 4.7683716e-6),
             edge2_z: simd::new(30.0, 30.0, 30.0, 30.0),
+            motion: None,
         };
         let ray = Ray::new(
             Point(0.80026245, 4.7896767, -45.0),
@@ -233,10 +352,41 @@ mod tests {
             1.0,
         );
         assert_eq!(
-            pt.intersect(&ray).map(|v| ComparableF32x4(v)),
+            pt.intersect(&ray).map(ComparableF32x4),
             Some(ComparableF32x4(simd::new(
                 41.368675, 41.368675, 41.368675, 0.0
             )))
         );
     }
+
+    #[test]
+    fn test_intersect_moving() {
+        let material = Arc::new(Material::default());
+        let triangle = |dx: f32| {
+            Triangle::new(
+                Point(-1.0 + dx, -1.0, 0.0),
+                Point(1.0 + dx, -1.0, 0.0),
+                Point(dx, 1.0, 0.0),
+                material.clone(),
+            )
+        };
+        let moving = Arc::new(Triangle::moving(&triangle(0.0), &triangle(10.0)));
+        let pt = PackedTriangles::new(&[moving]);
+
+        let hit = |x: f32, time: f32| {
+            let ray = Ray::new(
+                Point(x, 0.0, -5.0),
+                Normal(0.0, 0.0, 1.0),
+                f32::INFINITY,
+                1.0,
+            )
+            .with_time(time);
+            pt.intersect(&ray).is_some_and(|d| simd::get(d, 0) > 0.0)
+        };
+        assert!(hit(0.0, 0.0));
+        assert!(!hit(0.0, 1.0));
+        assert!(!hit(10.0, 0.0));
+        assert!(hit(10.0, 1.0));
+        assert!(hit(5.0, 0.5));
+    }
 }
@@ -1,17 +1,30 @@
 pub use ilios_types;
 pub(crate) mod accelerators;
+pub(crate) mod adaptive;
 pub(crate) mod algorithms;
 mod closest_primitive;
 pub mod demos;
+pub(crate) mod filter;
 pub(crate) mod geometry;
+pub(crate) mod progress;
+pub(crate) mod progressive;
 pub(crate) mod render_method;
 pub(crate) mod renderer;
 pub(crate) mod rng;
+pub(crate) mod sampler;
 mod simd;
+pub(crate) mod stereo;
 mod trace;
 
 pub use accelerators::Accelerator;
 pub use accelerators::BvhBuildMethod;
+pub use accelerators::BvhWidth;
+pub use adaptive::AdaptiveSampling;
 pub use algorithms::Algorithm;
-pub use render_method::RenderMethod;
-pub use renderer::Renderer;
+pub use filter::{Filter, FilterMethod};
+pub use progress::{CancellationToken, Progress};
+pub use progressive::Progressive;
+pub use render_method::{RenderMethod, TileOrder};
+pub use renderer::{Renderer, RendererBuilder};
+pub use sampler::Sampler;
+pub use stereo::{Stereo, StereoLayout};
@@ -0,0 +1,188 @@
+use std::{
+    fmt,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+// Progress of the render pass running, work is counted in the jobs of the render method:
+// pixels, tiles or scanlines. Multi pass renders report every pass from zero
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    pub completed: u32,
+    pub total: u32,
+    // zero on wasm32, it has no clock
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.completed as f32 / self.total as f32
+        }
+    }
+
+    // Estimated time left for the pass, the remaining jobs are assumed to take
+    // as long as the completed ones
+    pub fn eta(&self) -> Option<Duration> {
+        if self.completed == 0 {
+            return None;
+        }
+        let remaining = self.total.saturating_sub(self.completed) as f32;
+        Some(self.elapsed.mul_f32(remaining / self.completed as f32))
+    }
+}
+
+// Shared flag to stop a render, it is checked before starting every job so cancelled renders
+// return with the unfinished pixels black
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub(crate) type ProgressFn = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+// Cancellation and progress reporting of the render running
+#[derive(Clone, Default)]
+pub(crate) struct RenderControl {
+    cancel: CancellationToken,
+    progress: Option<ProgressFn>,
+    // only taken when reporting progress
+    start: Option<Instant>,
+}
+
+impl RenderControl {
+    pub(crate) fn new(cancel: CancellationToken, progress: Option<ProgressFn>) -> RenderControl {
+        // wasm32 has no clock
+        #[cfg(not(target_arch = "wasm32"))]
+        let start = progress.as_ref().map(|_| Instant::now());
+        #[cfg(target_arch = "wasm32")]
+        let start = None;
+        RenderControl {
+            cancel,
+            progress,
+            start,
+        }
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    // Starts counting the jobs of a pass
+    pub(crate) fn pass(&self, total: u32) -> PassControl<'_> {
+        PassControl {
+            control: self,
+            total,
+            completed: AtomicU32::new(0),
+        }
+    }
+}
+
+impl fmt::Debug for RenderControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RenderControl")
+            .field("cancel", &self.cancel)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+pub(crate) struct PassControl<'a> {
+    control: &'a RenderControl,
+    total: u32,
+    completed: AtomicU32,
+}
+
+impl PassControl<'_> {
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.control.is_cancelled()
+    }
+
+    pub(crate) fn job_done(&self) {
+        let completed = self.completed.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(progress) = &self.control.progress {
+            progress(&Progress {
+                completed,
+                total: self.total,
+                elapsed: self
+                    .control
+                    .start
+                    .map_or(Duration::ZERO, |start| start.elapsed()),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicU32, Ordering},
+        },
+        time::Duration,
+    };
+
+    use ilios_types::section::Section;
+
+    use super::{CancellationToken, Progress};
+    use crate::{Accelerator, Algorithm, RenderMethod, Renderer, demos};
+
+    #[test]
+    fn eta_scales_with_remaining_jobs() {
+        let progress = Progress {
+            completed: 1,
+            total: 4,
+            elapsed: Duration::from_secs(2),
+        };
+        assert_eq!(progress.eta(), Some(Duration::from_secs(6)));
+        assert_eq!(progress.fraction(), 0.25);
+    }
+
+    #[test]
+    fn cancelled_renders_stop_reporting() {
+        let mut renderer = Renderer::builder()
+            .width(16)
+            .height(8)
+            .algorithm(Algorithm::PathTracing)
+            .render_method(RenderMethod::Scanlines)
+            .accelerator(Accelerator::BoundingVolumeHierarchy)
+            .world(demos::simple())
+            .build();
+        let section = Section::new(0, 0, 16, 8);
+
+        let reported = Arc::new(AtomicU32::new(0));
+        let counter = reported.clone();
+        renderer.render_with(&section, &CancellationToken::new(), move |progress| {
+            counter.fetch_max(progress.completed, Ordering::Relaxed);
+        });
+        assert_eq!(reported.load(Ordering::Relaxed), 8);
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let reported = Arc::new(AtomicU32::new(0));
+        let counter = reported.clone();
+        let pixels = renderer.render_with(&section, &cancel, move |progress| {
+            counter.fetch_max(progress.completed, Ordering::Relaxed);
+        });
+        assert_eq!(reported.load(Ordering::Relaxed), 0);
+        assert_eq!(pixels.len(), 16 * 8);
+    }
+}
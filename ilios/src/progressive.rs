@@ -0,0 +1,244 @@
+use ilios_types::{
+    camera::Camera,
+    color::{self, Color},
+    float::Float,
+    section::Section,
+    transform::Transform,
+    world::World,
+};
+
+use crate::{CancellationToken, Progress, Renderer, renderer::RendererBuilder};
+
+// Progressive rendering session, every pass adds the renderer samples to an accumulation buffer.
+// Changes to the scene go through the session so the accumulated image is discarded with them
+pub struct Progressive {
+    renderer: Renderer,
+    section: Section,
+    accumulated: Vec<Color>,
+    sample_counts: Vec<u32>,
+    passes: u32,
+    camera_changed: bool,
+}
+
+impl Progressive {
+    pub fn new(renderer: Renderer) -> Progressive {
+        let section = Section::new(0, 0, renderer.width, renderer.height);
+        let len = (section.width * section.height) as usize;
+        Progressive {
+            accumulated: vec![color::BLACK; len],
+            sample_counts: vec![0; len],
+            renderer,
+            section,
+            passes: 0,
+            camera_changed: false,
+        }
+    }
+
+    // Continues a session from the averaged image of its passes, as saved by a checkpoint.
+    // The renderer needs the same settings and scene for the passes to continue the sequence
+    pub fn restore(
+        renderer: Renderer,
+        image: &[Color],
+        sample_counts: Vec<u32>,
+        passes: u32,
+    ) -> Progressive {
+        let mut session = Progressive::new(renderer);
+        if image.len() == session.accumulated.len() && sample_counts.len() == image.len() {
+            for (accumulated, color) in session.accumulated.iter_mut().zip(image) {
+                *accumulated = *color * passes as Float;
+            }
+            session.sample_counts = sample_counts;
+            session.passes = passes;
+        }
+        session
+    }
+
+    // Renders one more pass over the whole image and adds it to the accumulated one
+    pub fn render_pass(&mut self) {
+        self.prepare_pass();
+        let pixels = self.renderer.render(&self.section);
+        self.accumulate(pixels);
+    }
+
+    // Renders a pass reporting its progress, a cancelled pass is discarded.
+    // Returns whether the pass was added
+    pub fn render_pass_with<F>(&mut self, cancel: &CancellationToken, progress: F) -> bool
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        self.prepare_pass();
+        let pixels = self.renderer.render_with(&self.section, cancel, progress);
+        if cancel.is_cancelled() {
+            return false;
+        }
+        self.accumulate(pixels);
+        true
+    }
+
+    fn prepare_pass(&mut self) {
+        if self.camera_changed {
+            self.renderer.update_camera();
+            self.camera_changed = false;
+        }
+        self.renderer.first_sample = self.renderer.pass_first_sample(self.passes);
+    }
+
+    fn accumulate(&mut self, pixels: Vec<Color>) {
+        for (accumulated, pixel) in self.accumulated.iter_mut().zip(pixels) {
+            *accumulated = *accumulated + pixel;
+        }
+        // adaptive passes count the samples of every pixel
+        let pass_counts = self.renderer.sample_counts();
+        if pass_counts.len() == self.sample_counts.len() {
+            for (count, pass_count) in self.sample_counts.iter_mut().zip(pass_counts) {
+                *count += pass_count;
+            }
+        } else {
+            for count in self.sample_counts.iter_mut() {
+                *count += self.renderer.samples;
+            }
+        }
+        self.passes += 1;
+    }
+
+    // Average of the passes rendered so far
+    pub fn image(&self) -> Vec<Color> {
+        let passes = self.passes.max(1) as Float;
+        self.accumulated
+            .iter()
+            .map(|color| *color / passes)
+            .collect()
+    }
+
+    pub fn passes(&self) -> u32 {
+        self.passes
+    }
+
+    // Samples per pixel accumulated so far
+    pub fn samples(&self) -> u32 {
+        self.passes * self.renderer.samples
+    }
+
+    // Samples accumulated by each pixel, they differ between pixels with adaptive sampling
+    pub fn sample_counts(&self) -> &[u32] {
+        &self.sample_counts
+    }
+
+    pub fn width(&self) -> u32 {
+        self.section.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.section.height
+    }
+
+    pub fn renderer(&self) -> &Renderer {
+        &self.renderer
+    }
+
+    // Discards the accumulated passes
+    pub fn reset(&mut self) {
+        self.accumulated.fill(color::BLACK);
+        self.sample_counts.fill(0);
+        self.passes = 0;
+    }
+
+    // Gives access to the camera, the session restarts as the camera is about to change
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        self.reset();
+        self.camera_changed = true;
+        &mut self.renderer.camera
+    }
+
+    pub fn set_camera(&mut self, camera: Camera) {
+        *self.camera_mut() = camera;
+    }
+
+    pub fn set_world(&mut self, world: World) {
+        self.rebuild(|builder| {
+            builder.world(world);
+        });
+    }
+
+    // Moves a solid of the world, its accelerator is refitted instead of built again when it can
+    pub fn set_transform(&mut self, solid: usize, transform: &Transform) {
+        self.reset();
+        self.renderer.set_transform(solid, transform);
+    }
+
+    pub fn set_scene(&mut self, camera: Camera, world: World) {
+        self.rebuild(|builder| {
+            builder.camera(camera).world(world);
+        });
+    }
+
+    // Builds the renderer again with modified settings and restarts the session
+    pub fn rebuild<F>(&mut self, configure: F)
+    where
+        F: FnOnce(&mut RendererBuilder),
+    {
+        let mut builder = self.renderer.to_builder();
+        configure(&mut builder);
+        *self = Progressive::new(builder.build());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Accelerator, Algorithm, Renderer, demos};
+
+    use super::Progressive;
+
+    #[test]
+    fn passes_accumulate_until_the_camera_changes() {
+        let renderer = Renderer::builder()
+            .width(8)
+            .height(4)
+            .samples(1)
+            .algorithm(Algorithm::PathTracing)
+            .accelerator(Accelerator::BoundingVolumeHierarchy)
+            .world(demos::simple())
+            .build();
+        let mut session = Progressive::new(renderer);
+        session.render_pass();
+        let first = session.image();
+        session.render_pass();
+        assert_eq!(session.samples(), 2);
+        assert_eq!(session.image().len(), first.len());
+
+        session.camera_mut().eye.2 -= 1.0;
+        assert_eq!(session.passes(), 0);
+        session.render_pass();
+        assert_eq!(session.samples(), 1);
+        assert!(session.sample_counts().iter().all(|count| *count == 1));
+    }
+
+    #[test]
+    fn restored_sessions_continue_the_passes() {
+        let build = || {
+            Renderer::builder()
+                .width(8)
+                .height(4)
+                .algorithm(Algorithm::PathTracing)
+                .world(demos::simple())
+                .build()
+        };
+        let mut session = Progressive::new(build());
+        session.render_pass();
+        session.render_pass();
+
+        let mut restored = Progressive::restore(
+            build(),
+            &session.image(),
+            session.sample_counts().to_vec(),
+            session.passes(),
+        );
+        assert_eq!(restored.passes(), 2);
+        restored.render_pass();
+        session.render_pass();
+        assert!(restored.sample_counts().iter().all(|count| *count == 3));
+        for (a, b) in restored.image().iter().zip(session.image()) {
+            assert!((a.0 - b.0).abs() < 1e-4);
+        }
+    }
+}
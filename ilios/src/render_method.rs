@@ -1,6 +1,6 @@
-use super::rng::{Rng, XorRng};
+use super::rng::Rng;
 use crate::Renderer;
-use ilios_types::{color::Color, section::Section};
+use ilios_types::{color::Color, float::Float, section::Section};
 use rayon::prelude::*;
 
 #[derive(Clone, Debug)]
@@ -10,7 +10,70 @@ pub enum RenderMethod {
     Scanlines,
 }
 
+// Order in which the tiles of the tiles render method are started
+#[derive(Clone, Copy, Debug, Default)]
+pub enum TileOrder {
+    // left to right, top to bottom
+    #[default]
+    Row,
+    // outwards from the center of the image
+    Spiral,
+    // along a Hilbert curve, neighbouring tiles run close in time
+    Hilbert,
+}
+
+impl TileOrder {
+    // Tile coordinates of a grid in the order they should be rendered
+    pub(crate) fn tiles(&self, tiles_h: u32, tiles_v: u32) -> Vec<(u32, u32)> {
+        let mut tiles: Vec<(u32, u32)> = (0..tiles_v)
+            .flat_map(|y| (0..tiles_h).map(move |x| (x, y)))
+            .collect();
+        match self {
+            TileOrder::Row => {}
+            TileOrder::Spiral => {
+                let center = (tiles_h as Float / 2.0, tiles_v as Float / 2.0);
+                let key = |(x, y): (u32, u32)| {
+                    let dx = x as Float + 0.5 - center.0;
+                    let dy = y as Float + 0.5 - center.1;
+                    // rings of tiles around the center, each walked by angle
+                    (dx.abs().max(dy.abs()).floor(), dy.atan2(dx))
+                };
+                tiles.sort_by(|a, b| {
+                    let (a, b) = (key(*a), key(*b));
+                    a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1))
+                });
+            }
+            TileOrder::Hilbert => {
+                let size = tiles_h.max(tiles_v).next_power_of_two();
+                tiles.sort_by_key(|tile| hilbert_index(size, *tile));
+            }
+        }
+        tiles
+    }
+}
+
+// Distance along the Hilbert curve filling a square grid of a power of two size
+fn hilbert_index(size: u32, (mut x, mut y): (u32, u32)) -> u64 {
+    let mut index = 0;
+    let mut s = size / 2;
+    while s > 0 {
+        let rx = u32::from(x & s > 0);
+        let ry = u32::from(y & s > 0);
+        index += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        if ry == 0 {
+            if rx == 1 {
+                x = size - 1 - x;
+                y = size - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
+
 pub type TraceFn = fn(&Renderer, &mut dyn Rng, (u32, u32)) -> Color;
+pub type SampleFn = fn(&Renderer, &mut dyn Rng, (Float, Float)) -> Color;
 pub type RenderFn = fn(&mut Renderer, &Section, TraceFn) -> Vec<Color>;
 
 impl RenderMethod {
@@ -21,85 +84,206 @@ impl RenderMethod {
             RenderMethod::Scanlines => render_scanlines,
         }
     }
+
+    // Evaluates every pixel of the section once, splitting the work like the render method does.
+    // Multi pass renderers call it once per pass, the results are in row major order
+    pub(crate) fn map_pixels<T, F>(
+        &self,
+        renderer: &Renderer,
+        section: &Section,
+        evaluate: F,
+    ) -> Vec<T>
+    where
+        T: Clone + Default + Send,
+        F: Fn(&mut dyn Rng, (u32, u32)) -> T + Sync,
+    {
+        match self {
+            RenderMethod::Pixels => map_pixels(renderer, section, evaluate),
+            RenderMethod::Tiles => map_tiles(renderer, section, evaluate),
+            RenderMethod::Scanlines => map_scanlines(renderer, section, evaluate),
+        }
+    }
 }
 
 fn render_pixels(renderer: &mut Renderer, section: &Section, trace: TraceFn) -> Vec<Color> {
+    let renderer: &Renderer = renderer;
+    map_pixels(renderer, section, |rng, pixel| trace(renderer, rng, pixel))
+}
+
+fn render_tiles(renderer: &mut Renderer, section: &Section, trace: TraceFn) -> Vec<Color> {
+    let renderer: &Renderer = renderer;
+    map_tiles(renderer, section, |rng, pixel| trace(renderer, rng, pixel))
+}
+
+fn render_scanlines(renderer: &mut Renderer, section: &Section, trace: TraceFn) -> Vec<Color> {
+    let renderer: &Renderer = renderer;
+    map_scanlines(renderer, section, |rng, pixel| trace(renderer, rng, pixel))
+}
+
+fn map_pixels<T, F>(renderer: &Renderer, section: &Section, evaluate: F) -> Vec<T>
+where
+    T: Default + Send,
+    F: Fn(&mut dyn Rng, (u32, u32)) -> T + Sync,
+{
     let Section {
         left,
         top,
         height,
         width,
     } = section;
+    let (sampler, samples, seed) = (renderer.sampler, renderer.samples, renderer.seed);
+    let pass = renderer.control.pass(width * height);
     (0..width * height)
         .into_par_iter()
-        .map_init(XorRng::new, |rng, idx| {
-            let pixel = (left + (idx % width), top + (idx / width));
-            trace(renderer, rng, pixel)
-        })
+        .map_init(
+            || sampler.rng(samples, seed),
+            |rng, idx| {
+                if pass.is_cancelled() {
+                    return T::default();
+                }
+                let pixel = (left + (idx % width), top + (idx / width));
+                let value = evaluate(rng, pixel);
+                pass.job_done();
+                value
+            },
+        )
         .collect()
 }
 
-fn render_tiles(renderer: &mut Renderer, section: &Section, trace: TraceFn) -> Vec<Color> {
+fn map_tiles<T, F>(renderer: &Renderer, section: &Section, evaluate: F) -> Vec<T>
+where
+    T: Clone + Default + Send,
+    F: Fn(&mut dyn Rng, (u32, u32)) -> T + Sync,
+{
     let Section {
         left,
         top,
         height,
         width,
-    } = section;
-    let tile_size = 4;
-    let sections_v = height / tile_size;
-    let sections_h = width / tile_size;
-
-    let tiles: Vec<(u32, u32)> = (0..sections_v * sections_h)
-        .map(|idx| {
-            let x = left + (idx % sections_h) * tile_size;
-            let y = top + (idx / sections_h) * tile_size;
-            (x, y)
-        })
-        .collect();
+    } = *section;
+    let (sampler, samples, seed) = (renderer.sampler, renderer.samples, renderer.seed);
+    let tile_size = renderer.tile_size.max(1);
+    let tiles_h = width.div_ceil(tile_size);
+    let tiles_v = height.div_ceil(tile_size);
+
+    // tiles on the right and bottom edges are cut to the section size
+    let tile_rect = |(tile_x, tile_y): (u32, u32)| {
+        let (x, y) = (tile_x * tile_size, tile_y * tile_size);
+        (x, y, tile_size.min(width - x), tile_size.min(height - y))
+    };
+
+    let tiles = renderer.tile_order.tiles(tiles_h, tiles_v);
+    let pass = renderer.control.pass(tiles.len() as u32);
+    // bridging keeps the tiles starting in the order given
     let tiles = tiles
-        .into_par_iter()
-        .map_init(XorRng::new, |rng, (x, y)| {
-            (0..tile_size * tile_size)
-                .map(|idx| (x + (idx % tile_size), y + (idx / tile_size)))
-                .map(|pixel| trace(renderer, rng, pixel))
-                .collect()
-        })
-        .collect::<Vec<Vec<Color>>>();
-
-    let mut pixels: Vec<Color> = vec![Color::default(); (width * height) as usize];
-    for (section, colors) in tiles.into_iter().enumerate() {
-        let start_x = (section as u32 % sections_h) * tile_size;
-        let start_y = (section as u32 / sections_h) * tile_size;
-        for (idx, color) in colors.into_iter().enumerate() {
-            let x = idx as u32 % tile_size;
-            let y = idx as u32 / tile_size;
-            pixels[((start_y + y) * width + start_x + x) as usize] = color;
+        .into_iter()
+        .par_bridge()
+        .map_init(
+            || sampler.rng(samples, seed),
+            |rng, tile| {
+                let (x, y, tile_width, tile_height) = tile_rect(tile);
+                if pass.is_cancelled() {
+                    return (
+                        tile,
+                        vec![T::default(); (tile_width * tile_height) as usize],
+                    );
+                }
+                let values = (0..tile_width * tile_height)
+                    .map(|idx| (left + x + idx % tile_width, top + y + idx / tile_width))
+                    .map(|pixel| evaluate(rng, pixel))
+                    .collect();
+                pass.job_done();
+                (tile, values)
+            },
+        )
+        .collect::<Vec<((u32, u32), Vec<T>)>>();
+
+    let mut pixels: Vec<T> = vec![T::default(); (width * height) as usize];
+    for (tile, values) in tiles {
+        let (x, y, tile_width, _) = tile_rect(tile);
+        for (idx, value) in values.into_iter().enumerate() {
+            let (dx, dy) = (idx as u32 % tile_width, idx as u32 / tile_width);
+            pixels[((y + dy) * width + x + dx) as usize] = value;
         }
     }
 
     pixels
 }
 
-fn render_scanlines(renderer: &mut Renderer, section: &Section, trace: TraceFn) -> Vec<Color> {
+fn map_scanlines<T, F>(renderer: &Renderer, section: &Section, evaluate: F) -> Vec<T>
+where
+    T: Clone + Default + Send,
+    F: Fn(&mut dyn Rng, (u32, u32)) -> T + Sync,
+{
     let Section {
-        height, width, top, ..
+        left,
+        top,
+        height,
+        width,
     } = section;
+    let (sampler, samples, seed) = (renderer.sampler, renderer.samples, renderer.seed);
+    let pass = renderer.control.pass(*height);
 
     (0..*height)
         .into_par_iter()
-        .map_init(XorRng::new, |rng, row| {
-            let y = top + row;
+        .map_init(
+            || sampler.rng(samples, seed),
+            |rng, row| {
+                if pass.is_cancelled() {
+                    return vec![T::default(); *width as usize];
+                }
+                let y = top + row;
 
-            (0..*width)
-                .map(|idx| (idx, y))
-                .map(|pixel| trace(renderer, rng, pixel))
-                .collect::<Vec<Color>>()
-        })
-        .collect::<Vec<Vec<Color>>>()
+                let values = (0..*width)
+                    .map(|idx| (left + idx, y))
+                    .map(|pixel| evaluate(rng, pixel))
+                    .collect::<Vec<T>>();
+                pass.job_done();
+                values
+            },
+        )
+        .collect::<Vec<Vec<T>>>()
         .into_iter()
-        .fold(Vec::new(), |mut acc, mut colors| {
-            acc.append(&mut colors);
+        .fold(Vec::new(), |mut acc, mut values| {
+            acc.append(&mut values);
             acc
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use ilios_types::section::Section;
+
+    use super::{RenderMethod, TileOrder, hilbert_index};
+    use crate::Renderer;
+
+    #[test]
+    fn tiles_cover_sections_of_any_size() {
+        for order in [TileOrder::Row, TileOrder::Spiral, TileOrder::Hilbert] {
+            let renderer = Renderer::builder()
+                .width(11)
+                .height(7)
+                .tile_size(4)
+                .tile_order(order)
+                .build();
+            let section = Section::new(2, 1, 9, 6);
+            let pixels = RenderMethod::Tiles.map_pixels(&renderer, &section, |_, pixel| pixel);
+            for (idx, pixel) in pixels.into_iter().enumerate() {
+                let idx = idx as u32;
+                assert_eq!(pixel, (2 + idx % 9, 1 + idx / 9));
+            }
+        }
+    }
+
+    #[test]
+    fn hilbert_steps_between_neighbours() {
+        let mut tiles = TileOrder::Hilbert.tiles(4, 4);
+        assert_eq!(tiles.len(), 16);
+        tiles.dedup();
+        for pair in tiles.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!(a.0.abs_diff(b.0) + a.1.abs_diff(b.1), 1);
+        }
+        assert_eq!(hilbert_index(2, (1, 0)), 3);
+    }
+}
@@ -1,11 +1,29 @@
-use ilios_types::{camera::Camera, color::Color, float::Float, section::Section, world::World};
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use ilios_types::{
+    camera::Camera, color::Color, float::Float, geometry::Triangle, section::Section,
+    solids::Solid, transform::Transform, world::World,
+};
+use rayon::{ThreadPool, ThreadPoolBuilder};
 
 use super::{
-    accelerators::{Accelerator, AcceleratorInstance, BvhBuildMethod},
+    accelerators::{Accelerator, AcceleratorInstance, BvhBuildMethod, BvhWidth},
+    adaptive::{self, AdaptiveSampling},
     algorithms::{Algorithm, path_tracing, whitted},
-    render_method::{RenderMethod, TraceFn},
+    filter::{self, Filter, FilterMethod, FilterSampler},
+    progress::{CancellationToken, Progress, RenderControl},
+    render_method::{RenderMethod, SampleFn, TileOrder, TraceFn},
+    sampler::Sampler,
+    stereo::{self, Stereo},
 };
 
+// Refitted BVHs are built again once they cost half again as much as when built
+const DEFAULT_REFIT_THRESHOLD: Float = 1.5;
+
 #[derive(Debug)]
 pub struct Renderer {
     pub width: u32,
@@ -17,8 +35,29 @@ pub struct Renderer {
     pub algorithm: Algorithm,
     pub threads: Option<u32>,
     pub samples: u32,
+    pub stereo: Option<Stereo>,
+    pub filter: Filter,
+    pub filter_method: FilterMethod,
+    pub sampler: Sampler,
+    pub seed: u32,
+    pub adaptive: Option<AdaptiveSampling>,
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
     accelerator_type: Accelerator,
     bvh_build_method: BvhBuildMethod,
+    bvh_width: BvhWidth,
+    refit_threshold: Float,
+    bvh_cache: Option<PathBuf>,
+    // where the primitives of each solid of the world went in the accelerator
+    placements: Vec<Placement>,
+    eyes: Option<[Camera; 2]>,
+    pub(crate) filter_sampler: FilterSampler,
+    // index of the first sample taken per pixel, progressive passes continue the sequence
+    pub(crate) first_sample: u32,
+    pub(crate) control: RenderControl,
+    pub(crate) sample_counts: Vec<u32>,
+    // pool running the render jobs, renderers without one use the pool they are called from
+    thread_pool: Option<Arc<ThreadPool>>,
 }
 
 impl Renderer {
@@ -34,27 +73,55 @@ impl Renderer {
             threads,
             samples,
             bvh_build_method,
+            bvh_width,
+            refit_threshold,
+            bvh_cache,
+            stereo,
+            filter,
+            filter_method,
+            sampler,
+            seed,
+            adaptive,
+            tile_size,
+            tile_order,
+            thread_pool,
         } = builder.clone();
 
+        let thread_pool = thread_pool.or_else(|| {
+            let threads = threads?;
+            match ThreadPoolBuilder::new()
+                .num_threads(threads as usize)
+                .build()
+            {
+                Ok(pool) => Some(Arc::new(pool)),
+                Err(error) => {
+                    eprintln!("Could not create a pool of {} threads: {}", threads, error);
+                    None
+                }
+            }
+        });
+
         let mut camera = camera;
         camera.init(width as Float, height as Float);
+        let eyes = stereo.map(|stereo| stereo.eye_cameras(&camera, width, height));
 
-        let primitives = world.primitives();
+        let (accelerator_instance, placements) = run_in(&thread_pool, || {
+            build_accelerator(
+                &world,
+                &accelerator,
+                bvh_build_method,
+                bvh_width,
+                bvh_cache.as_deref(),
+            )
+        });
 
-        let accelerator_instance = match accelerator {
-            Accelerator::BruteForce => AcceleratorInstance::new_brute_force(primitives),
-            Accelerator::BoundingVolumeHierarchy => {
-                AcceleratorInstance::new_bounding_volume_hierarchy(bvh_build_method, primitives)
-            }
-        };
-
-        if let Some(trds) = threads {
-            _ = rayon::ThreadPoolBuilder::new()
-                .num_threads(trds as usize)
-                .build_global();
-        }
-
-        eprintln!("Threads: {}", rayon::current_num_threads());
+        eprintln!(
+            "Threads: {}",
+            thread_pool
+                .as_ref()
+                .map_or_else(rayon::current_num_threads, |pool| pool
+                    .current_num_threads())
+        );
 
         Renderer {
             width,
@@ -66,8 +133,26 @@ impl Renderer {
             algorithm,
             threads,
             samples,
+            stereo,
+            filter,
+            filter_method,
+            sampler,
+            seed,
+            adaptive,
+            tile_size,
+            tile_order,
             accelerator_type: accelerator,
             bvh_build_method,
+            bvh_width,
+            refit_threshold,
+            bvh_cache,
+            placements,
+            eyes,
+            filter_sampler: FilterSampler::new(&filter),
+            first_sample: 0,
+            control: RenderControl::default(),
+            sample_counts: Vec::new(),
+            thread_pool,
         }
     }
 
@@ -83,25 +168,220 @@ impl Renderer {
             threads: None,
             samples: 1,
             bvh_build_method: BvhBuildMethod::Sah,
+            bvh_width: BvhWidth::default(),
+            refit_threshold: DEFAULT_REFIT_THRESHOLD,
+            bvh_cache: None,
+            stereo: None,
+            filter: Filter::default(),
+            filter_method: FilterMethod::default(),
+            sampler: Sampler::default(),
+            seed: 0,
+            adaptive: None,
+            tile_size: 4,
+            tile_order: TileOrder::default(),
+            thread_pool: None,
+        }
+    }
+
+    // Builder with the settings of the renderer, the world is moved instead of copied
+    pub fn into_builder(mut self) -> RendererBuilder {
+        let world = std::mem::take(&mut self.world);
+        RendererBuilder {
+            world,
+            ..self.to_builder()
         }
     }
 
-    pub fn into_builder(self) -> RendererBuilder {
+    pub fn to_builder(&self) -> RendererBuilder {
         RendererBuilder {
             width: self.width,
             height: self.height,
-            accelerator: self.accelerator_type,
-            camera: self.camera,
-            world: self.world,
-            render_method: self.render_method,
-            algorithm: self.algorithm,
+            accelerator: self.accelerator_type.clone(),
+            camera: self.camera.clone(),
+            world: self.world.clone(),
+            render_method: self.render_method.clone(),
+            algorithm: self.algorithm.clone(),
             threads: self.threads,
             samples: self.samples,
             bvh_build_method: self.bvh_build_method,
+            bvh_width: self.bvh_width,
+            refit_threshold: self.refit_threshold,
+            bvh_cache: self.bvh_cache.clone(),
+            stereo: self.stereo,
+            filter: self.filter,
+            filter_method: self.filter_method,
+            sampler: self.sampler,
+            seed: self.seed,
+            adaptive: self.adaptive,
+            tile_size: self.tile_size,
+            tile_order: self.tile_order,
+            thread_pool: self.thread_pool.clone(),
         }
     }
 
+    // Prepares the camera again after it was modified, including the stereo eyes
+    pub fn update_camera(&mut self) {
+        self.camera.init(self.width as Float, self.height as Float);
+        self.eyes = self
+            .stereo
+            .map(|stereo| stereo.eye_cameras(&self.camera, self.width, self.height));
+    }
+
+    // Places a solid of the world, given by its position among the objects, with a new
+    // transform. The accelerator is refitted around the moved primitives, and built again when
+    // it cannot follow the change or refitting degraded it past the refit threshold
+    pub fn set_transform(&mut self, solid: usize, transform: &Transform) {
+        let Some(object) = self.world.objects.get(solid) else {
+            return;
+        };
+        let object = object.with_transform(transform);
+        // solids added to the world since the accelerator was built are not in it yet
+        let changes = match self.placements.get(solid) {
+            Some(Placement::Primitives(range)) => {
+                let primitives = object.primitives();
+                (primitives.len() == range.len()).then(|| (range.start, primitives, vec![]))
+            }
+            Some(Placement::Instance(idx)) => Some((0, vec![], vec![(*idx, transform.clone())])),
+            None => None,
+        };
+        self.world.objects[solid] = object;
+        let Some((first, primitives, moved)) = changes else {
+            return self.rebuild_accelerator();
+        };
+
+        let accelerator = &mut self.accelerator;
+        let threshold = self.refit_threshold;
+        if !run_in(&self.thread_pool, || {
+            accelerator.refit(first, primitives, moved, threshold)
+        }) {
+            self.rebuild_accelerator();
+        }
+    }
+
+    fn rebuild_accelerator(&mut self) {
+        let (world, accelerator) = (&self.world, &self.accelerator_type);
+        let (bvh_build_method, bvh_width) = (self.bvh_build_method, self.bvh_width);
+        // moved solids leave the cache to the scene as loaded
+        (self.accelerator, self.placements) = run_in(&self.thread_pool, || {
+            build_accelerator(world, accelerator, bvh_build_method, bvh_width, None)
+        });
+    }
+
+    // Renders the section of the output image, stereo layouts place both eyes within it
     pub fn render(&mut self, section: &Section) -> Vec<Color> {
+        let thread_pool = self.thread_pool.clone();
+        run_in(&thread_pool, || self.render_eyes(section))
+    }
+
+    fn render_eyes(&mut self, section: &Section) -> Vec<Color> {
+        match (self.stereo, self.eyes.take()) {
+            (Some(stereo), Some(mut eyes)) => {
+                let pixels =
+                    stereo::render(self, &stereo, &mut eyes, section, Renderer::render_view);
+                self.eyes = Some(eyes);
+                pixels
+            }
+            _ => self.render_view(section),
+        }
+    }
+
+    // Renders like render, reporting the progress of every job of the render method and stopping
+    // once the token is cancelled. Unfinished pixels of a cancelled render are black
+    pub fn render_with<F>(
+        &mut self,
+        section: &Section,
+        cancel: &CancellationToken,
+        progress: F,
+    ) -> Vec<Color>
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        self.control = RenderControl::new(cancel.clone(), Some(Arc::new(progress)));
+        let pixels = self.render(section);
+        self.control = RenderControl::default();
+        pixels
+    }
+
+    // Keeps adding passes of the configured samples to the section until the time budget is spent,
+    // a pass only starts when the last one suggests it fits and the first always runs.
+    // Returns the averaged pixels and the samples per pixel taken. Adaptive passes take a
+    // different count in every pixel, their average is returned and sample_counts holds the
+    // total of each pixel. Not built for wasm32, it has no clock to measure the budget with
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn render_for(
+        &mut self,
+        section: &Section,
+        budget: std::time::Duration,
+    ) -> (Vec<Color>, u32) {
+        use std::time::{Duration, Instant};
+
+        let start = Instant::now();
+        let len = (section.width * section.height) as usize;
+        let mut accumulated = vec![Color::default(); len];
+        let mut sample_counts = vec![0; len];
+        let mut passes = 0;
+        let mut last_pass = Duration::ZERO;
+        while passes == 0 || start.elapsed() + last_pass <= budget {
+            let pass_start = Instant::now();
+            self.first_sample = self.pass_first_sample(passes);
+            let pixels = self.render(section);
+            for (accumulated, pixel) in accumulated.iter_mut().zip(pixels) {
+                *accumulated = *accumulated + pixel;
+            }
+            let pass_counts = &self.sample_counts;
+            if self.adaptive.is_some() && pass_counts.len() == len {
+                for (count, pass_count) in sample_counts.iter_mut().zip(pass_counts) {
+                    *count += pass_count;
+                }
+            } else {
+                for count in sample_counts.iter_mut() {
+                    *count += self.samples;
+                }
+            }
+            passes += 1;
+            last_pass = pass_start.elapsed();
+        }
+        self.first_sample = 0;
+
+        let pixels = accumulated
+            .into_iter()
+            .map(|color| color / passes as Float)
+            .collect();
+        let total: u64 = sample_counts.iter().map(|&count| count as u64).sum();
+        let samples = (total as Float / len.max(1) as Float).round() as u32;
+        if self.adaptive.is_some() {
+            self.sample_counts = sample_counts;
+        }
+        (pixels, samples)
+    }
+
+    // Index of the first sample of the pass following the given passes, adaptive passes can take
+    // up to their maximum samples in every pixel
+    pub(crate) fn pass_first_sample(&self, passes: u32) -> u32 {
+        let samples = self
+            .adaptive
+            .map_or(self.samples, |adaptive| adaptive.max_samples);
+        passes * samples
+    }
+
+    // Samples taken by each pixel of the last adaptive render
+    pub fn sample_counts(&self) -> &[u32] {
+        &self.sample_counts
+    }
+
+    fn render_view(&mut self, section: &Section) -> Vec<Color> {
+        let sample: SampleFn = match self.algorithm {
+            Algorithm::Whitted => whitted::trace_sample,
+            Algorithm::PathTracing => path_tracing::trace_sample,
+        };
+        if let Some(adaptive) = self.adaptive {
+            let (pixels, sample_counts) = adaptive::render(self, section, &adaptive, sample);
+            self.sample_counts = sample_counts;
+            return pixels;
+        }
+        if let FilterMethod::Splatting = self.filter_method {
+            return filter::render_splatted(self, section, sample);
+        }
         let trace: TraceFn = match self.algorithm {
             Algorithm::Whitted => whitted::trace_ray,
             Algorithm::PathTracing => path_tracing::trace_ray,
@@ -111,6 +391,89 @@ impl Renderer {
     }
 }
 
+// Where the primitives of a solid of the world went, refits replace them there
+#[derive(Clone, Debug)]
+enum Placement {
+    Primitives(Range<usize>),
+    // position among the instances traced in two levels
+    Instance(usize),
+}
+
+// Builds the accelerator over the primitives of the world, binary BVHs trace the instances of
+// shared meshes in two levels and the other accelerators get a copy of the mesh for every
+// instance. Returns it with where the primitives of each solid went
+fn build_accelerator(
+    world: &World,
+    accelerator: &Accelerator,
+    bvh_build_method: BvhBuildMethod,
+    bvh_width: BvhWidth,
+    bvh_cache: Option<&Path>,
+) -> (AcceleratorInstance, Vec<Placement>) {
+    let two_level = matches!(accelerator, Accelerator::BoundingVolumeHierarchy)
+        && matches!(bvh_width, BvhWidth::Two)
+        && world
+            .objects
+            .iter()
+            .any(|object| matches!(object, Solid::Instance(..)));
+    let mut primitives: Vec<Triangle> = vec![];
+    let mut instances = vec![];
+    let mut placements = Vec::with_capacity(world.objects.len());
+    for object in world.objects.iter() {
+        match object {
+            Solid::Instance(transform, triangles) if two_level => {
+                placements.push(Placement::Instance(instances.len()));
+                instances.push((transform.clone(), triangles.clone()));
+            }
+            object => {
+                let first = primitives.len();
+                primitives.extend(object.primitives());
+                placements.push(Placement::Primitives(first..primitives.len()));
+            }
+        }
+    }
+
+    let accelerator = match accelerator {
+        Accelerator::BruteForce => AcceleratorInstance::new_brute_force(primitives),
+        Accelerator::BoundingVolumeHierarchy if two_level => {
+            AcceleratorInstance::new_two_level(bvh_build_method, primitives, instances)
+        }
+        Accelerator::BoundingVolumeHierarchy if matches!(bvh_width, BvhWidth::Two) => {
+            match bvh_cache {
+                Some(path) => AcceleratorInstance::new_cached_bounding_volume_hierarchy(
+                    bvh_build_method,
+                    primitives,
+                    path,
+                ),
+                None => AcceleratorInstance::new_bounding_volume_hierarchy(
+                    bvh_build_method,
+                    bvh_width,
+                    primitives,
+                ),
+            }
+        }
+        Accelerator::BoundingVolumeHierarchy => AcceleratorInstance::new_bounding_volume_hierarchy(
+            bvh_build_method,
+            bvh_width,
+            primitives,
+        ),
+        Accelerator::KdTree => AcceleratorInstance::new_kd_tree(primitives),
+        Accelerator::Grid => AcceleratorInstance::new_grid(primitives),
+    };
+    (accelerator, placements)
+}
+
+// Runs the operation in the pool when there is one, otherwise in the pool of the caller
+fn run_in<R, F>(thread_pool: &Option<Arc<ThreadPool>>, operation: F) -> R
+where
+    R: Send,
+    F: FnOnce() -> R + Send,
+{
+    match thread_pool {
+        Some(pool) => pool.install(operation),
+        None => operation(),
+    }
+}
+
 #[derive(Clone)]
 pub struct RendererBuilder {
     pub width: u32,
@@ -123,6 +486,18 @@ pub struct RendererBuilder {
     pub threads: Option<u32>,
     pub samples: u32,
     pub bvh_build_method: BvhBuildMethod,
+    pub bvh_width: BvhWidth,
+    pub refit_threshold: Float,
+    pub bvh_cache: Option<PathBuf>,
+    pub stereo: Option<Stereo>,
+    pub filter: Filter,
+    pub filter_method: FilterMethod,
+    pub sampler: Sampler,
+    pub seed: u32,
+    pub adaptive: Option<AdaptiveSampling>,
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    pub thread_pool: Option<Arc<ThreadPool>>,
 }
 
 impl RendererBuilder {
@@ -158,8 +533,16 @@ impl RendererBuilder {
         self.accelerator = accelerator;
         self
     }
+    // Renders with a pool of its own with this many threads, 0 uses the pool of the caller
     pub fn threads(&mut self, count: u32) -> &mut RendererBuilder {
         self.threads = if count > 0 { Some(count) } else { None };
+        self.thread_pool = None;
+        self
+    }
+    // Renders with a pool shared with the application or other renderers
+    pub fn thread_pool(&mut self, thread_pool: Arc<ThreadPool>) -> &mut RendererBuilder {
+        self.threads = Some(thread_pool.current_num_threads() as u32);
+        self.thread_pool = Some(thread_pool);
         self
     }
     pub fn samples(&mut self, samples: u32) -> &mut RendererBuilder {
@@ -170,7 +553,260 @@ impl RendererBuilder {
         self.bvh_build_method = bvh_build_method;
         self
     }
+    pub fn bvh_width(&mut self, bvh_width: BvhWidth) -> &mut RendererBuilder {
+        self.bvh_width = bvh_width;
+        self
+    }
+    // How much costlier than as built refits let a BVH get before building it again
+    pub fn refit_threshold(&mut self, refit_threshold: Float) -> &mut RendererBuilder {
+        self.refit_threshold = refit_threshold;
+        self
+    }
+    // File the binary BVH is saved to once built and loaded from while the primitives and the
+    // build method stay the same
+    pub fn bvh_cache(&mut self, path: PathBuf) -> &mut RendererBuilder {
+        self.bvh_cache = Some(path);
+        self
+    }
+    pub fn stereo(&mut self, stereo: Stereo) -> &mut RendererBuilder {
+        self.stereo = Some(stereo);
+        self
+    }
+    pub fn filter(&mut self, filter: Filter) -> &mut RendererBuilder {
+        self.filter = filter;
+        self
+    }
+    pub fn filter_method(&mut self, filter_method: FilterMethod) -> &mut RendererBuilder {
+        self.filter_method = filter_method;
+        self
+    }
+    pub fn sampler(&mut self, sampler: Sampler) -> &mut RendererBuilder {
+        self.sampler = sampler;
+        self
+    }
+    pub fn seed(&mut self, seed: u32) -> &mut RendererBuilder {
+        self.seed = seed;
+        self
+    }
+    pub fn adaptive(&mut self, adaptive: AdaptiveSampling) -> &mut RendererBuilder {
+        self.adaptive = Some(adaptive);
+        self
+    }
+    pub fn tile_size(&mut self, tile_size: u32) -> &mut RendererBuilder {
+        self.tile_size = tile_size.max(1);
+        self
+    }
+    pub fn tile_order(&mut self, tile_order: TileOrder) -> &mut RendererBuilder {
+        self.tile_order = tile_order;
+        self
+    }
     pub fn build(&mut self) -> Renderer {
         Renderer::new(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use ilios_types::{
+        color::Color, float::Float, material::Material, section::Section, solids::Solid,
+        transform::Transform,
+    };
+
+    use crate::{Accelerator, AdaptiveSampling, Algorithm, Renderer, demos};
+
+    #[test]
+    fn renders_are_reproducible_across_thread_counts() {
+        let render = |threads: u32| {
+            let mut renderer = Renderer::builder()
+                .width(32)
+                .height(16)
+                .samples(2)
+                .algorithm(Algorithm::PathTracing)
+                .accelerator(Accelerator::BoundingVolumeHierarchy)
+                .world(demos::cornell())
+                .seed(7)
+                .threads(threads)
+                .build();
+            let pixels = renderer.render(&Section::new(0, 0, 32, 16));
+            pixels
+                .iter()
+                .map(|color| (color.0.to_bits(), color.1.to_bits(), color.2.to_bits()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(render(1), render(4));
+    }
+
+    #[test]
+    fn renderers_keep_their_own_thread_pools() {
+        let single = Renderer::builder().threads(1).build();
+        let triple = Renderer::builder().threads(3).build();
+        let shared = Renderer::builder()
+            .thread_pool(triple.thread_pool.clone().unwrap())
+            .build();
+        let threads = |renderer: &Renderer| {
+            renderer
+                .thread_pool
+                .as_ref()
+                .map(|pool| pool.current_num_threads())
+        };
+        assert_eq!(threads(&single), Some(1));
+        assert_eq!(threads(&triple), Some(3));
+        assert_eq!(threads(&shared), Some(3));
+        assert!(threads(&Renderer::builder().build()).is_none());
+    }
+
+    #[test]
+    fn time_budget_renders_whole_passes() {
+        let mut renderer = Renderer::builder()
+            .width(8)
+            .height(8)
+            .samples(2)
+            .algorithm(Algorithm::PathTracing)
+            .world(demos::simple())
+            .build();
+        let section = Section::new(0, 0, 8, 8);
+        let (pixels, samples) = renderer.render_for(&section, Duration::ZERO);
+        assert_eq!(pixels.len(), 64);
+        assert_eq!(samples, 2);
+
+        let (_, samples) = renderer.render_for(&section, Duration::from_millis(50));
+        assert!(samples >= 2 && samples % 2 == 0);
+    }
+
+    #[test]
+    fn time_budget_counts_adaptive_samples() {
+        let mut renderer = Renderer::builder()
+            .width(32)
+            .height(32)
+            .algorithm(Algorithm::PathTracing)
+            .adaptive(AdaptiveSampling::new(2, 16, 0.2))
+            .world(demos::simple())
+            .build();
+        let section = Section::new(0, 0, 32, 32);
+        let (_, samples) = renderer.render_for(&section, Duration::ZERO);
+        let counts = renderer.sample_counts();
+        assert_eq!(counts.len(), 1024);
+        assert!(counts.iter().all(|&count| (2..=16).contains(&count)));
+        let total: u32 = counts.iter().sum();
+        assert_eq!(samples, (total as Float / 1024.0).round() as u32);
+        assert!(samples < 16);
+    }
+
+    #[test]
+    fn adaptive_passes_take_new_samples() {
+        let build = |samples| {
+            Renderer::builder()
+                .width(16)
+                .height(16)
+                .algorithm(Algorithm::PathTracing)
+                .adaptive(AdaptiveSampling::new(samples, samples, 0.0))
+                .world(demos::simple())
+                .build()
+        };
+        let section = Section::new(0, 0, 16, 16);
+        let mut renderer = build(4);
+        let first = renderer.render(&section);
+        renderer.first_sample = renderer.pass_first_sample(1);
+        let second = renderer.render(&section);
+        assert!(
+            first
+                .iter()
+                .zip(&second)
+                .any(|(first, second)| first.0 != second.0)
+        );
+
+        // two passes average the same samples as one pass taking twice as many
+        let single = build(8).render(&section);
+        for ((first, second), single) in first.iter().zip(&second).zip(&single) {
+            let average = (*first + *second) / 2.0;
+            assert!((average.0 - single.0).abs() < 1e-4);
+            assert!((average.1 - single.1).abs() < 1e-4);
+            assert!((average.2 - single.2).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn moved_solids_render_like_rebuilt_scenes() {
+        let mut world = demos::cornell();
+        let mesh = Arc::new(
+            Solid::Torus(1.0, 3.0, 12, 24, Transform::default(), Material::green()).primitives(),
+        );
+        world
+            .objects
+            .push(Solid::Instance(Transform::translate(0.0, 0.0, 10.0), mesh));
+        let instance = world.objects.len() - 1;
+        for (accelerator, refit_threshold) in [
+            (Accelerator::BoundingVolumeHierarchy, Float::INFINITY),
+            (Accelerator::BoundingVolumeHierarchy, 1.0),
+            (Accelerator::KdTree, Float::INFINITY),
+        ] {
+            let mut renderer = Renderer::builder()
+                .width(32)
+                .height(24)
+                .accelerator(accelerator)
+                .refit_threshold(refit_threshold)
+                .world(world.clone())
+                .build();
+            renderer.set_transform(
+                0,
+                &Transform::combine(&[
+                    Transform::scale(5.0, 5.0, 5.0),
+                    Transform::translate(-12.0, 3.0, -6.0),
+                ]),
+            );
+            renderer.set_transform(
+                instance,
+                &Transform::combine(&[
+                    Transform::rotate(0.5, 0.0, 0.0),
+                    Transform::translate(4.0, -2.0, 8.0),
+                ]),
+            );
+            let section = Section::new(0, 0, 32, 24);
+            let bits = |pixels: Vec<Color>| {
+                pixels
+                    .iter()
+                    .map(|color| (color.0.to_bits(), color.1.to_bits(), color.2.to_bits()))
+                    .collect::<Vec<_>>()
+            };
+            let refitted = bits(renderer.render(&section));
+            let rebuilt = bits(renderer.to_builder().build().render(&section));
+            assert_eq!(refitted, rebuilt);
+        }
+    }
+
+    #[test]
+    fn transforms_replace_the_placement_of_solids() {
+        let mut world = demos::simple();
+        let triangle = world
+            .objects
+            .iter()
+            .position(|object| matches!(object, Solid::Triangle(..)))
+            .unwrap();
+        let mut renderer = Renderer::builder()
+            .width(32)
+            .height(24)
+            .algorithm(Algorithm::PathTracing)
+            .accelerator(Accelerator::BoundingVolumeHierarchy)
+            .world(world.clone())
+            .build();
+        let transform = Transform::translate(0.0, 2.0, 0.0);
+        renderer.set_transform(triangle, &transform);
+        renderer.set_transform(triangle, &transform);
+
+        world.objects[triangle] = world.objects[triangle].with_transform(&transform);
+        let mut placed = renderer.to_builder().world(world).build();
+        let section = Section::new(0, 0, 32, 24);
+        let bits = |pixels: Vec<Color>| {
+            pixels
+                .iter()
+                .map(|color| (color.0.to_bits(), color.1.to_bits(), color.2.to_bits()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            bits(renderer.render(&section)),
+            bits(placed.render(&section))
+        );
+    }
+}
@@ -1,11 +1,7 @@
 const MAX: Float = 4294967295.0;
 
-use std::sync::atomic::{AtomicU32, Ordering};
-
 use ilios_types::float::Float;
 
-static SEED: AtomicU32 = AtomicU32::new(123456789);
-
 pub struct XorRng {
     x: u32,
     y: u32,
@@ -14,15 +10,13 @@ pub struct XorRng {
 }
 
 impl XorRng {
-    pub fn new() -> XorRng {
-        let mut rng = XorRng {
-            x: SEED.load(Ordering::SeqCst),
+    pub fn new(seed: u32) -> XorRng {
+        XorRng {
+            x: 123456789 ^ seed,
             y: 362436069,
             z: 521288629,
             w: 88675123,
-        };
-        SEED.store(rng.gen_u32(), Ordering::SeqCst);
-        rng
+        }
     }
 
     pub fn r#gen(&mut self) -> Float {
@@ -41,6 +35,10 @@ impl XorRng {
 
 pub trait Rng {
     fn r#gen(&mut self) -> Float;
+
+    // Starts a new sample of a pixel, dimension aware generators return the
+    // dimensions of that sample from the following calls to gen
+    fn start_sample(&mut self, _pixel: (u32, u32), _index: u32) {}
 }
 
 impl Rng for XorRng {
@@ -0,0 +1,322 @@
+use std::sync::OnceLock;
+
+use ilios_types::float::Float;
+
+use crate::rng::{Rng, XorRng};
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+// Direction numbers for the first four Sobol dimensions, from the Joe-Kuo primitive polynomials
+const SOBOL_DIRECTIONS: [[u32; 32]; 4] = [
+    sobol_directions(0, 0, [0, 0, 0]),
+    sobol_directions(1, 0, [1, 0, 0]),
+    sobol_directions(2, 1, [1, 3, 0]),
+    sobol_directions(3, 1, [1, 3, 1]),
+];
+
+const BLUE_NOISE_SIZE: usize = 32;
+
+// Sequences used to generate the sample values
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Sampler {
+    // independent random numbers
+    #[default]
+    Random,
+    // jittered strata, shuffled independently for every dimension
+    Stratified,
+    // Halton sequence, randomly rotated per pixel
+    Halton,
+    // Sobol sequence with Owen scrambling, padded every four dimensions
+    Sobol,
+    // Sobol sequence rotated per pixel by a blue noise mask, spreads the error as blue noise
+    BlueNoise,
+}
+
+impl Sampler {
+    // Creates the generator used by one rendering job, the values of every pixel sample
+    // depend only on the render seed, the pixel and the sample index
+    pub(crate) fn rng(&self, samples: u32, seed: u32) -> SamplerRng {
+        SamplerRng {
+            sampler: *self,
+            samples: samples.max(1),
+            seed,
+            random: XorRng::new(hash(seed, 0)),
+            sample: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct SampleState {
+    seed: u32,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32,
+}
+
+// Rng whose values walk the dimensions of the current pixel sample
+pub(crate) struct SamplerRng {
+    sampler: Sampler,
+    samples: u32,
+    seed: u32,
+    random: XorRng,
+    sample: Option<SampleState>,
+}
+
+impl Rng for SamplerRng {
+    fn r#gen(&mut self) -> Float {
+        let Some(state) = self.sample.as_mut() else {
+            return self.random.r#gen();
+        };
+        let SampleState {
+            seed,
+            pixel,
+            index,
+            dimension,
+        } = *state;
+        state.dimension += 1;
+        let dimension_seed = hash(seed, dimension);
+
+        match self.sampler {
+            Sampler::Random => self.random.r#gen(),
+            Sampler::Stratified => {
+                let stratum = permute(index % self.samples, self.samples, dimension_seed);
+                (stratum as Float + self.random.r#gen()) / self.samples as Float
+            }
+            Sampler::Halton => match PRIMES.get(dimension as usize) {
+                Some(base) => {
+                    let rotation = to_float(dimension_seed);
+                    (radical_inverse(*base, index) + rotation).fract()
+                }
+                None => self.random.r#gen(),
+            },
+            Sampler::Sobol => to_float(owen_sobol(index, dimension, seed)),
+            Sampler::BlueNoise => {
+                let mask = blue_noise();
+                let x = pixel.0.wrapping_add(hash(dimension, 1)) as usize % BLUE_NOISE_SIZE;
+                let y = pixel.1.wrapping_add(hash(dimension, 2)) as usize % BLUE_NOISE_SIZE;
+                let rotation = mask[y * BLUE_NOISE_SIZE + x];
+                (to_float(owen_sobol(index, dimension, 0)) + rotation).fract()
+            }
+        }
+    }
+
+    fn start_sample(&mut self, pixel: (u32, u32), index: u32) {
+        let seed = hash(hash(self.seed, pixel.0), pixel.1);
+        self.random = XorRng::new(hash(seed, index));
+        self.sample = Some(SampleState {
+            seed,
+            pixel,
+            index,
+            dimension: 0,
+        });
+    }
+}
+
+fn to_float(value: u32) -> Float {
+    (value >> 8) as Float / (1u32 << 24) as Float
+}
+
+fn hash(a: u32, b: u32) -> u32 {
+    let mut x = a ^ b.wrapping_mul(0x9e3779b9).rotate_left(16);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^ (x >> 16)
+}
+
+fn radical_inverse(base: u32, mut index: u32) -> Float {
+    let inverse_base = 1.0 / base as Float;
+    let mut inverse = inverse_base;
+    let mut value = 0.0;
+    while index > 0 {
+        value += (index % base) as Float * inverse;
+        index /= base;
+        inverse *= inverse_base;
+    }
+    value.min(1.0 - Float::EPSILON)
+}
+
+// Random permutation of the integers below length, from Kensler's "Correlated Multi-Jittered Sampling"
+fn permute(mut i: u32, length: u32, seed: u32) -> u32 {
+    let mut w = length - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < length {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % length
+}
+
+const fn sobol_directions(degree: u32, coefficients: u32, initial: [u32; 3]) -> [u32; 32] {
+    let mut directions = [0; 32];
+    let mut i = 0;
+    while i < 32 {
+        directions[i] = if degree == 0 {
+            1 << (31 - i)
+        } else if i < degree as usize {
+            initial[i] << (31 - i)
+        } else {
+            let s = degree as usize;
+            let mut value = directions[i - s] ^ (directions[i - s] >> s);
+            let mut k = 1;
+            while k < s {
+                value ^= ((coefficients >> (s - 1 - k)) & 1) * directions[i - k];
+                k += 1;
+            }
+            value
+        };
+        i += 1;
+    }
+    directions
+}
+
+fn sobol(index: u32, dimension: usize) -> u32 {
+    let directions = &SOBOL_DIRECTIONS[dimension];
+    let mut value = 0;
+    let mut index = index;
+    let mut bit = 0;
+    while index > 0 {
+        if index & 1 == 1 {
+            value ^= directions[bit];
+        }
+        index >>= 1;
+        bit += 1;
+    }
+    value
+}
+
+// Hash based Owen scrambling, from Burley's "Practical Hash-based Owen Scrambling"
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// Dimensions are grouped in fours, each group shuffles the sample order to stay decorrelated
+fn owen_sobol(index: u32, dimension: u32, seed: u32) -> u32 {
+    let group_seed = hash(seed, dimension / 4);
+    let shuffled = nested_uniform_scramble(index, group_seed);
+    let value = sobol(shuffled, (dimension % 4) as usize);
+    nested_uniform_scramble(value, hash(group_seed, dimension))
+}
+
+// Tileable blue noise mask built with a simplified void-and-cluster method: every pixel is ranked
+// by the order in which it fills the largest remaining void
+fn blue_noise() -> &'static [Float] {
+    static MASK: OnceLock<Vec<Float>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        let size = BLUE_NOISE_SIZE;
+        let count = size * size;
+        let sigma: Float = 1.5;
+        let kernel: Vec<Float> = (0..count)
+            .map(|idx| {
+                let wrap = |d: usize| d.min(size - d) as Float;
+                let (dx, dy) = (wrap(idx % size), wrap(idx / size));
+                (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+            })
+            .collect();
+
+        let mut energy: Vec<Float> = vec![0.0; count];
+        let mut mask: Vec<Float> = vec![-1.0; count];
+        for rank in 0..count {
+            let void = (0..count)
+                .filter(|idx| mask[*idx] < 0.0)
+                .min_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+                .unwrap_or(0);
+            mask[void] = (rank as Float + 0.5) / count as Float;
+            let (vx, vy) = (void % size, void / size);
+            for (idx, value) in energy.iter_mut().enumerate() {
+                let dx = (idx % size + size - vx) % size;
+                let dy = (idx / size + size - vy) % size;
+                *value += kernel[dy * size + dx];
+            }
+        }
+        mask
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sampler, blue_noise, owen_sobol, radical_inverse, sobol, to_float};
+    use crate::rng::Rng;
+
+    #[test]
+    fn sobol_matches_reference_values() {
+        let values: Vec<f32> = (0..4).map(|idx| to_float(sobol(idx, 1))).collect();
+        assert_eq!(values, vec![0.0, 0.5, 0.75, 0.25]);
+        assert_eq!(radical_inverse(3, 5), 7.0 / 9.0);
+    }
+
+    #[test]
+    fn owen_sobol_is_stratified() {
+        for dimension in [0, 2, 5] {
+            let mut strata = [false; 16];
+            for idx in 0..16 {
+                let value = to_float(owen_sobol(idx, dimension, 1234));
+                strata[(value * 16.0) as usize] = true;
+            }
+            assert!(strata.iter().all(|s| *s));
+        }
+    }
+
+    #[test]
+    fn samplers_stay_in_unit_interval() {
+        let samplers = [
+            Sampler::Random,
+            Sampler::Stratified,
+            Sampler::Halton,
+            Sampler::Sobol,
+            Sampler::BlueNoise,
+        ];
+        for sampler in samplers {
+            let mut rng = sampler.rng(8, 0);
+            for index in 0..8 {
+                rng.start_sample((3, 7), index);
+                for _ in 0..40 {
+                    let value = rng.r#gen();
+                    assert!((0.0..1.0).contains(&value));
+                }
+            }
+        }
+        assert!(
+            blue_noise()
+                .iter()
+                .all(|value| *value > 0.0 && *value < 1.0)
+        );
+    }
+}
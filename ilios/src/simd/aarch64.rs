@@ -1,7 +1,8 @@
 use std::arch::aarch64::{
     float32x4_t, uint32x4_t, vaddq_f32, vaddvq_f32, vaddvq_u32, vandq_u32, vceqq_f32, vcgeq_f32,
     vcgtq_f32, vcleq_f32, vcltq_f32, vdivq_f32, vfmaq_f32, vgetq_lane_f32, vld1q_dup_f32,
-    vld1q_lane_f32, vmulq_f32, vreinterpretq_f32_u32, vreinterpretq_u32_f32, vsubq_f32,
+    vld1q_lane_f32, vld1q_u32, vmaxnmq_f32, vminnmq_f32, vmulq_f32, vreinterpretq_f32_u32,
+    vreinterpretq_u32_f32, vsubq_f32,
 };
 
 pub type F32x4 = float32x4_t;
@@ -88,6 +89,14 @@ pub fn mul_add(a: F32x4, b: F32x4, acc: F32x4) -> F32x4 {
     unsafe { vfmaq_f32(acc, a, b) }
 }
 
+pub fn min(a: F32x4, b: F32x4) -> F32x4 {
+    unsafe { vminnmq_f32(a, b) }
+}
+
+pub fn max(a: F32x4, b: F32x4) -> F32x4 {
+    unsafe { vmaxnmq_f32(a, b) }
+}
+
 pub fn cross(x1: F32x4, y1: F32x4, z1: F32x4, x2: F32x4, y2: F32x4, z2: F32x4) -> [F32x4; 3] {
     let x = sub(mul(y1, z2), mul(z1, y2));
     let y = sub(mul(z1, x2), mul(x1, z2));
@@ -129,6 +138,12 @@ pub fn is_zero(a: Mask) -> bool {
     unsafe { vaddvq_u32(a) == 0 }
 }
 
+// One bit per lane set in the mask, the first lane in the lowest bit
+pub fn bitmask(a: Mask) -> u32 {
+    let bits: [u32; 4] = [1, 2, 4, 8];
+    unsafe { vaddvq_u32(vandq_u32(a, vld1q_u32(bits.as_ptr()))) }
+}
+
 pub fn and_f32x4(a: F32x4, b: Mask) -> F32x4 {
     unsafe {
         let a_mask = vreinterpretq_u32_f32(a);
@@ -1,14 +1,17 @@
 #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+#[allow(dead_code)]
 pub mod aarch64;
 #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
 pub use aarch64::*;
 
-#[cfg(all(target_arch = "wasm32"))]
+#[cfg(target_arch = "wasm32")]
+#[allow(dead_code)]
 pub mod wasm32;
-#[cfg(all(target_arch = "wasm32"))]
+#[cfg(target_arch = "wasm32")]
 pub use wasm32::*;
 
 #[cfg(all(not(target_arch = "aarch64"), not(target_arch = "wasm32")))]
+#[allow(dead_code)]
 pub mod reference;
 #[cfg(all(not(target_arch = "aarch64"), not(target_arch = "wasm32")))]
 pub use reference::*;
@@ -36,7 +39,7 @@ mod tests {
                 simd::splat(4.0),
                 simd::splat(5.0),
             )
-            .map(|v| ComparableF32x4(v)),
+            .map(ComparableF32x4),
             [
                 ComparableF32x4(simd::splat(-2.0)),
                 ComparableF32x4(simd::splat(4.0)),
@@ -59,4 +62,32 @@ mod tests {
             ComparableF32x4(simd::splat(26.0)),
         );
     }
+
+    #[test]
+    fn test_box_ops() {
+        let a = simd::new(1.0, 5.0, -2.0, 0.0);
+        let b = simd::new(3.0, 4.0, -1.0, 0.0);
+        assert_eq!(
+            ComparableF32x4(simd::min(a, b)),
+            ComparableF32x4(simd::new(1.0, 4.0, -2.0, 0.0))
+        );
+        assert_eq!(
+            ComparableF32x4(simd::max(a, b)),
+            ComparableF32x4(simd::new(3.0, 5.0, -1.0, 0.0))
+        );
+        assert_eq!(simd::bitmask(simd::lt(a, b)), 0b0101);
+        assert_eq!(simd::bitmask(simd::lte(a, b)), 0b1101);
+
+        // lanes with a NaN take the other operand, rays starting on a slab plane get them
+        let c = simd::new(f32::NAN, 2.0, f32::NAN, 0.0);
+        let d = simd::new(1.0, f32::NAN, -1.0, 0.0);
+        assert_eq!(
+            ComparableF32x4(simd::min(c, d)),
+            ComparableF32x4(simd::new(1.0, 2.0, -1.0, 0.0))
+        );
+        assert_eq!(
+            ComparableF32x4(simd::max(c, d)),
+            ComparableF32x4(simd::new(1.0, 2.0, -1.0, 0.0))
+        );
+    }
 }
@@ -1,6 +1,9 @@
 pub type F32x4 = [f32; 4];
 pub type Mask = [u32; 4];
 
+#[derive(Debug, PartialEq)]
+pub struct ComparableF32x4(pub F32x4);
+
 pub fn set(vector: F32x4, value: f32, lane: usize) -> F32x4 {
     let idx = lane % 4;
     let mut result = vector;
@@ -54,6 +57,24 @@ pub fn mul_add(a: F32x4, b: F32x4, c: F32x4) -> F32x4 {
     ]
 }
 
+pub fn min(a: F32x4, b: F32x4) -> F32x4 {
+    [
+        a[0].min(b[0]),
+        a[1].min(b[1]),
+        a[2].min(b[2]),
+        a[3].min(b[3]),
+    ]
+}
+
+pub fn max(a: F32x4, b: F32x4) -> F32x4 {
+    [
+        a[0].max(b[0]),
+        a[1].max(b[1]),
+        a[2].max(b[2]),
+        a[3].max(b[3]),
+    ]
+}
+
 pub fn cross(x1: F32x4, y1: F32x4, z1: F32x4, x2: F32x4, y2: F32x4, z2: F32x4) -> [F32x4; 3] {
     let x = sub(mul(y1, z2), mul(z1, y2));
     let y = sub(mul(z1, x2), mul(x1, z2));
@@ -117,7 +138,12 @@ pub fn and_mask(a: Mask, b: Mask) -> Mask {
 }
 
 pub fn is_zero(a: Mask) -> bool {
-    (a[0] + a[1] + a[2] + a[3]) == 0
+    (a[0] | a[1] | a[2] | a[3]) == 0
+}
+
+// One bit per lane set in the mask, the first lane in the lowest bit
+pub fn bitmask(a: Mask) -> u32 {
+    (a[0] & 1) | (a[1] & 1) << 1 | (a[2] & 1) << 2 | (a[3] & 1) << 3
 }
 
 pub fn and_f32x4(a: F32x4, b: Mask) -> F32x4 {
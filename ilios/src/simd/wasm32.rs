@@ -1,7 +1,7 @@
 use std::arch::wasm32::{
     f32x4, f32x4_add, f32x4_div, f32x4_eq, f32x4_extract_lane, f32x4_ge, f32x4_gt, f32x4_le,
-    f32x4_lt, f32x4_mul, f32x4_replace_lane, f32x4_splat, f32x4_sub, u32x4_extract_lane, v128,
-    v128_and,
+    f32x4_lt, f32x4_max, f32x4_min, f32x4_mul, f32x4_ne, f32x4_replace_lane, f32x4_splat,
+    f32x4_sub, i32x4_bitmask, u32x4_extract_lane, v128, v128_and, v128_bitselect,
 };
 
 pub type F32x4 = v128;
@@ -72,6 +72,21 @@ pub fn mul_add(a: F32x4, b: F32x4, acc: F32x4) -> F32x4 {
     f32x4_add(acc, f32x4_mul(a, b))
 }
 
+// f32x4_min and f32x4_max return NaN when either lane is, lanes with a NaN take the other
+// operand instead like f32::min and max and the NEON minnm and maxnm do
+fn ignoring_nan(a: F32x4, b: F32x4, result: F32x4) -> F32x4 {
+    let result = v128_bitselect(b, result, f32x4_ne(a, a));
+    v128_bitselect(a, result, f32x4_ne(b, b))
+}
+
+pub fn min(a: F32x4, b: F32x4) -> F32x4 {
+    ignoring_nan(a, b, f32x4_min(a, b))
+}
+
+pub fn max(a: F32x4, b: F32x4) -> F32x4 {
+    ignoring_nan(a, b, f32x4_max(a, b))
+}
+
 pub fn cross(x1: F32x4, y1: F32x4, z1: F32x4, x2: F32x4, y2: F32x4, z2: F32x4) -> [F32x4; 3] {
     let x = sub(mul(y1, z2), mul(z1, y2));
     let y = sub(mul(z1, x2), mul(x1, z2));
@@ -119,6 +134,11 @@ pub fn is_zero(a: Mask) -> bool {
         == 0
 }
 
+// One bit per lane set in the mask, the first lane in the lowest bit
+pub fn bitmask(a: Mask) -> u32 {
+    i32x4_bitmask(a) as u32
+}
+
 pub fn and_f32x4(a: F32x4, b: Mask) -> F32x4 {
     v128_and(a, b)
 }
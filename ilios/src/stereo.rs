@@ -0,0 +1,191 @@
+use ilios_types::{camera::Camera, color::Color, float::Float, section::Section};
+
+use crate::Renderer;
+
+#[derive(Clone, Copy, Debug)]
+pub enum StereoLayout {
+    // left eye on the left half of the image, right eye on the right half
+    SideBySide,
+    // left eye on the top half of the image, right eye on the bottom half
+    TopBottom,
+    // red channel from the left eye, green and blue from the right eye
+    Anaglyph,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Stereo {
+    pub layout: StereoLayout,
+    // distance between the eyes, in scene units
+    pub interocular_distance: Float,
+    // distance from the camera at which both eyes see the same image
+    pub convergence_distance: Float,
+}
+
+impl Stereo {
+    pub fn new(
+        layout: StereoLayout,
+        interocular_distance: Float,
+        convergence_distance: Float,
+    ) -> Stereo {
+        Stereo {
+            layout,
+            interocular_distance,
+            convergence_distance,
+        }
+    }
+
+    // Resolution rendered for the eye, 0 for the left one and 1 for the right one, given the
+    // size of the whole output image. The right eye takes the column or row left over by odd
+    // sizes
+    pub fn eye_size(&self, eye: usize, width: u32, height: u32) -> (u32, u32) {
+        let half = |size: u32| if eye == 0 { size / 2 } else { size - size / 2 };
+        match self.layout {
+            StereoLayout::SideBySide => (half(width), height),
+            StereoLayout::TopBottom => (width, half(height)),
+            StereoLayout::Anaglyph => (width, height),
+        }
+    }
+
+    // Where the image of each eye starts within the output image
+    fn eye_origin(&self, eye: usize, width: u32, height: u32) -> (u32, u32) {
+        let (left_width, left_height) = self.eye_size(0, width, height);
+        match self.layout {
+            StereoLayout::SideBySide => (eye as u32 * left_width, 0),
+            StereoLayout::TopBottom => (0, eye as u32 * left_height),
+            StereoLayout::Anaglyph => (0, 0),
+        }
+    }
+
+    // Left and right eye cameras, initialized for the resolution of each eye
+    pub fn eye_cameras(&self, camera: &Camera, width: u32, height: u32) -> [Camera; 2] {
+        let offset = self.interocular_distance / 2.0;
+        [(0, -offset), (1, offset)].map(|(eye, offset)| {
+            let (eye_width, eye_height) = self.eye_size(eye, width, height);
+            let mut camera = camera.clone();
+            camera.init(eye_width as Float, eye_height as Float);
+            camera.stereo_eye(offset, self.convergence_distance)
+        })
+    }
+}
+
+// Renders both eyes into the section, adaptive renders leave the samples taken by each pixel
+// in the sample counts of the renderer, placed like the pixels
+pub(crate) fn render(
+    renderer: &mut Renderer,
+    stereo: &Stereo,
+    eyes: &mut [Camera; 2],
+    section: &Section,
+    render_view: fn(&mut Renderer, &Section) -> Vec<Color>,
+) -> Vec<Color> {
+    let mut render_eye = |renderer: &mut Renderer, eye: usize, section: &Section| {
+        std::mem::swap(&mut renderer.camera, &mut eyes[eye]);
+        let pixels = render_view(renderer, section);
+        std::mem::swap(&mut renderer.camera, &mut eyes[eye]);
+        (pixels, std::mem::take(&mut renderer.sample_counts))
+    };
+
+    if let StereoLayout::Anaglyph = stereo.layout {
+        let (left, left_counts) = render_eye(renderer, 0, section);
+        let (right, right_counts) = render_eye(renderer, 1, section);
+        // each pixel counts the samples of the eye that took fewer
+        renderer.sample_counts = left_counts
+            .into_iter()
+            .zip(right_counts)
+            .map(|(left, right)| left.min(right))
+            .collect();
+        return left
+            .into_iter()
+            .zip(right)
+            .map(|(Color(r, _, _), Color(_, g, b))| Color(r, g, b))
+            .collect();
+    }
+
+    let len = (section.width * section.height) as usize;
+    let mut pixels = vec![Color::default(); len];
+    let mut sample_counts = Vec::new();
+    for eye in 0..2 {
+        let (eye_width, eye_height) = stereo.eye_size(eye, renderer.width, renderer.height);
+        let (origin_x, origin_y) = stereo.eye_origin(eye, renderer.width, renderer.height);
+        // part of the requested section covered by this eye, in eye coordinates
+        let left = section.left.max(origin_x);
+        let top = section.top.max(origin_y);
+        let right = (section.left + section.width).min(origin_x + eye_width);
+        let bottom = (section.top + section.height).min(origin_y + eye_height);
+        if left >= right || top >= bottom {
+            continue;
+        }
+        let eye_section = Section::new(left - origin_x, top - origin_y, right - left, bottom - top);
+        let (colors, counts) = render_eye(renderer, eye, &eye_section);
+        if !counts.is_empty() {
+            sample_counts.resize(len, 0);
+        }
+        for (idx, color) in colors.into_iter().enumerate() {
+            let x = left + idx as u32 % eye_section.width - section.left;
+            let y = top + idx as u32 / eye_section.width - section.top;
+            let pixel = (y * section.width + x) as usize;
+            pixels[pixel] = color;
+            if let Some(count) = counts.get(idx) {
+                sample_counts[pixel] = *count;
+            }
+        }
+    }
+    renderer.sample_counts = sample_counts;
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use ilios_types::section::Section;
+
+    use super::{Stereo, StereoLayout};
+    use crate::{AdaptiveSampling, Algorithm, Renderer, demos};
+
+    #[test]
+    fn eyes_cover_odd_sized_images() {
+        for layout in [StereoLayout::SideBySide, StereoLayout::TopBottom] {
+            let stereo = Stereo::new(layout, 0.1, 10.0);
+            let (width, height) = (33, 25);
+            let (right_x, right_y) = stereo.eye_origin(1, width, height);
+            let (right_width, right_height) = stereo.eye_size(1, width, height);
+            assert_eq!(
+                (right_x + right_width, right_y + right_height),
+                (width, height)
+            );
+            let (left_width, left_height) = stereo.eye_size(0, width, height);
+            assert_eq!(
+                stereo.eye_origin(1, width, height),
+                match layout {
+                    StereoLayout::SideBySide => (left_width, 0),
+                    _ => (0, left_height),
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn adaptive_eyes_count_the_samples_of_the_whole_image() {
+        for layout in [
+            StereoLayout::SideBySide,
+            StereoLayout::TopBottom,
+            StereoLayout::Anaglyph,
+        ] {
+            let mut renderer = Renderer::builder()
+                .width(17)
+                .height(9)
+                .algorithm(Algorithm::PathTracing)
+                .adaptive(AdaptiveSampling::new(2, 8, 0.2))
+                .stereo(Stereo::new(layout, 0.1, 10.0))
+                .world(demos::simple())
+                .build();
+            renderer.render(&Section::new(0, 0, 17, 9));
+            let counts = renderer.sample_counts();
+            assert_eq!(counts.len(), 17 * 9);
+            assert!(counts.iter().all(|&count| (2..=8).contains(&count)));
+
+            // sections across both eyes take the counts of the pixels they cover
+            renderer.render(&Section::new(5, 2, 8, 5));
+            assert_eq!(renderer.sample_counts().len(), 8 * 5);
+            assert!(renderer.sample_counts().iter().all(|&count| count >= 2));
+        }
+    }
+}
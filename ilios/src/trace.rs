@@ -1,7 +1,11 @@
-use ilios_types::ray::Ray;
+use ilios_types::{float::Float, ray::Ray};
 
-use super::geometry::PackedTriangles;
+use super::closest_primitive::ClosestPrimitive;
 
 pub trait Trace {
-    fn trace(&self, ray: &Ray) -> Option<Vec<&PackedTriangles>>;
+    // Nearest primitive hit by the ray within its max distance
+    fn closest_hit(&self, ray: &Ray) -> Option<ClosestPrimitive<'_>>;
+
+    // Whether anything is hit before the max distance, stops at the first hit found
+    fn occluded(&self, ray: &Ray, max_distance: Float) -> bool;
 }
@@ -1,7 +1,7 @@
 use anyhow::{Result, anyhow};
 use ilios_types::{
-    camera::Camera,
-    geometry::{Point, Triangle},
+    camera::{Camera, FieldOfView, Projection, Shutter},
+    geometry::{Point, Triangle, Vector},
     material::Material,
     solids::Solid,
     transform::Transform,
@@ -11,11 +11,16 @@ use serde::de::DeserializeOwned;
 use std::{collections::HashMap, sync::Arc};
 
 use crate::{
-    config_types::{self, CameraConfig, SceneConfig, TransformConfig},
+    config_types::{
+        self, CameraConfig, CameraPlacementConfig, ProjectionConfig, SceneConfig, TransformConfig,
+    },
     loaders::Loader,
     parsers,
 };
 
+// full frame 35mm sensor height, in millimeters
+const DEFAULT_SENSOR_HEIGHT: f32 = 24.0;
+
 pub struct ConfigLoader {
     loader: Box<dyn Loader>,
 }
@@ -32,44 +37,78 @@ impl ConfigLoader {
             .load("camera.json")
             .map(deserialize::<CameraConfig>)??;
         let CameraConfig {
-            eye,
-            left_bottom,
-            left_top,
-            right_top,
-        }: CameraConfig = config;
-
-        Ok(Camera::new(
-            eye.into(),
-            left_top.into(),
-            left_bottom.into(),
-            right_top.into(),
-        ))
-    }
-
-    pub fn world(&self) -> Result<World> {
-        let into_transform = |cfg: &TransformConfig| -> Transform {
-            match cfg {
-                TransformConfig::Rotate { values } => {
-                    Transform::rotate(values[0], values[1], values[2])
-                }
-                TransformConfig::Translate { values } => {
-                    Transform::translate(values[0], values[1], values[2])
-                }
-                TransformConfig::Scale { values } => {
-                    Transform::scale(values[0], values[1], values[2])
-                }
+            placement,
+            projection,
+            shutter,
+            motion,
+        } = config;
+        let mut camera = match placement {
+            CameraPlacementConfig::Corners {
+                eye,
+                left_bottom,
+                left_top,
+                right_top,
+            } => Camera::new(
+                eye.into(),
+                left_top.into(),
+                left_bottom.into(),
+                right_top.into(),
+            ),
+            CameraPlacementConfig::LookAt {
+                position,
+                target,
+                up,
+                fov,
+                focal_length,
+                sensor_height,
+            } => {
+                let field_of_view = match (fov, focal_length) {
+                    (Some(fov), _) if fov > 0.0 && fov < 180.0 => {
+                        FieldOfView::Vertical(fov.to_radians())
+                    }
+                    (Some(fov), _) => return Err(anyhow!("invalid camera fov: {}", fov)),
+                    (None, Some(focal_length)) if focal_length > 0.0 => FieldOfView::Lens {
+                        focal_length,
+                        sensor_height: sensor_height.unwrap_or(DEFAULT_SENSOR_HEIGHT),
+                    },
+                    (None, Some(focal_length)) => {
+                        return Err(anyhow!("invalid camera focal length: {}", focal_length));
+                    }
+                    (None, None) => {
+                        return Err(anyhow!("camera requires either fov or focalLength"));
+                    }
+                };
+                let [x, y, z] = up.unwrap_or([0.0, 1.0, 0.0]);
+                Camera::look_at(
+                    position.into(),
+                    target.into(),
+                    Vector(x, y, z),
+                    field_of_view,
+                )
             }
         };
-        let from_transforms = |trs: &[TransformConfig]| {
-            Transform::combine(&trs.iter().map(into_transform).collect::<Vec<Transform>>())
-        };
+        camera.projection = into_projection(projection)?;
+        if let Some([open, close]) = shutter {
+            if open > close {
+                return Err(anyhow!("invalid camera shutter: [{}, {}]", open, close));
+            }
+            camera.shutter = Shutter::new(open, close);
+        }
+        camera.motion = motion.map(|trs| from_transforms(&trs).0);
+        Ok(camera)
+    }
 
+    pub fn world(&self) -> Result<World> {
         let config = self
             .loader
             .load("scene.json")
             .map(deserialize::<SceneConfig>)??;
 
-        let SceneConfig { materials, solids } = config;
+        let SceneConfig {
+            materials,
+            meshes,
+            solids,
+        } = config;
         let mut builder = World::builder();
         let mut materials_hash: HashMap<String, Arc<Material>> = HashMap::default();
         for material in materials.iter() {
@@ -97,19 +136,26 @@ impl ConfigLoader {
             }
         }
         let get_material = |id: &str| -> Result<&Arc<Material>> {
-            Ok(materials_hash
+            materials_hash
                 .get(id)
-                .ok_or(anyhow!("material not found: {}", id))?)
+                .ok_or(anyhow!("material not found: {}", id))
         };
+        let mut meshes_hash: HashMap<String, Arc<Vec<Triangle>>> = HashMap::default();
+        for mesh in meshes.iter() {
+            let mt = get_material(&mesh.material)?;
+            let triangles = self.ply(&mesh.file, mesh.normalize, mt)?;
+            meshes_hash.insert(mesh.id.to_string(), Arc::new(triangles));
+        }
         for solid in solids.into_iter() {
+            let (start, end) = from_transforms(solid.transforms());
             let sld = match solid {
                 config_types::SolidConfig::Torus {
                     radius1,
                     radius2,
                     steps1,
                     steps2,
-                    transforms,
                     material,
+                    ..
                 } => {
                     let mt = get_material(&material)?;
                     Solid::Torus(
@@ -117,62 +163,111 @@ impl ConfigLoader {
                         radius2,
                         steps1 as usize,
                         steps2 as usize,
-                        from_transforms(&transforms),
+                        start,
                         mt.clone(),
                     )
                 }
                 config_types::SolidConfig::Sphere {
-                    sections,
-                    material,
-                    transforms,
+                    sections, material, ..
                 } => {
                     let mt = get_material(&material)?;
-                    Solid::Sphere(sections as usize, from_transforms(&transforms), mt.clone())
+                    Solid::Sphere(sections as usize, start, mt.clone())
                 }
-                config_types::SolidConfig::ColoredCube { transforms } => {
-                    Solid::ColoredCube(from_transforms(&transforms))
-                }
-                config_types::SolidConfig::Cube {
-                    material,
-                    transforms,
-                } => {
+                config_types::SolidConfig::ColoredCube { .. } => Solid::ColoredCube(start),
+                config_types::SolidConfig::Cube { material, .. } => {
                     let mt = get_material(&material)?;
-                    Solid::Cube(from_transforms(&transforms), mt.clone())
-                }
-                config_types::SolidConfig::CornellBox { transforms } => {
-                    Solid::CornellBox(from_transforms(&transforms))
+                    Solid::Cube(start, mt.clone())
                 }
-                config_types::SolidConfig::Plane {
-                    transforms,
-                    material,
-                } => {
+                config_types::SolidConfig::CornellBox { .. } => Solid::CornellBox(start),
+                config_types::SolidConfig::Plane { material, .. } => {
                     let mt = get_material(&material)?;
-                    Solid::Plane(from_transforms(&transforms), mt.clone())
+                    Solid::Plane(start, mt.clone())
                 }
                 config_types::SolidConfig::Ply {
                     file,
-                    transforms,
                     material,
                     normalize,
+                    ..
                 } => {
                     let mt = get_material(&material)?;
-                    let trn = from_transforms(&transforms);
-                    let ply = parsers::ply::parse(&self.loader.load(&file)?);
-                    let mut faces: Vec<Vec<Point>> = ply.faces().collect();
-                    if normalize.is_some_and(|x| x) {
-                        faces = normalize_points(faces);
-                    }
-                    let triangles = faces
-                        .into_iter()
-                        .map(|pts: Vec<Point>| Triangle::new(pts[0], pts[1], pts[2], mt.clone()))
-                        .collect();
-                    Solid::Mesh(trn, triangles)
+                    Solid::Mesh(start, self.ply(&file, normalize, mt)?)
                 }
+                config_types::SolidConfig::Instance { mesh, .. } => {
+                    let triangles = meshes_hash
+                        .get(&mesh)
+                        .ok_or(anyhow!("mesh not found: {}", mesh))?;
+                    Solid::Instance(start, triangles.clone())
+                }
+            };
+            match end {
+                Some(end) => builder.add_solid(Solid::Moving(Box::new(sld), end)),
+                None => builder.add_solid(sld),
             };
-            builder.add_solid(sld);
         }
         Ok(builder.build())
     }
+
+    fn ply(
+        &self,
+        file: &str,
+        normalize: Option<bool>,
+        material: &Arc<Material>,
+    ) -> Result<Vec<Triangle>> {
+        let ply = parsers::ply::parse(&self.loader.load(file)?);
+        let mut faces: Vec<Vec<Point>> = ply.faces().collect();
+        if normalize.is_some_and(|x| x) {
+            faces = normalize_points(faces);
+        }
+        Ok(faces
+            .into_iter()
+            .map(|pts: Vec<Point>| Triangle::new(pts[0], pts[1], pts[2], material.clone()))
+            .collect())
+    }
+}
+
+fn into_transform(cfg: &TransformConfig, values: [f32; 3]) -> Transform {
+    match cfg {
+        TransformConfig::Rotate { .. } => Transform::rotate(values[0], values[1], values[2]),
+        TransformConfig::Translate { .. } => Transform::translate(values[0], values[1], values[2]),
+        TransformConfig::Scale { .. } => Transform::scale(values[0], values[1], values[2]),
+    }
+}
+
+// Combines the transforms at shutter open, and at shutter close when any of them is keyed
+fn from_transforms(trs: &[TransformConfig]) -> (Transform, Option<Transform>) {
+    let start = Transform::combine(
+        &trs.iter()
+            .map(|cfg| into_transform(cfg, cfg.values().start()))
+            .collect::<Vec<Transform>>(),
+    );
+    let end = trs.iter().any(|cfg| cfg.values().is_keyed()).then(|| {
+        Transform::combine(
+            &trs.iter()
+                .map(|cfg| into_transform(cfg, cfg.values().end()))
+                .collect::<Vec<Transform>>(),
+        )
+    });
+    (start, end)
+}
+
+fn into_projection(config: Option<ProjectionConfig>) -> Result<Projection> {
+    match config {
+        None | Some(ProjectionConfig::Perspective) => Ok(Projection::Perspective),
+        Some(ProjectionConfig::Orthographic { view_width }) if view_width > 0.0 => {
+            Ok(Projection::Orthographic { view_width })
+        }
+        Some(ProjectionConfig::Orthographic { view_width }) => Err(anyhow!(
+            "invalid orthographic camera view width: {}",
+            view_width
+        )),
+        Some(ProjectionConfig::Equirectangular) => Ok(Projection::Equirectangular),
+        Some(ProjectionConfig::Fisheye { fov }) if fov > 0.0 && fov <= 360.0 => {
+            Ok(Projection::Fisheye {
+                field_of_view: fov.to_radians(),
+            })
+        }
+        Some(ProjectionConfig::Fisheye { fov }) => Err(anyhow!("invalid fisheye fov: {}", fov)),
+    }
 }
 
 fn deserialize<T>(buffer: String) -> Result<T>
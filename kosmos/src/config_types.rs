@@ -1,25 +1,102 @@
 use serde::Deserialize;
 
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum TransformValues {
+    Static([f32; 3]),
+    // values at shutter open and close, used for motion blur
+    Keyed { start: [f32; 3], end: [f32; 3] },
+}
+
+impl TransformValues {
+    pub fn start(&self) -> [f32; 3] {
+        match self {
+            TransformValues::Static(values) => *values,
+            TransformValues::Keyed { start, .. } => *start,
+        }
+    }
+    pub fn end(&self) -> [f32; 3] {
+        match self {
+            TransformValues::Static(values) => *values,
+            TransformValues::Keyed { end, .. } => *end,
+        }
+    }
+    pub fn is_keyed(&self) -> bool {
+        matches!(self, TransformValues::Keyed { .. })
+    }
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum TransformConfig {
     #[serde(rename = "rotate")]
-    Rotate { values: [f32; 3] },
+    Rotate { values: TransformValues },
     #[serde(rename = "translate")]
-    Translate { values: [f32; 3] },
+    Translate { values: TransformValues },
     #[serde(rename = "scale")]
-    Scale { values: [f32; 3] },
+    Scale { values: TransformValues },
+}
+
+impl TransformConfig {
+    pub fn values(&self) -> &TransformValues {
+        match self {
+            TransformConfig::Rotate { values }
+            | TransformConfig::Translate { values }
+            | TransformConfig::Scale { values } => values,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum ProjectionConfig {
+    #[serde(rename = "perspective")]
+    Perspective,
+    #[serde(rename = "orthographic")]
+    Orthographic {
+        #[serde(rename = "viewWidth")]
+        view_width: f32,
+    },
+    #[serde(rename = "equirectangular")]
+    Equirectangular,
+    #[serde(rename = "fisheye")]
+    Fisheye { fov: f32 },
 }
 
 #[derive(Deserialize, Debug)]
 pub struct CameraConfig {
-    pub eye: [f32; 3],
-    #[serde(rename = "leftBottom")]
-    pub left_bottom: [f32; 3],
-    #[serde(rename = "leftTop")]
-    pub left_top: [f32; 3],
-    #[serde(rename = "rightTop")]
-    pub right_top: [f32; 3],
+    #[serde(flatten)]
+    pub placement: CameraPlacementConfig,
+    pub projection: Option<ProjectionConfig>,
+    // open and close times, motion happens between 0.0 and 1.0
+    pub shutter: Option<[f32; 2]>,
+    // transforms that take the camera to where it is when the shutter closes
+    pub motion: Option<Vec<TransformConfig>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum CameraPlacementConfig {
+    LookAt {
+        position: [f32; 3],
+        target: [f32; 3],
+        up: Option<[f32; 3]>,
+        // vertical field of view in degrees
+        fov: Option<f32>,
+        #[serde(rename = "focalLength")]
+        focal_length: Option<f32>,
+        #[serde(rename = "sensorHeight")]
+        sensor_height: Option<f32>,
+    },
+    Corners {
+        eye: [f32; 3],
+        #[serde(rename = "leftBottom")]
+        left_bottom: [f32; 3],
+        #[serde(rename = "leftTop")]
+        left_top: [f32; 3],
+        #[serde(rename = "rightTop")]
+        right_top: [f32; 3],
+    },
 }
 
 #[derive(Deserialize, Debug)]
@@ -61,6 +138,27 @@ pub enum SolidConfig {
         material: String,
         transforms: Vec<TransformConfig>,
     },
+    // places a mesh declared in the meshes of the scene, instances share its triangles
+    #[serde(rename = "instance")]
+    Instance {
+        mesh: String,
+        transforms: Vec<TransformConfig>,
+    },
+}
+
+impl SolidConfig {
+    pub fn transforms(&self) -> &[TransformConfig] {
+        match self {
+            SolidConfig::Torus { transforms, .. }
+            | SolidConfig::Sphere { transforms, .. }
+            | SolidConfig::Cube { transforms, .. }
+            | SolidConfig::ColoredCube { transforms }
+            | SolidConfig::CornellBox { transforms }
+            | SolidConfig::Plane { transforms, .. }
+            | SolidConfig::Ply { transforms, .. }
+            | SolidConfig::Instance { transforms, .. } => transforms,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -76,8 +174,19 @@ pub enum MaterialConfig {
     Refractive { id: String },
 }
 
+// A ply mesh loaded once for the instances referencing its id
+#[derive(Deserialize, Debug)]
+pub struct MeshConfig {
+    pub id: String,
+    pub file: String,
+    pub normalize: Option<bool>,
+    pub material: String,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct SceneConfig {
     pub materials: Vec<MaterialConfig>,
+    #[serde(default)]
+    pub meshes: Vec<MeshConfig>,
     pub solids: Vec<SolidConfig>,
 }
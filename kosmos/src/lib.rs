@@ -1,6 +1,10 @@
 use anyhow::{Result, anyhow};
 use ilios_types::{camera::Camera, world::World};
-use std::{fs, path::Path};
+use std::{
+    fs,
+    io::{Cursor, Write},
+    path::Path,
+};
 
 use crate::{
     config_loader::ConfigLoader,
@@ -32,6 +36,35 @@ pub fn load_zip_data(data: Vec<u8>) -> Result<SceneDescriptor> {
     load_scene(zip_loader)
 }
 
+// Packs the files of a scene folder into zip data that load_zip_data can read back
+pub fn zip_folder(path: &str) -> Result<Vec<u8>> {
+    let mut archive = rawzip::ZipArchiveWriter::new(Cursor::new(Vec::new()));
+    let mut pending = vec![(Path::new(path).to_path_buf(), String::new())];
+    while let Some((folder, prefix)) = pending.pop() {
+        for entry in fs::read_dir(&folder)? {
+            let entry = entry?;
+            let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                pending.push((entry.path(), format!("{}/", name)));
+                continue;
+            }
+            let data = fs::read(entry.path())?;
+            let (mut file, config) = archive
+                .new_file(&name)
+                .compression_method(rawzip::CompressionMethod::Deflate)
+                .start()?;
+            let encoder =
+                flate2::write::DeflateEncoder::new(&mut file, flate2::Compression::default());
+            let mut writer = config.wrap(encoder);
+            writer.write_all(&data)?;
+            let (encoder, output) = writer.finish()?;
+            encoder.finish()?;
+            file.finish(output)?;
+        }
+    }
+    Ok(archive.finish()?.into_inner())
+}
+
 fn load_scene<L: Loader + 'static>(loader: L) -> Result<SceneDescriptor> {
     let config_loader = ConfigLoader::new(loader);
     let camera = config_loader.camera()?;
@@ -46,12 +79,11 @@ pub fn load(path: &str) -> Result<SceneDescriptor> {
     let file_type = metadata.file_type();
     if file_type.is_dir() {
         return load_folder(path);
-    } else if file_type.is_file() {
-        if let Some(ext) = p.extension()
-            && ext.to_ascii_lowercase() == "zip"
-        {
-            return load_zip_file(path);
-        }
+    } else if file_type.is_file()
+        && let Some(ext) = p.extension()
+        && ext.eq_ignore_ascii_case("zip")
+    {
+        return load_zip_file(path);
     }
     Err(anyhow!("file type not supported: {}", path))
 }
@@ -114,7 +114,7 @@ pub fn parse(file_content: &str) -> PlyFile {
     let mut header = Header::default();
     let mut parsing_state = ParsingState::HeaderStart;
 
-    while let Some(line) = lines.next() {
+    for line in lines.by_ref() {
         match parsing_state {
             ParsingState::HeaderStart => {
                 if line == "ply" {
@@ -138,13 +138,13 @@ pub fn parse(file_content: &str) -> PlyFile {
                 } else if line.starts_with("comment") {
                     header.comments.push(line.to_string());
                 } else if line.starts_with("element") {
-                    header.elements.push(parse_element(&line));
+                    header.elements.push(parse_element(line));
                 } else if line.starts_with("property") {
                     let mut el = header
                         .elements
                         .pop()
                         .expect("No element available for property");
-                    el.properties.push(parse_property(&line));
+                    el.properties.push(parse_property(line));
                     header.elements.push(el);
                 }
             }
@@ -169,7 +169,7 @@ pub fn parse(file_content: &str) -> PlyFile {
 
         for _i in 0..count {
             if let Some(line) = lines.next() {
-                let value = parse_element_line(element, &line);
+                let value = parse_element_line(element, line);
                 entries.push(Component(value));
             }
         }
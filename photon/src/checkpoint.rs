@@ -0,0 +1,207 @@
+use bincode::{Decode, Encode, config};
+use clap::ArgMatches;
+use ilios::{Progress, Progressive, RendererBuilder};
+use ilios_types::color::Color;
+use std::fmt::{self, Write};
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::BinaryRender;
+
+const DEFAULT_CHECKPOINT_FILE: &str = "renders/checkpoint.brf";
+const DEFAULT_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// State needed to resume a render, saved along the averaged pixels of a .brf file
+#[derive(Encode, Decode)]
+pub struct Checkpoint {
+    sample_counts: Vec<u32>,
+    passes: u32,
+    // samples are indexed from the seed, the next pass starts at the sample after the passes
+    seed: u32,
+    settings: String,
+    scene_hash: u64,
+    // rendering time so far in milliseconds
+    elapsed: f32,
+}
+
+// FNV-1a, stable across builds unlike the standard library hasher
+struct SceneHasher(u64);
+
+impl Write for SceneHasher {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+        Ok(())
+    }
+}
+
+// Hash of the camera, lights and solids of the world, whatever the scene was loaded from.
+// The material library is left out, its map has no stable order and solids hold their materials
+fn scene_hash(renderer_builder: &RendererBuilder) -> u64 {
+    let mut hasher = SceneHasher(0xcbf29ce484222325);
+    let world = &renderer_builder.world;
+    write!(
+        hasher,
+        "{:?}{:?}{:?}",
+        renderer_builder.camera, world.lights, world.objects
+    )
+    .unwrap();
+    hasher.0
+}
+
+// Settings that change the rendered image, the samples are left out so a render can be
+// resumed with more of them
+fn settings(renderer_builder: &RendererBuilder) -> String {
+    format!(
+        "{}x{} {:?} {:?} {:?} {:?} {:?}",
+        renderer_builder.width,
+        renderer_builder.height,
+        renderer_builder.algorithm,
+        renderer_builder.filter,
+        renderer_builder.filter_method,
+        renderer_builder.sampler,
+        renderer_builder.stereo,
+    )
+}
+
+fn save(path: &str, session: &Progressive, checkpoint: Checkpoint) -> io::Result<()> {
+    let pixels = session
+        .image()
+        .iter()
+        .map(|Color(r, g, b)| (*r, *g, *b))
+        .collect();
+    let binary_render = BinaryRender {
+        width: session.width(),
+        height: session.height(),
+        pixels,
+        checkpoint: Some(checkpoint),
+    };
+    let data = bincode::encode_to_vec(binary_render, config::standard())
+        .map_err(|error| io::Error::other(error.to_string()))?;
+    if let Some(folder) = Path::new(path).parent() {
+        fs::create_dir_all(folder)?;
+    }
+    // a crash while writing leaves the previous checkpoint in place
+    let temporary = format!("{}.tmp", path);
+    fs::write(&temporary, data)?;
+    fs::rename(&temporary, path)
+}
+
+fn load(path: &str) -> Result<(Vec<Color>, Checkpoint), String> {
+    let data = fs::read(path).map_err(|error| format!("cannot read {}: {}", path, error))?;
+    let (binary_render, _): (BinaryRender, usize) =
+        bincode::decode_from_slice(&data, config::standard())
+            .map_err(|error| format!("{} is not a render file: {}", path, error))?;
+    let checkpoint = binary_render
+        .checkpoint
+        .ok_or_else(|| format!("{} is not a checkpoint", path))?;
+    let pixels = binary_render
+        .pixels
+        .into_iter()
+        .map(|(r, g, b)| Color(r, g, b))
+        .collect();
+    Ok((pixels, checkpoint))
+}
+
+// Renders one sample per pixel per pass, saving a checkpoint every interval and once done
+pub fn render(matches: &ArgMatches) {
+    let mut renderer_builder = crate::configure_scene(matches);
+    let target = renderer_builder.samples;
+    renderer_builder.samples(1);
+    let settings = settings(&renderer_builder);
+    let scene_hash = scene_hash(&renderer_builder);
+    let seed = renderer_builder.seed;
+
+    let resume = matches.get_one::<String>("resume");
+    let path = resume
+        .or(matches.get_one::<String>("checkpoint file"))
+        .map_or(DEFAULT_CHECKPOINT_FILE, |v| v.as_str())
+        .to_string();
+    let interval = matches
+        .get_one::<Duration>("checkpoint")
+        .map_or(DEFAULT_CHECKPOINT_INTERVAL, |v| *v);
+
+    let renderer = renderer_builder.build();
+    let (mut session, previous) = match resume {
+        Some(path) => {
+            let (pixels, checkpoint) = load(path).unwrap_or_else(|error| exit(&error));
+            if checkpoint.scene_hash != scene_hash {
+                exit("the scene changed since the checkpoint was saved");
+            }
+            if checkpoint.settings != settings || checkpoint.seed != seed {
+                exit(&format!(
+                    "the settings changed since the checkpoint was saved, it used: {} seed {}",
+                    checkpoint.settings, checkpoint.seed
+                ));
+            }
+            eprintln!("resuming from {} samples", checkpoint.passes);
+            let session = Progressive::restore(
+                renderer,
+                &pixels,
+                checkpoint.sample_counts,
+                checkpoint.passes,
+            );
+            (session, checkpoint.elapsed)
+        }
+        None => (Progressive::new(renderer), 0.0),
+    };
+
+    let checkpoint = |session: &Progressive, elapsed: f32| Checkpoint {
+        sample_counts: session.sample_counts().to_vec(),
+        passes: session.passes(),
+        seed,
+        settings: settings.clone(),
+        scene_hash,
+        elapsed,
+    };
+    let show_progress = io::stderr().is_terminal() && !matches.get_flag("no progress");
+    let progress = crate::progress_bar();
+    let start = Instant::now();
+    let mut last_checkpoint = Instant::now();
+    let first_pass = session.passes();
+    while session.passes() < target {
+        session.render_pass();
+        let elapsed = previous + start.elapsed().as_secs_f32() * 1000.0;
+        if show_progress {
+            progress(&Progress {
+                completed: session.passes() - first_pass,
+                total: target.saturating_sub(first_pass),
+                elapsed: start.elapsed(),
+            });
+        }
+        if last_checkpoint.elapsed() >= interval && session.passes() < target {
+            save(&path, &session, checkpoint(&session, elapsed))
+                .unwrap_or_else(|error| eprintln!("\ncheckpoint failed: {}", error));
+            last_checkpoint = Instant::now();
+        }
+    }
+    if show_progress {
+        eprintln!();
+    }
+    let elapsed = previous + start.elapsed().as_secs_f32() * 1000.0;
+    eprintln!("Rendering time: {}ms", elapsed);
+
+    // the final checkpoint lets a later run continue with more samples
+    match save(&path, &session, checkpoint(&session, elapsed)) {
+        Ok(()) => eprintln!("saved checkpoint {}", path),
+        Err(error) => eprintln!("checkpoint failed: {}", error),
+    }
+
+    crate::output_image(
+        matches,
+        session.samples(),
+        elapsed,
+        session.width(),
+        session.height(),
+        &session.image(),
+        session.sample_counts(),
+    );
+}
+
+fn exit(message: &str) -> ! {
+    eprintln!("cannot resume: {}", message);
+    std::process::exit(1);
+}
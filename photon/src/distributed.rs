@@ -0,0 +1,358 @@
+use bincode::{Decode, Encode, config};
+use ilios::Progress;
+use ilios_types::{color::Color, section::Section};
+use kosmos::SceneDescriptor;
+use std::collections::VecDeque;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Messages sent by the coordinator, a connection starts with a setup followed by the sections
+#[derive(Encode, Decode)]
+enum Request {
+    // command line of the coordinator and the zipped scene folder when it renders one
+    Setup {
+        args: Vec<String>,
+        scene: Option<Vec<u8>>,
+    },
+    Render {
+        left: u32,
+        top: u32,
+        width: u32,
+        height: u32,
+    },
+}
+
+#[derive(Encode, Decode)]
+enum Response {
+    Ready,
+    Pixels(Vec<(f32, f32, f32)>),
+    Failed(String),
+}
+
+fn send<T: Encode>(stream: &mut BufWriter<TcpStream>, message: T) -> io::Result<()> {
+    bincode::encode_into_std_write(message, stream, config::standard())
+        .map_err(|error| io::Error::other(error.to_string()))?;
+    stream.flush()
+}
+
+fn receive<T: Decode>(stream: &mut BufReader<TcpStream>) -> io::Result<T> {
+    bincode::decode_from_std_read(stream, config::standard()).map_err(|error| match error {
+        bincode::error::DecodeError::Io { inner, .. } => inner,
+        error => io::Error::other(error.to_string()),
+    })
+}
+
+// Serves coordinators one at a time, each connection renders the sections it is sent
+pub fn work(address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    eprintln!("worker listening on {}", listener.local_addr()?);
+    serve_all(listener)
+}
+
+fn serve_all(listener: TcpListener) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let peer = stream.peer_addr()?;
+        eprintln!("coordinator {} connected", peer);
+        match serve(stream) {
+            Ok(sections) => eprintln!("coordinator {} done, {} sections", peer, sections),
+            Err(error) => eprintln!("coordinator {} dropped: {}", peer, error),
+        }
+    }
+    Ok(())
+}
+
+fn serve(stream: TcpStream) -> io::Result<u32> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    let Request::Setup { args, scene } = receive(&mut reader)? else {
+        send(
+            &mut writer,
+            Response::Failed("expected the setup".to_string()),
+        )?;
+        return Ok(0);
+    };
+    let mut renderer = match setup(args, scene) {
+        Ok(renderer) => renderer,
+        Err(error) => {
+            send(&mut writer, Response::Failed(error.clone()))?;
+            return Err(io::Error::other(error));
+        }
+    };
+    send(&mut writer, Response::Ready)?;
+
+    let mut sections = 0;
+    loop {
+        let request = match receive(&mut reader) {
+            Ok(request) => request,
+            // the coordinator closes the connection once every section is rendered
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(sections),
+            Err(error) => return Err(error),
+        };
+        let Request::Render {
+            left,
+            top,
+            width,
+            height,
+        } = request
+        else {
+            send(
+                &mut writer,
+                Response::Failed("unexpected setup".to_string()),
+            )?;
+            continue;
+        };
+        let pixels = renderer.render(&Section::new(left, top, width, height));
+        let pixels = pixels.iter().map(|Color(r, g, b)| (*r, *g, *b)).collect();
+        send(&mut writer, Response::Pixels(pixels))?;
+        sections += 1;
+    }
+}
+
+fn setup(args: Vec<String>, scene: Option<Vec<u8>>) -> Result<ilios::Renderer, String> {
+    let matches = crate::cli()
+        .no_binary_name(true)
+        .try_get_matches_from(args)
+        .map_err(|error| error.to_string())?;
+    let mut renderer_builder = crate::configure_renderer(&matches);
+    if let Some(scene) = scene {
+        let SceneDescriptor { camera, world } =
+            kosmos::load_zip_data(scene).map_err(|error| error.to_string())?;
+        renderer_builder.camera(camera);
+        renderer_builder.world(world);
+    }
+    Ok(renderer_builder.build())
+}
+
+// Sections waiting for a worker and the ones not rendered yet, sections of a worker that
+// drops out go back to the queue
+struct Queue {
+    pending: VecDeque<Section>,
+    remaining: usize,
+}
+
+// Splits the section in square blocks and renders them across the workers, a worker that
+// drops out has its section given to the others. Workers that take longer than the timeout to
+// connect or answer, like unreachable ones, count as dropped. Fails when no worker is left
+pub fn render<F>(
+    workers: &[String],
+    args: Vec<String>,
+    scene: Option<Vec<u8>>,
+    section: Section,
+    section_size: u32,
+    timeout: Duration,
+    progress: F,
+) -> io::Result<Vec<Color>>
+where
+    F: Fn(&Progress) + Sync,
+{
+    let mut blocks = VecDeque::new();
+    for top in (0..section.height).step_by(section_size as usize) {
+        for left in (0..section.width).step_by(section_size as usize) {
+            blocks.push_back(Section::new(
+                section.left + left,
+                section.top + top,
+                section_size.min(section.width - left),
+                section_size.min(section.height - top),
+            ));
+        }
+    }
+    let total = blocks.len();
+    let queue = Mutex::new(Queue {
+        pending: blocks,
+        remaining: total,
+    });
+    let changed = Condvar::new();
+    let image = Mutex::new(vec![
+        Color::default();
+        (section.width * section.height) as usize
+    ]);
+    let start = Instant::now();
+
+    // next section for a worker, waits while other workers may still give theirs back
+    let next = || {
+        let mut queue = queue.lock().unwrap();
+        loop {
+            if let Some(block) = queue.pending.pop_front() {
+                return Some(block);
+            }
+            if queue.remaining == 0 {
+                return None;
+            }
+            queue = changed.wait(queue).unwrap();
+        }
+    };
+
+    thread::scope(|scope| {
+        for worker in workers {
+            let (next, queue, changed, image) = (&next, &queue, &changed, &image);
+            let (args, scene, progress) = (args.clone(), scene.clone(), &progress);
+            scope.spawn(move || {
+                let mut assigned = None;
+                let result = (|| -> io::Result<()> {
+                    let address = worker
+                        .to_socket_addrs()?
+                        .next()
+                        .ok_or_else(|| io::Error::other("no address"))?;
+                    let stream = TcpStream::connect_timeout(&address, timeout)?;
+                    stream.set_read_timeout(Some(timeout))?;
+                    stream.set_write_timeout(Some(timeout))?;
+                    let mut reader = BufReader::new(stream.try_clone()?);
+                    let mut writer = BufWriter::new(stream);
+                    send(&mut writer, Request::Setup { args, scene })?;
+                    match receive(&mut reader)? {
+                        Response::Ready => {}
+                        Response::Failed(error) => return Err(io::Error::other(error)),
+                        Response::Pixels(_) => return Err(io::Error::other("unexpected pixels")),
+                    }
+
+                    while let Some(block) = next() {
+                        assigned = Some(block);
+                        send(
+                            &mut writer,
+                            Request::Render {
+                                left: block.left,
+                                top: block.top,
+                                width: block.width,
+                                height: block.height,
+                            },
+                        )?;
+                        let pixels = match receive(&mut reader)? {
+                            Response::Pixels(pixels) => pixels,
+                            Response::Failed(error) => return Err(io::Error::other(error)),
+                            Response::Ready => return Err(io::Error::other("unexpected ready")),
+                        };
+                        if pixels.len() != (block.width * block.height) as usize {
+                            return Err(io::Error::other("wrong number of pixels"));
+                        }
+
+                        let mut image = image.lock().unwrap();
+                        for (idx, (r, g, b)) in pixels.into_iter().enumerate() {
+                            let x = block.left - section.left + idx as u32 % block.width;
+                            let y = block.top - section.top + idx as u32 / block.width;
+                            image[(y * section.width + x) as usize] = Color(r, g, b);
+                        }
+                        drop(image);
+                        assigned = None;
+
+                        let mut queue = queue.lock().unwrap();
+                        queue.remaining -= 1;
+                        progress(&Progress {
+                            completed: (total - queue.remaining) as u32,
+                            total: total as u32,
+                            elapsed: start.elapsed(),
+                        });
+                        changed.notify_all();
+                    }
+                    Ok(())
+                })();
+
+                if let Err(error) = result {
+                    // timed out reads fail with WouldBlock on unix
+                    match error.kind() {
+                        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+                            eprintln!("worker {} dropped: timed out", worker)
+                        }
+                        _ => eprintln!("worker {} dropped: {}", worker, error),
+                    }
+                    if let Some(block) = assigned {
+                        queue.lock().unwrap().pending.push_back(block);
+                    }
+                    changed.notify_all();
+                }
+            });
+        }
+    });
+
+    if queue.into_inner().unwrap().remaining > 0 {
+        return Err(io::Error::other(
+            "no workers left to render the remaining sections",
+        ));
+    }
+    Ok(image.into_inner().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, BufWriter};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use ilios_types::section::Section;
+
+    use super::{Request, Response, receive, send, serve_all};
+
+    // Worker that takes the setup and its first section, then drops the connection when
+    // dropping is set or keeps it open without answering otherwise
+    fn failing_worker(dropping: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = BufWriter::new(stream);
+            let Ok(Request::Setup { .. }) = receive(&mut reader) else {
+                return;
+            };
+            send(&mut writer, Response::Ready).unwrap();
+            let Ok(Request::Render { .. }) = receive::<Request>(&mut reader) else {
+                return;
+            };
+            if !dropping {
+                thread::sleep(Duration::from_secs(60));
+            }
+        });
+        address
+    }
+
+    fn worker() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || serve_all(listener));
+        address
+    }
+
+    #[test]
+    fn sections_of_dropped_workers_are_rendered_by_the_others() {
+        let args: Vec<String> = ["--demo", "cornell", "--width", "32", "--height", "24"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let section = Section::new(0, 0, 32, 24);
+        let matches = crate::cli().try_get_matches_from(
+            std::iter::once("photon".to_string()).chain(args.iter().cloned()),
+        );
+        let expected = crate::configure_renderer(&matches.unwrap())
+            .build()
+            .render(&section);
+
+        let workers = [
+            failing_worker(true),
+            worker(),
+            failing_worker(false),
+            worker(),
+        ];
+        let pixels = super::render(
+            &workers,
+            args,
+            None,
+            section,
+            8,
+            Duration::from_secs(1),
+            |_| {},
+        )
+        .unwrap();
+        let bits = |pixels: &[ilios_types::color::Color]| {
+            pixels
+                .iter()
+                .map(|color| (color.0.to_bits(), color.1.to_bits(), color.2.to_bits()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(bits(&pixels), bits(&expected));
+    }
+}
@@ -1,24 +1,45 @@
-use bincode::{Encode, config};
+mod checkpoint;
+mod distributed;
+
+use bincode::{Decode, Encode, config};
 use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
-use ilios::{Accelerator, Algorithm, BvhBuildMethod, RenderMethod, Renderer, demos};
+use ilios::{
+    Accelerator, AdaptiveSampling, Algorithm, BvhBuildMethod, BvhWidth, CancellationToken, Filter,
+    FilterMethod, Progress, RenderMethod, Renderer, RendererBuilder, Sampler, Stereo, StereoLayout,
+    TileOrder, demos,
+};
 use ilios_types::camera::Camera;
 use ilios_types::color::Color;
 use ilios_types::geometry::Point;
 use ilios_types::section::Section;
 use kosmos::SceneDescriptor;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
 use std::{fs, io::BufWriter};
 
 const DEFAULT_WIDTH: u32 = 640;
 const DEFAULT_HEIGHT: u32 = 360;
 const DEFAULT_THREADS: u32 = 0;
 const DEFAULT_SAMPLES: u32 = 10;
+const DEFAULT_MIN_SAMPLES: u32 = 4;
+const DEFAULT_INTEROCULAR_DISTANCE: f32 = 0.065;
+const DEFAULT_SBVH_ALPHA: f32 = 0.00001;
+// convergence at 30 times the interocular distance keeps the parallax comfortable
+const CONVERGENCE_RATIO: f32 = 30.0;
+const PROGRESS_BAR_WIDTH: usize = 40;
+const DEFAULT_SECTION_SIZE: u32 = 64;
+// longer than a worker should take to set up the scene or render a section
+const DEFAULT_WORKER_TIMEOUT: Duration = Duration::from_secs(300);
 
-#[derive(Encode)]
+#[derive(Encode, Decode)]
 struct BinaryRender {
     width: u32,
     height: u32,
     pixels: Vec<(f32, f32, f32)>,
+    // renders saved as checkpoints carry what is needed to resume them
+    checkpoint: Option<checkpoint::Checkpoint>,
 }
 
 fn gamma_correct(x: u8) -> u8 {
@@ -95,12 +116,13 @@ fn format_as_binary(pixels: &[Color], width: u32, height: u32) -> Vec<u8> {
         width,
         height,
         pixels: px,
+        checkpoint: None,
     };
     let config = config::standard();
     bincode::encode_to_vec(binary_render, config).unwrap()
 }
 
-fn process_cli() -> ArgMatches {
+fn cli() -> Command {
     Command::new("Photon")
         .version("0.1")
         .author("Enrique <niofis@gmail.com>")
@@ -119,7 +141,7 @@ fn process_cli() -> ArgMatches {
                 .action(ArgAction::Set)
                 .value_parser(value_parser!(String))
                 .default_value("bvh")
-                .help("specify the accelerator structure to use from: brute_force and bvh. Defaults to bvh"))
+                .help("specify the accelerator structure to use from: brute-force, bvh, kdtree and grid. Defaults to bvh"))
         .arg(
             Arg::new("demo")
                 .short('d')
@@ -143,6 +165,12 @@ fn process_cli() -> ArgMatches {
                 .conflicts_with("ply")
                 .help("render the specified scene, should point to a folder")
         )
+        .arg(
+            Arg::new("no bvh cache")
+                .long("no-bvh-cache")
+                .action(ArgAction::SetTrue)
+                .help("builds the bvh of --scene every time instead of saving it next to the scene and loading it from there while the scene geometry and build method stay the same")
+        )
         .arg(
             Arg::new("stats")
                 .long("stats")
@@ -187,7 +215,6 @@ fn process_cli() -> ArgMatches {
         )
         .arg(
             Arg::new("savefile")
-                .short('s')
                 .long("save")
                 .action(ArgAction::SetTrue)
                 .help("saves the image file to disk using the default name structure: YYYYMMDD-HHMM-SAMPLES-TIME.ppm")
@@ -224,12 +251,217 @@ fn process_cli() -> ArgMatches {
         .arg(
             Arg::new("BVH build method")
                 .long("bvh-build-method")
-                .help("Selects the build method to use for building th BVH. Options: octree, sah")
+                .help("Selects the build method to use for building th BVH. Options: octree, sah, lbvh, sbvh. lbvh builds fastest and traces up to half again slower than sah, sbvh builds slowest and traces fastest on scenes with large triangles")
+        )
+        .arg(
+            Arg::new("SBVH alpha")
+                .long("sbvh-alpha")
+                .value_parser(parse_alpha)
+                .help("overlap of the BVH nodes, relative to the scene surface, over which the sbvh build method splits triangles. Defaults to 0.00001")
+        )
+        .arg(
+            Arg::new("BVH width")
+                .long("bvh-width")
+                .value_parser(["2", "4", "8"])
+                .help("children per BVH node, the children of 4 and 8 wide nodes are tested together with SIMD. Defaults to 2")
+        )
+        .arg(
+            Arg::new("filter")
+                .long("filter")
+                .value_parser(["box", "tent", "gaussian", "mitchell", "lanczos"])
+                .help("pixel reconstruction filter: box, tent, gaussian, mitchell, lanczos. Defaults to box")
+        )
+        .arg(
+            Arg::new("filter radius")
+                .long("filter-radius")
+                .value_parser(parse_radius)
+                .requires("filter")
+                .help("radius of the reconstruction filter in pixels, each filter has its own default")
+        )
+        .arg(
+            Arg::new("filter method")
+                .long("filter-method")
+                .value_parser(["importance", "splat"])
+                .help("importance samples the filter within each pixel or splats samples into the neighbouring pixels. Defaults to importance")
+        )
+        .arg(
+            Arg::new("adaptive")
+                .long("adaptive")
+                .value_parser(value_parser!(f32))
+                .help("keeps sampling the pixels whose relative error is above this threshold, up to --samples per pixel")
+        )
+        .arg(
+            Arg::new("min samples")
+                .long("min-samples")
+                .value_parser(value_parser!(u32))
+                .requires("adaptive")
+                .help("samples per pixel taken before estimating the error of adaptive sampling, defaults to 4")
+        )
+        .arg(
+            Arg::new("sample map")
+                .long("sample-map")
+                .action(ArgAction::SetTrue)
+                .requires("adaptive")
+                .help("also saves an image with the samples taken by each pixel, requires --save")
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_parser(value_parser!(u32))
+                .help("seed for the random numbers, the same seed renders the same image regardless of the threads used. Defaults to 0")
+        )
+        .arg(
+            Arg::new("sampler")
+                .long("sampler")
+                .value_parser(["random", "stratified", "halton", "sobol", "blue-noise"])
+                .help("sequence used to place the samples: random, stratified, halton, sobol, blue-noise. Defaults to random")
+        )
+        .arg(
+            Arg::new("stereo")
+                .long("stereo")
+                .value_parser(["side-by-side", "top-bottom", "anaglyph"])
+                .help("renders a stereo pair: side-by-side, top-bottom or anaglyph. The width and height are those of the whole image")
+        )
+        .arg(
+            Arg::new("interocular distance")
+                .long("interocular")
+                .value_parser(value_parser!(f32))
+                .requires("stereo")
+                .help("distance between the eyes in scene units, defaults to 0.065")
+        )
+        .arg(
+            Arg::new("convergence distance")
+                .long("convergence")
+                .value_parser(value_parser!(f32))
+                .requires("stereo")
+                .help("distance at which both eyes converge, defaults to 30 times the interocular distance")
+        )
+        .arg(
+            Arg::new("tile size")
+                .long("tile-size")
+                .value_parser(value_parser!(u32).range(1..))
+                .help("size in pixels of the square tiles of the tiles render method, defaults to 4")
+        )
+        .arg(
+            Arg::new("tile order")
+                .long("tile-order")
+                .value_parser(["row", "spiral", "hilbert"])
+                .help("order in which tiles are rendered: row, spiral from the center or along a hilbert curve. Defaults to row")
+        )
+        .arg(
+            Arg::new("time")
+                .long("time")
+                .value_parser(parse_duration)
+                .conflicts_with("adaptive")
+                .help("keeps adding passes of --samples per pixel until the time budget is spent, e.g. 30s, 500ms or 2m. Passes default to 1 sample")
+        )
+        .arg(
+            Arg::new("no progress")
+                .long("no-progress")
+                .action(ArgAction::SetTrue)
+                .help("hides the progress bar, it is only shown when stderr is a terminal")
+        )
+        .arg(
+            Arg::new("checkpoint")
+                .long("checkpoint")
+                .value_parser(parse_duration)
+                .conflicts_with_all(["adaptive", "time", "workers"])
+                .help("renders one sample per pixel at a time and saves a checkpoint every interval, e.g. 10m. Defaults to 5m when resuming")
+        )
+        .arg(
+            Arg::new("checkpoint file")
+                .long("checkpoint-file")
+                .value_parser(value_parser!(String))
+                .requires("checkpoint")
+                .help("file the checkpoints are written to, defaults to renders/checkpoint.brf")
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .value_parser(value_parser!(String))
+                .conflicts_with_all(["adaptive", "time", "workers", "checkpoint file"])
+                .help("continues the render saved in a checkpoint up to --samples, the scene and settings must match. New checkpoints overwrite it")
+        )
+        .arg(
+            Arg::new("worker")
+                .long("worker")
+                .value_parser(value_parser!(String))
+                .help("runs as a render worker listening on the given address, e.g. 0.0.0.0:7878. Scene and settings come from the coordinator")
+        )
+        .arg(
+            Arg::new("workers")
+                .long("workers")
+                .value_parser(value_parser!(String))
+                .value_delimiter(',')
+                .conflicts_with_all(["worker", "time"])
+                .help("coordinates a render across the comma separated worker addresses, sections of a worker that drops out are given to the others")
+        )
+        .arg(
+            Arg::new("section size")
+                .long("section-size")
+                .value_parser(value_parser!(u32).range(1..))
+                .requires("workers")
+                .help("size in pixels of the square sections sent to the workers, defaults to 64")
+        )
+        .arg(
+            Arg::new("worker timeout")
+                .long("worker-timeout")
+                .value_parser(parse_duration)
+                .requires("workers")
+                .help("drops workers that take longer than this to connect or answer, e.g. 30s, their sections are given to the others. Defaults to 5m")
         )
-        .get_matches()
 }
 
-fn build_renderer(matches: &ArgMatches) -> Renderer {
+fn process_cli() -> ArgMatches {
+    cli().get_matches()
+}
+
+// Parses a duration with an optional unit: ms, s (default), m or h
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, scale) = if let Some(number) = value.strip_suffix("ms") {
+        (number, 0.001)
+    } else if let Some(number) = value.strip_suffix('s') {
+        (number, 1.0)
+    } else if let Some(number) = value.strip_suffix('m') {
+        (number, 60.0)
+    } else if let Some(number) = value.strip_suffix('h') {
+        (number, 3600.0)
+    } else {
+        (value, 1.0)
+    };
+    number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite() && *number >= 0.0)
+        .map(|number| Duration::from_secs_f64(number * scale))
+        .ok_or_else(|| format!("invalid duration: {}", value))
+}
+
+// Filter radii must be positive, the filters have no width otherwise
+fn parse_radius(value: &str) -> Result<f32, String> {
+    value
+        .trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|radius| radius.is_finite() && *radius > 0.0)
+        .ok_or_else(|| format!("invalid radius: {}", value))
+}
+
+// Overlap budgets can be zero, to try spatial splits wherever nodes overlap, or inf to never
+// try them
+fn parse_alpha(value: &str) -> Result<f32, String> {
+    value
+        .trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|alpha| *alpha >= 0.0)
+        .ok_or_else(|| format!("invalid alpha: {}", value))
+}
+
+// Renderer settings from the command line, everything but a scene folder or file
+fn configure_renderer(matches: &ArgMatches) -> RendererBuilder {
     let v_offset = 3.0;
     let z_offset = -10.0;
     let mut renderer_builder = Renderer::builder();
@@ -265,20 +497,103 @@ fn build_renderer(matches: &ArgMatches) -> Renderer {
                 .get_one::<u32>("threads")
                 .map_or(DEFAULT_THREADS, |v| *v),
         )
-        .samples(
-            matches
-                .get_one::<u32>("samples count")
-                .map_or(DEFAULT_SAMPLES, |v| *v),
-        )
+        .samples(match matches.get_one::<u32>("samples count") {
+            Some(samples) => *samples,
+            None if matches.contains_id("time") => 1,
+            None => DEFAULT_SAMPLES,
+        })
+        .seed(matches.get_one::<u32>("seed").map_or(0, |v| *v))
         .accelerator(match matches.get_one::<String>("accelerator") {
             Some(val) if val == "brute-force" => Accelerator::BruteForce,
             Some(val) if val == "bvh" => Accelerator::BoundingVolumeHierarchy,
+            Some(val) if val == "kdtree" => Accelerator::KdTree,
+            Some(val) if val == "grid" => Accelerator::Grid,
             _ => Accelerator::BoundingVolumeHierarchy,
         })
         .bvh_build_method(match matches.get_one::<String>("BVH build method") {
             Some(val) if val == "octree" => BvhBuildMethod::Octree,
+            Some(val) if val == "lbvh" => BvhBuildMethod::Lbvh,
+            Some(val) if val == "sbvh" => BvhBuildMethod::SpatialSplit {
+                alpha: matches
+                    .get_one::<f32>("SBVH alpha")
+                    .map_or(DEFAULT_SBVH_ALPHA, |v| *v),
+            },
             _ => BvhBuildMethod::Sah,
+        })
+        .bvh_width(match matches.get_one::<String>("BVH width") {
+            Some(val) if val == "4" => BvhWidth::Four,
+            Some(val) if val == "8" => BvhWidth::Eight,
+            _ => BvhWidth::Two,
+        });
+
+    if let Some(filter) = matches.get_one::<String>("filter") {
+        let radius = matches.get_one::<f32>("filter radius").copied();
+        renderer_builder.filter(match filter.as_str() {
+            "tent" => Filter::tent(radius.unwrap_or(1.0)),
+            "gaussian" => Filter::gaussian(radius.unwrap_or(1.5)),
+            "mitchell" => Filter::mitchell(radius.unwrap_or(2.0)),
+            "lanczos" => Filter::lanczos(radius.unwrap_or(2.0)),
+            _ => Filter::box_filter(radius.unwrap_or(0.5)),
+        });
+    }
+
+    if let Some(method) = matches.get_one::<String>("filter method") {
+        renderer_builder.filter_method(match method.as_str() {
+            "splat" => FilterMethod::Splatting,
+            _ => FilterMethod::ImportanceSampling,
         });
+    }
+
+    if let Some(threshold) = matches.get_one::<f32>("adaptive") {
+        let min_samples = matches
+            .get_one::<u32>("min samples")
+            .map_or(DEFAULT_MIN_SAMPLES, |v| *v);
+        let max_samples = matches
+            .get_one::<u32>("samples count")
+            .map_or(DEFAULT_SAMPLES, |v| *v);
+        renderer_builder.adaptive(AdaptiveSampling::new(min_samples, max_samples, *threshold));
+    }
+
+    if let Some(tile_size) = matches.get_one::<u32>("tile size") {
+        renderer_builder.tile_size(*tile_size);
+    }
+
+    if let Some(order) = matches.get_one::<String>("tile order") {
+        renderer_builder.tile_order(match order.as_str() {
+            "spiral" => TileOrder::Spiral,
+            "hilbert" => TileOrder::Hilbert,
+            _ => TileOrder::Row,
+        });
+    }
+
+    if let Some(sampler) = matches.get_one::<String>("sampler") {
+        renderer_builder.sampler(match sampler.as_str() {
+            "stratified" => Sampler::Stratified,
+            "halton" => Sampler::Halton,
+            "sobol" => Sampler::Sobol,
+            "blue-noise" => Sampler::BlueNoise,
+            _ => Sampler::Random,
+        });
+    }
+
+    if let Some(layout) = matches.get_one::<String>("stereo") {
+        let layout = match layout.as_str() {
+            "top-bottom" => StereoLayout::TopBottom,
+            "anaglyph" => StereoLayout::Anaglyph,
+            _ => StereoLayout::SideBySide,
+        };
+        let interocular_distance = matches
+            .get_one::<f32>("interocular distance")
+            .map_or(DEFAULT_INTEROCULAR_DISTANCE, |v| *v);
+        let convergence_distance = matches
+            .get_one::<f32>("convergence distance")
+            .map_or(interocular_distance * CONVERGENCE_RATIO, |v| *v);
+        renderer_builder.stereo(Stereo::new(
+            layout,
+            interocular_distance,
+            convergence_distance,
+        ));
+    }
 
     match matches.get_one::<String>("demo") {
         Some(val) if val == "simple" => {
@@ -290,13 +605,27 @@ fn build_renderer(matches: &ArgMatches) -> Renderer {
         _ => {}
     }
 
+    renderer_builder
+}
+
+// Renderer settings from the command line along with the scene to render
+fn configure_scene(matches: &ArgMatches) -> RendererBuilder {
+    let mut renderer_builder = configure_renderer(matches);
     if let Some(scene_path) = matches.get_one::<String>("scene") {
         let SceneDescriptor { camera, world } = kosmos::load(scene_path).unwrap();
         renderer_builder.camera(camera);
         renderer_builder.world(world);
+        // scene.bvh next to a scene folder, scene.zip.bvh next to a zip
+        if !matches.get_flag("no bvh cache") {
+            let scene_path = scene_path.trim_end_matches(['/', '\\']);
+            renderer_builder.bvh_cache(PathBuf::from(format!("{}.bvh", scene_path)));
+        }
     }
+    renderer_builder
+}
 
-    renderer_builder.build()
+fn build_renderer(matches: &ArgMatches) -> Renderer {
+    configure_scene(matches).build()
 }
 
 fn output_image(
@@ -306,6 +635,7 @@ fn output_image(
     width: u32,
     height: u32,
     pixels: &[Color],
+    sample_counts: &[u32],
 ) {
     if matches.get_flag("save binary") {
         let now = chrono::offset::Local::now();
@@ -331,6 +661,31 @@ fn output_image(
         );
         fs::write(&filename, data).unwrap();
         eprintln!("saved file {}", filename);
+
+        if matches.get_flag("sample map") && sample_counts.len() == pixels.len() {
+            let max = sample_counts.iter().copied().max().unwrap_or(1).max(1) as f32;
+            let map: Vec<Color> = sample_counts
+                .iter()
+                .map(|count| {
+                    let value = *count as f32 / max;
+                    Color(value, value, value)
+                })
+                .collect();
+            let data = if extension == "png" {
+                format_as_png(&map, width, height)
+            } else {
+                format_as_ppm(&map, width, height).into()
+            };
+            let filename = format!(
+                "renders/{}-{}-{}-samples.{}",
+                date,
+                samples,
+                elapsed.floor(),
+                extension
+            );
+            fs::write(&filename, data).unwrap();
+            eprintln!("saved file {}", filename);
+        }
     } else if matches.get_flag("ppm") {
         let ppm = format_as_ppm(pixels, width, height);
         println!("{}", ppm);
@@ -340,17 +695,134 @@ fn output_image(
     }
 }
 
+// Renders the image across the workers and saves it like a local render
+fn render_distributed(matches: &ArgMatches, workers: &[String]) {
+    let renderer_builder = configure_renderer(matches);
+    let (width, height) = (renderer_builder.width, renderer_builder.height);
+    let section_size = matches
+        .get_one::<u32>("section size")
+        .map_or(DEFAULT_SECTION_SIZE, |v| *v);
+    let timeout = matches
+        .get_one::<Duration>("worker timeout")
+        .map_or(DEFAULT_WORKER_TIMEOUT, |v| *v);
+    let scene = matches.get_one::<String>("scene").map(|path| {
+        if fs::metadata(path).unwrap().is_dir() {
+            kosmos::zip_folder(path).unwrap()
+        } else {
+            fs::read(path).unwrap()
+        }
+    });
+    let show_progress = io::stderr().is_terminal() && !matches.get_flag("no progress");
+    let progress = progress_bar();
+
+    let start = time::Instant::now();
+    let pixels = distributed::render(
+        workers,
+        std::env::args().skip(1).collect(),
+        scene,
+        Section::new(0, 0, width, height),
+        section_size,
+        timeout,
+        |status| {
+            if show_progress {
+                progress(status)
+            }
+        },
+    )
+    .unwrap_or_else(|error| {
+        eprintln!("distributed render failed: {}", error);
+        std::process::exit(1);
+    });
+    if show_progress {
+        eprintln!();
+    }
+    let elapsed = start.elapsed().as_seconds_f32() * 1000.0;
+    eprintln!("Rendering time: {}ms", elapsed);
+
+    output_image(
+        matches,
+        renderer_builder.samples,
+        elapsed,
+        width,
+        height,
+        &pixels,
+        &[],
+    );
+}
+
+// Draws the progress bar on stderr, redrawing only when the percentage changes
+fn progress_bar() -> impl Fn(&Progress) + Send + Sync + 'static {
+    let last_percent = AtomicU32::new(u32::MAX);
+    move |progress| {
+        let percent = (progress.fraction() * 100.0) as u32;
+        if last_percent.swap(percent, Ordering::Relaxed) == percent {
+            return;
+        }
+        let filled = (progress.fraction() * PROGRESS_BAR_WIDTH as f32) as usize;
+        let eta = progress
+            .eta()
+            .map(|eta| format!("{:.1}s", eta.as_secs_f32()))
+            .unwrap_or_else(|| "-".to_string());
+        eprint!(
+            "\r[{}{}] {:3}% {}/{} elapsed {:.1}s eta {}  ",
+            "#".repeat(filled),
+            ".".repeat(PROGRESS_BAR_WIDTH - filled),
+            percent,
+            progress.completed,
+            progress.total,
+            progress.elapsed.as_secs_f32(),
+            eta
+        );
+    }
+}
+
 fn main() {
     let matches = process_cli();
+    if let Some(address) = matches.get_one::<String>("worker") {
+        if let Err(error) = distributed::work(address) {
+            eprintln!("worker stopped: {}", error);
+        }
+        return;
+    }
+    if matches.contains_id("checkpoint") || matches.contains_id("resume") {
+        checkpoint::render(&matches);
+        return;
+    }
+    if let Some(workers) = matches.get_many::<String>("workers") {
+        let workers: Vec<String> = workers.cloned().collect();
+        render_distributed(&matches, &workers);
+        return;
+    }
+
     let mut renderer = build_renderer(&matches);
     let width: u32 = renderer.width;
     let height: u32 = renderer.height;
 
     let section = Section::new(0, 0, width, height);
     let start = time::Instant::now();
-    let pixels = renderer.render(&section);
+    let mut samples = renderer.samples;
+    let pixels = if let Some(budget) = matches.get_one::<Duration>("time") {
+        let (pixels, taken) = renderer.render_for(&section, *budget);
+        samples = taken;
+        eprintln!("Samples: {}", samples);
+        pixels
+    } else if io::stderr().is_terminal() && !matches.get_flag("no progress") {
+        let pixels = renderer.render_with(&section, &CancellationToken::new(), progress_bar());
+        eprintln!();
+        pixels
+    } else {
+        renderer.render(&section)
+    };
     let elapsed = start.elapsed().as_seconds_f32() * 1000.0;
     eprintln!("Rendering time: {}ms", elapsed);
 
-    output_image(&matches, renderer.samples, elapsed, width, height, &pixels);
+    output_image(
+        &matches,
+        samples,
+        elapsed,
+        width,
+        height,
+        &pixels,
+        renderer.sample_counts(),
+    );
 }
@@ -5,8 +5,8 @@
 // }
 
 use light::{
-    demos, parsers, Accelerator, Algorithm, Camera, Color, Point, RenderMethod, Renderer, Section,
-    Transform,
+    demos, parsers, Accelerator, Algorithm, BvhBuildMethod, Camera, CancellationToken, Point, Progressive,
+    RenderMethod, Renderer, Transform,
 };
 use tauri::ipc::Response;
 use std::sync::Mutex;
@@ -14,16 +14,17 @@ use std::sync::Mutex;
 const width: u32 = 640;
 const height: u32 = 360;
 const bpp: u32 = 4;
-static renderer_global: Mutex<Option<Renderer>> = Mutex::new(None);
-static frames_count: Mutex<f32> = Mutex::new(0.0);
-static frames_acc_global: Mutex<Vec<Color>> = Mutex::new(vec![]);
+static session_global: Mutex<Option<Progressive>> = Mutex::new(None);
+// token of the pass being rendered, cancelled when the scene is replaced
+static cancel_global: Mutex<Option<CancellationToken>> = Mutex::new(None);
 
 #[tauri::command()]
 fn initialize_renderer(json: String) {
-    let mut frames_acc = frames_acc_global.lock().unwrap();
-    let mut renderer = renderer_global.lock().unwrap();
+    if let Some(cancel) = cancel_global.lock().unwrap().take() {
+        cancel.cancel();
+    }
+    let mut session = session_global.lock().unwrap();
     let (camera, world) = parsers::json(&json);
-    let section = Section::new(0, 0, width, height);
     let mut renderer_builder = Renderer::builder();
     renderer_builder
         .width(width)
@@ -31,42 +32,33 @@ fn initialize_renderer(json: String) {
         .algorithm(Algorithm::PathTracing)
         .render_method(RenderMethod::Tiles)
         .accelerator(Accelerator::BoundingVolumeHierarchy)
+        // scenes are rebuilt while editing
+        .bvh_build_method(BvhBuildMethod::Lbvh)
         .camera(camera)
         .world(world);
 
-    renderer.replace(renderer_builder.build());
-    *frames_acc = vec![Color(0.0, 0.0, 0.0); (width * height) as usize];
-    let mut count = frames_count.lock().unwrap();
-    *count = 0.0;
+    session.replace(Progressive::new(renderer_builder.build()));
 }
 
 #[tauri::command(async)]
 fn generate_image() -> Response {
-    let mut frames_acc = frames_acc_global.lock().unwrap();
-    let mut renderer = renderer_global.lock().unwrap();
+    let mut session = session_global.lock().unwrap();
 
     let mut img = vec![0; (width * height * bpp) as usize];
-    if renderer.is_none() {
+    let Some(session) = session.as_mut() else {
         return tauri::ipc::Response::new(img);
-    }
-
-    let section = Section::new(0, 0, width, height);
-    let pixels = renderer.as_mut().unwrap().render(&section);
-    let mut count = frames_count.lock().unwrap();
-    *count = *count + 1.0;
+    };
 
-    for (idx, pixel) in pixels.into_iter().enumerate() {
-        let px = frames_acc[idx] + pixel;
-        frames_acc[idx] = px;
-        let px = px / *count;
-        let x = section.left + (idx as u32 % section.width);
-        let y = section.top + (idx as u32 / section.width);
-        let offset = (y * (width) + x) * 4;
-        let (red, green, blue) = (px).as_gamma_corrected_rgb_u8();
-        img[offset as usize] = blue;
-        img[(offset + 1) as usize] = green;
-        img[(offset + 2) as usize] = red;
-        img[(offset + 3) as usize] = 0xff;
+    let cancel = CancellationToken::new();
+    cancel_global.lock().unwrap().replace(cancel.clone());
+    session.render_pass_with(&cancel, |_| {});
+    for (idx, px) in session.image().into_iter().enumerate() {
+        let offset = idx * 4;
+        let (red, green, blue) = px.as_gamma_corrected_rgb_u8();
+        img[offset] = blue;
+        img[offset + 1] = green;
+        img[offset + 2] = red;
+        img[offset + 3] = 0xff;
     }
 
     tauri::ipc::Response::new(img)
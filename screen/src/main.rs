@@ -1,9 +1,7 @@
-use ilios::{Accelerator, Algorithm, BvhBuildMethod, RenderMethod, Renderer};
+use ilios::{Accelerator, Algorithm, BvhBuildMethod, Progressive, RenderMethod, Renderer};
 use ilios_types::camera::Camera;
-use ilios_types::color::Color;
 use ilios_types::float::PI;
 use ilios_types::geometry::{Point, Vector};
-use ilios_types::section::Section;
 use ilios_types::transform::Transform;
 use kosmos::SceneDescriptor;
 use sdl2::event::Event;
@@ -83,10 +81,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         .bvh_build_method(BvhBuildMethod::Sah)
         .accelerator(Accelerator::BoundingVolumeHierarchy);
 
-    let mut frames: Vec<Color> = vec![Color(0., 0., 0.); (4 * width * height) as usize];
     let mut buffer: Vec<u8> = vec![0; (4 * width * height) as usize];
-    let section = Section::new(0, 0, width, height);
-    let mut frames_count: f32 = 0.0;
 
     let mut frame_timmings = FrameTimmings::new();
     let mut reset: bool = false;
@@ -97,7 +92,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     renderer_builder.camera(camera);
     renderer_builder.world(world);
 
-    let mut renderer = renderer_builder.build();
+    let mut session = Progressive::new(renderer_builder.build());
 
     let mut camera_transforms: Vec<Transform> = vec![];
 
@@ -120,22 +115,22 @@ fn main() -> Result<(), Box<dyn Error>> {
                     match if keycode == Keycode::Left {
                         Some(Transform::rotate_around_vector(
                             PI / 10.0,
-                            renderer.camera.coordinate_system.v,
+                            session.renderer().camera.coordinate_system.v,
                         ))
                     } else if keycode == Keycode::Right {
                         Some(Transform::rotate_around_vector(
                             -PI / 10.0,
-                            renderer.camera.coordinate_system.v,
+                            session.renderer().camera.coordinate_system.v,
                         ))
                     } else if keycode == Keycode::Up {
                         Some(Transform::rotate_around_vector(
                             PI / 10.0,
-                            renderer.camera.coordinate_system.u,
+                            session.renderer().camera.coordinate_system.u,
                         ))
                     } else if keycode == Keycode::Down {
                         Some(Transform::rotate_around_vector(
                             -PI / 10.0,
-                            renderer.camera.coordinate_system.u,
+                            session.renderer().camera.coordinate_system.u,
                         ))
                     } else if keycode == Keycode::W {
                         Some(Transform::translate(0.0, 0.0, 5.0))
@@ -150,11 +145,11 @@ fn main() -> Result<(), Box<dyn Error>> {
                     } else if keycode == Keycode::E {
                         Some(Transform::translate(0.0, -5.0, 0.0))
                     } else if keycode == Keycode::I {
-                        let algo: Vector = renderer.camera.eye.into();
+                        let algo: Vector = session.renderer().camera.eye.into();
                         let algo = algo.unit() * -1.0;
                         Some(Transform::translate(algo.0, algo.1, algo.2))
                     } else if keycode == Keycode::O {
-                        let algo: Vector = renderer.camera.eye.into();
+                        let algo: Vector = session.renderer().camera.eye.into();
                         let algo = algo.unit();
                         Some(Transform::translate(algo.0, algo.1, algo.2))
                     } else {
@@ -184,33 +179,26 @@ fn main() -> Result<(), Box<dyn Error>> {
             // renderer = renderer_builder.build();
             // renderer.camera = camera.clone();
 
-            renderer
-                .camera
+            session
+                .camera_mut()
                 .apply_transform(&Transform::combine(&camera_transforms));
             camera_transforms.clear();
             reset = true;
         }
 
         if reset {
-            frames = vec![Color(0., 0., 0.); (4 * width * height) as usize];
             buffer = vec![0; (4 * width * height) as usize];
-            frames_count = 0.0;
             frame_timmings = FrameTimmings::new();
             reset = false;
         }
 
-        let pixels = renderer.render(&section);
-        frames_count += 1.0;
-        for (idx, pixel) in pixels.into_iter().enumerate() {
-            let point = frames[idx] + pixel;
-            let x = section.left + (idx as u32 % section.width);
-            let y = section.top + (idx as u32 / section.width);
-            let offset = (y * (width) + x) * 4;
-            let (red, green, blue) = (point / frames_count.into()).as_gamma_corrected_rgb_u8();
-            buffer[offset as usize] = blue;
-            buffer[(offset + 1) as usize] = green;
-            buffer[(offset + 2) as usize] = red;
-            frames[idx] = point;
+        session.render_pass();
+        for (idx, pixel) in session.image().into_iter().enumerate() {
+            let offset = idx * 4;
+            let (red, green, blue) = pixel.as_gamma_corrected_rgb_u8();
+            buffer[offset] = blue;
+            buffer[offset + 1] = green;
+            buffer[offset + 2] = red;
         }
 
         texture.update(rect, &buffer, step)?;